@@ -0,0 +1,102 @@
+use crate::env::Env;
+use crate::object::Object;
+use std::cell::{Cell, RefCell};
+
+/// Entry of the handler stack, `guard` leaves a marker so the handlers
+/// outside of it are not run for the errors it is going to catch
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Handlers(Vec<(String, Object)>),
+    Guard,
+}
+
+/// Restart established by `restart-case` or by the evaluator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Restart {
+    pub id: usize,
+    pub name: String,
+    pub params: Vec<String>,
+}
+
+/// Called with the condition and the active restarts when an error has
+/// no handler, it returns the chosen restart and its arguments or
+/// `None` to let the error unwind
+pub type Debugger = fn(&Object, &[Restart], &mut Env) -> Option<(Restart, Vec<Object>)>;
+
+thread_local! {
+    static HANDLERS: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    static RESTARTS: RefCell<Vec<Restart>> = const { RefCell::new(Vec::new()) };
+    static RESTART_COUNTER: Cell<usize> = const { Cell::new(0) };
+    static DEBUGGER: Cell<Option<Debugger>> = const { Cell::new(None) };
+}
+
+pub fn push_frame(frame: Frame) {
+    HANDLERS.with(|h| h.borrow_mut().push(frame));
+}
+
+pub fn pop_frame() {
+    HANDLERS.with(|h| h.borrow_mut().pop());
+}
+
+/// Number of frames in the handler stack
+pub fn depth() -> usize {
+    HANDLERS.with(|h| h.borrow().len())
+}
+
+pub fn frame(index: usize) -> Frame {
+    HANDLERS.with(|h| h.borrow()[index].clone())
+}
+
+/// Remove the frames from `index` on, a handler runs with
+/// only the handlers that were active outside of it
+pub fn hide_frames(index: usize) -> Vec<Frame> {
+    HANDLERS.with(|h| h.borrow_mut().split_off(index))
+}
+
+pub fn restore_frames(frames: Vec<Frame>) {
+    HANDLERS.with(|h| h.borrow_mut().extend(frames));
+}
+
+/// Establish a restart and return its id
+pub fn push_restart(name: &str, params: Vec<String>) -> usize {
+    let id = RESTART_COUNTER.with(|counter| {
+        let n = counter.get() + 1;
+        counter.set(n);
+        n
+    });
+
+    RESTARTS.with(|r| {
+        r.borrow_mut().push(Restart {
+            id,
+            name: name.to_string(),
+            params,
+        })
+    });
+
+    id
+}
+
+pub fn pop_restarts(count: usize) {
+    RESTARTS.with(|r| {
+        let mut restarts = r.borrow_mut();
+        let len = restarts.len();
+        restarts.truncate(len.saturating_sub(count));
+    });
+}
+
+/// Active restarts, innermost first
+pub fn restarts() -> Vec<Restart> {
+    RESTARTS.with(|r| r.borrow().iter().rev().cloned().collect())
+}
+
+pub fn find_restart(name: &str) -> Option<Restart> {
+    restarts().into_iter().find(|r| r.name == name)
+}
+
+pub fn set_debugger(debugger: Option<Debugger>) {
+    DEBUGGER.with(|d| d.set(debugger));
+}
+
+pub fn debugger() -> Option<Debugger> {
+    DEBUGGER.with(|d| d.get())
+}
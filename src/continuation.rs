@@ -0,0 +1,192 @@
+use crate::env::Env;
+use crate::object::Object;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+/// What the evaluator does with the value of the form it is evaluating,
+/// the frames of a running evaluation are its continuation
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// Items of a sequence still to evaluate and the values so far
+    Sequence {
+        rest: IntoIter<Object>,
+        values: Vec<Object>,
+    },
+    /// Forms of a `begin` still to evaluate
+    Begin { rest: IntoIter<Object> },
+    /// Branches of an `if` waiting for the condition
+    If { then: Object, otherwise: Object },
+    /// Clauses of a `match` waiting for the value
+    Match { clauses: Vec<Object> },
+    /// `define` waiting for the value
+    Define { name: String },
+    /// Arguments of a call still to evaluate and the values so far
+    Args {
+        callee: Callee,
+        rest: IntoIter<Object>,
+        values: Vec<Object>,
+    },
+    /// Body of a procedure, it owns the innermost scope
+    Body { name: String },
+    /// Thunk of `dynamic-wind`, `after` runs when it returns or is left
+    Wind { id: usize, before: Object, after: Object },
+    /// Producer of `call-with-values` waiting for its values
+    Consume { consumer: Object },
+    /// Delimiter of the continuations captured by `shift`, or by `yield`
+    /// for the prompt of a generator
+    Prompt { generator: Option<Rc<Generator>> },
+    /// Body of a `guard`, the clauses handle its errors. Each of these
+    /// frames has a `Guard` entry in the handler stack
+    Guard { var: String, clauses: Vec<Object> },
+}
+
+/// What a call applies once its arguments are evaluated
+#[derive(Debug, Clone)]
+pub enum Callee {
+    /// Value of the head, a lambda, record procedure, generic
+    /// function or continuation
+    Procedure { name: String, func: Object },
+    /// Operator of the interpreter, like `+`, `concat` or `call/cc`
+    Builtin(String),
+    /// Body of a `let-values`, a `let` with patterns or a
+    /// `destructuring-bind`, the formals bind the values of each form
+    Values {
+        form: String,
+        formals: Vec<Object>,
+        body: Object,
+    },
+    /// Template of a `quasiquote`, the values fill its unquotes
+    Quasiquote { template: Object },
+}
+
+/// Continuation captured by `call/cc` or `shift`
+///
+/// It holds the frames and the scopes of the evaluation that captured
+/// it. That evaluation is a top-level form or a form evaluated by the
+/// interpreter itself, like the body of a `handler-bind`, and the
+/// continuation of `call/cc` extends up to it. A delimited one extends
+/// up to its `reset` and calling it adds its frames to the ones of the
+/// caller.
+/// One of a form evaluated by the interpreter can not be resumed once
+/// that form returned, its frames would go on without the rest of the
+/// program
+#[derive(Debug)]
+pub struct Continuation {
+    pub evaluation: usize,
+    pub frames: Vec<Frame>,
+    pub scopes: Vec<Env>,
+    pub delimited: bool,
+    /// Captured in an evaluation that runs inside another one
+    pub nested: bool,
+}
+
+/// Continuations are only equal to themselves
+impl PartialEq for Continuation {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<continuation>")
+    }
+}
+
+/// Generator made by `make-generator`, each call runs its producer
+/// until the next `yield`. The first call gives it the arguments
+#[derive(Debug)]
+pub struct Generator {
+    pub producer: Object,
+    pub args: Vec<Object>,
+    pub state: RefCell<GeneratorState>,
+}
+
+#[derive(Debug, Clone)]
+pub enum GeneratorState {
+    Fresh,
+    /// Stopped at a `yield`, the continuation goes on from it
+    Suspended(Rc<Continuation>),
+    Running,
+    Done,
+}
+
+impl Generator {
+    pub fn new(producer: Object, args: Vec<Object>) -> Self {
+        Generator {
+            producer,
+            args,
+            state: RefCell::new(GeneratorState::Fresh),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(*self.state.borrow(), GeneratorState::Done)
+    }
+}
+
+/// Generators are only equal to themselves
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<generator>")
+    }
+}
+
+thread_local! {
+    static RUNNING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    static COUNTER: Cell<usize> = const { Cell::new(0) };
+}
+
+/// New id for an evaluation or a `dynamic-wind`
+pub fn next_id() -> usize {
+    COUNTER.with(|counter| {
+        let n = counter.get() + 1;
+        counter.set(n);
+        n
+    })
+}
+
+/// Mark an evaluation as running, it can be jumped to until it finishes
+pub fn start(id: usize) {
+    RUNNING.with(|r| r.borrow_mut().push(id));
+}
+
+pub fn finish() {
+    RUNNING.with(|r| r.borrow_mut().pop());
+}
+
+pub fn is_running(id: usize) -> bool {
+    RUNNING.with(|r| r.borrow().contains(&id))
+}
+
+/// Whether an evaluation starting now runs inside another one
+pub fn is_nested() -> bool {
+    RUNNING.with(|r| !r.borrow().is_empty())
+}
+
+/// Ids of the `dynamic-wind` thunks the frames are in, outermost first
+pub fn winds(frames: &[Frame]) -> Vec<usize> {
+    frames
+        .iter()
+        .filter_map(|frame| match frame {
+            Frame::Wind { id, .. } => Some(*id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Number of `guard` bodies the frames are in
+pub fn guards(frames: &[Frame]) -> usize {
+    frames
+        .iter()
+        .filter(|frame| matches!(frame, Frame::Guard { .. }))
+        .count()
+}
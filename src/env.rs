@@ -1,4 +1,8 @@
+use crate::limits::{Budget, Limits};
+use crate::module::Registry;
 use crate::object::Object;
+use crate::sandbox::Sandbox;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
@@ -6,12 +10,72 @@ use std::rc::Rc;
 pub struct Env {
     parent: Option<Rc<Env>>,
     vars: HashMap<String, Object>,
+    macros: HashMap<String, Object>,
+    budget: Rc<Budget>,
+    sandbox: Rc<Sandbox>,
+    modules: Rc<RefCell<Registry>>,
 }
 
-pub const KEYWORDS: [&str; 9] = [
-    "if", "define", "lambda", "print", "debug", "equal", "load", "true", "false",
+pub const KEYWORDS: [&str; 48] = [
+    "if",
+    "define",
+    "lambda",
+    "print",
+    "debug",
+    "equal",
+    "load",
+    "true",
+    "false",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "defmacro",
+    "macroexpand",
+    "macroexpand-1",
+    "define-syntax",
+    "let-syntax",
+    "syntax-rules",
+    "begin",
+    "let",
+    "let*",
+    "letrec",
+    "destructuring-bind",
+    "let-values",
+    "define-values",
+    "receive",
+    "cond",
+    "when",
+    "unless",
+    "and",
+    "or",
+    "guard",
+    "handler-bind",
+    "restart-case",
+    "module",
+    "import",
+    "require",
+    "define-record-type",
+    "defclass",
+    "defgeneric",
+    "defmethod",
+    "match",
+    "reset",
+    "shift",
+    "delay",
+    "delay-force",
+    "stream-cons",
 ];
 
+/// When bound to true, float operations that produce NaN or infinity fail
+pub const STRICT_FLOAT: &str = "*strict-float*";
+
+/// List of the directories where `load`, `require` and `import` look for files
+pub const LOAD_PATH: &str = "*load-path*";
+
+/// When bound to true, `load` prints the definitions of the files it loads
+pub const LOAD_VERBOSE: &str = "*load-verbose*";
+
 /// TODO: Document this thing
 impl Env{
     pub fn new() -> Self {
@@ -29,4 +93,83 @@ impl Env{
     pub fn set(&mut self, name: &str, val: Object) {
         self.vars.insert(name.to_string(), val);
     }
+
+    /// Look up a macro transformer, macros live apart from variables
+    /// so they are found before a function call is attempted
+    pub fn get_macro(&self, name: &str) -> Option<Object> {
+        match self.macros.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref().and_then(|o| o.get_macro(name)),
+        }
+    }
+
+    pub fn set_macro(&mut self, name: &str, transformer: Object) {
+        self.macros.insert(name.to_string(), transformer);
+    }
+
+    /// Bound the evaluations in this environment and its copies
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Rc::new(Budget::new(limits));
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.budget.limits
+    }
+
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /// Restrict what the code evaluated in this environment and its copies can do
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.sandbox = Rc::new(sandbox);
+    }
+
+    pub fn sandbox(&self) -> &Sandbox {
+        &self.sandbox
+    }
+
+    /// Modules evaluated so far, shared with the module scopes
+    pub fn modules(&self) -> &RefCell<Registry> {
+        &self.modules
+    }
+
+    /// Empty environment for the body of a module, it keeps the
+    /// limits, the sandbox, the modules and the `*strict-float*`,
+    /// `*load-path*` and `*load-verbose*` settings
+    pub fn module_scope(&self) -> Env {
+        let mut scope = Env {
+            budget: self.budget.clone(),
+            sandbox: self.sandbox.clone(),
+            modules: self.modules.clone(),
+            ..Default::default()
+        };
+
+        for name in [STRICT_FLOAT, LOAD_PATH, LOAD_VERBOSE] {
+            if let Some(value) = self.get(name) {
+                scope.set(name, value);
+            }
+        }
+
+        scope
+    }
+
+    /// Bytes allocated by the evaluation running in this environment,
+    /// or by the last one
+    pub fn bytes_allocated(&self) -> usize {
+        self.budget.allocated()
+    }
+
+    /// Approximate bytes a copy of this environment takes
+    pub fn size(&self) -> usize {
+        self.vars
+            .iter()
+            .chain(self.macros.iter())
+            .map(|(name, value)| name.len() + value.size())
+            .sum()
+    }
+
+    pub fn strict_float(&self) -> bool {
+        self.get(STRICT_FLOAT) == Some(Object::Bool(true))
+    }
 }
@@ -0,0 +1,263 @@
+use crate::continuation::Continuation;
+use crate::object::{Condition, Object};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Most frames shown when an error is printed
+const BACKTRACE_LIMIT: usize = 10;
+
+/// Position in the source code, lines and columns start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// What went wrong while reading or evaluating a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// A symbol without a value
+    UnboundSymbol(String),
+    /// A value of the wrong type, `found` is the value received
+    TypeMismatch { expected: String, found: String },
+    /// Wrong number of arguments for a procedure or special form
+    Arity(String),
+    DivideByZero,
+    /// An integer result that does not fit in 64 bits
+    Overflow(String),
+    /// Arguments outside of the domain of an operation, like a negative
+    /// integer exponent or a NaN in strict-float mode
+    Domain(String),
+    /// A file that can not be read
+    Io(String),
+    /// Source code that can not be tokenized or parsed
+    Parse(String),
+    /// A malformed special form or macro use
+    Syntax(String),
+    /// A value raised by the program with `raise` or `error`
+    User(Box<Object>),
+    /// An operation the sandbox does not allow
+    Permission(String),
+    /// A step, depth or time limit was reached
+    ResourceLimit(String),
+    /// Transfer of control that can not happen: a restart invoked
+    /// without an active restart of that name, a continuation of a
+    /// form that already returned, `shift` outside of a `reset`,
+    /// `yield` outside of a generator or a call of a running generator
+    Control(String),
+    /// Generic function without a method for its arguments
+    NoMethod(String),
+    /// Value, as it is printed, that no clause of `match` accepts
+    NoMatch(String),
+    /// Unwinding to the `restart-case` that established restart `id`
+    Restart { id: usize, name: String, args: Vec<Object> },
+    /// Unwinding to the evaluation a continuation was captured in
+    Continuation { target: Rc<Continuation>, value: Box<Object> },
+}
+
+impl ErrorKind {
+    /// Name of the kind as seen from Lisp with `condition-kind`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::UnboundSymbol(_) => "unbound-symbol",
+            ErrorKind::TypeMismatch { .. } => "type-mismatch",
+            ErrorKind::Arity(_) => "arity",
+            ErrorKind::DivideByZero => "divide-by-zero",
+            ErrorKind::Overflow(_) => "overflow",
+            ErrorKind::Domain(_) => "domain-error",
+            ErrorKind::Io(_) => "io",
+            ErrorKind::Parse(_) => "parse",
+            ErrorKind::Syntax(_) => "syntax",
+            ErrorKind::User(_) => "user",
+            ErrorKind::Permission(_) => "permission-denied",
+            ErrorKind::ResourceLimit(_) => "resource-limit",
+            ErrorKind::Control(_) => "control-error",
+            ErrorKind::NoMethod(_) => "no-applicable-method",
+            ErrorKind::NoMatch(_) => "no-matching-clause",
+            ErrorKind::Restart { .. } => "restart",
+            ErrorKind::Continuation { .. } => "continuation",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnboundSymbol(s) => write!(f, "Unbound symbol: {}", s),
+            ErrorKind::TypeMismatch { expected, found } => {
+                write!(f, "Expected {}, found {}", expected, found)
+            }
+            ErrorKind::Arity(name) => write!(f, "Invalid number of arguments for {}", name),
+            ErrorKind::DivideByZero => write!(f, "Division by zero"),
+            ErrorKind::Overflow(op) => write!(f, "Integer overflow in {}", op),
+            ErrorKind::Domain(msg) => write!(f, "{}", msg),
+            ErrorKind::Io(msg) => write!(f, "{}", msg),
+            ErrorKind::Parse(msg) => write!(f, "Parse error: {}", msg),
+            ErrorKind::Syntax(msg) => write!(f, "{}", msg),
+            ErrorKind::User(obj) => match obj.as_ref() {
+                Object::Condition(c) => {
+                    write!(f, "{}", c.message)?;
+                    for irritant in &c.irritants {
+                        write!(f, " {}", irritant.repr())?;
+                    }
+                    Ok(())
+                }
+                obj => write!(f, "Uncaught raise: {}", obj.repr()),
+            },
+            ErrorKind::Permission(msg) => write!(f, "Permission denied: {}", msg),
+            ErrorKind::ResourceLimit(msg) => write!(f, "Resource limit exceeded: {}", msg),
+            ErrorKind::Control(msg) => write!(f, "{}", msg),
+            ErrorKind::NoMethod(msg) => write!(f, "{}", msg),
+            ErrorKind::NoMatch(value) => write!(f, "No matching clause for {}", value),
+            ErrorKind::Restart { name, .. } => write!(f, "Restart {} invoked outside of its restart-case", name),
+            ErrorKind::Continuation { .. } => write!(f, "Continuation invoked outside of its evaluation"),
+        }
+    }
+}
+
+/// Error of the interpreter
+///
+/// Besides the kind it keeps where the failing form starts, the file
+/// it was loaded from, and the lambdas that were running when it was
+/// raised, innermost first.
+/// `signaled` is set once the handlers of `handler-bind` have seen it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+    /// Boxed, errors are returned everywhere and should stay small
+    pub file: Option<Box<PathBuf>>,
+    pub backtrace: Vec<String>,
+    pub signaled: bool,
+}
+
+impl EvalError {
+    pub fn new(kind: ErrorKind) -> Self {
+        EvalError {
+            kind,
+            span: None,
+            file: None,
+            backtrace: Vec::new(),
+            signaled: false,
+        }
+    }
+
+    pub fn unbound(name: &str) -> Self {
+        EvalError::new(ErrorKind::UnboundSymbol(name.to_string()))
+    }
+
+    pub fn type_mismatch(expected: &str, found: &Object) -> Self {
+        EvalError::new(ErrorKind::TypeMismatch {
+            expected: expected.to_string(),
+            found: found.repr(),
+        })
+    }
+
+    pub fn arity(name: &str) -> Self {
+        EvalError::new(ErrorKind::Arity(name.to_string()))
+    }
+
+    pub fn syntax(msg: impl Into<String>) -> Self {
+        EvalError::new(ErrorKind::Syntax(msg.into()))
+    }
+
+    pub fn io(msg: impl Into<String>) -> Self {
+        EvalError::new(ErrorKind::Io(msg.into()))
+    }
+
+    /// Transfer control to an active restart, handlers never see it
+    pub fn restart(id: usize, name: &str, args: Vec<Object>) -> Self {
+        let mut error = EvalError::new(ErrorKind::Restart {
+            id,
+            name: name.to_string(),
+            args,
+        });
+        error.signaled = true;
+        error
+    }
+
+    /// Resume a continuation of an evaluation further out, handlers never see it
+    pub fn jump(target: Rc<Continuation>, value: Object) -> Self {
+        let mut error = EvalError::new(ErrorKind::Continuation {
+            target,
+            value: Box::new(value),
+        });
+        error.signaled = true;
+        error
+    }
+
+    /// Object seen by a `guard` clause, raised values are kept as they
+    /// are and the errors of the interpreter become conditions
+    pub fn to_condition(&self) -> Object {
+        match &self.kind {
+            ErrorKind::User(obj) => obj.as_ref().clone(),
+            kind => Object::Condition(Box::new(Condition {
+                kind: kind.name().to_string(),
+                message: kind.to_string(),
+                irritants: Vec::new(),
+            })),
+        }
+    }
+
+    /// Keep the innermost span, the first one attached wins
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    /// Keep the innermost file, the one the span refers to
+    pub fn with_file(mut self, file: &Path) -> Self {
+        self.file.get_or_insert_with(|| Box::new(file.to_path_buf()));
+        self
+    }
+
+    /// Record a lambda the error is unwinding through
+    pub fn with_frame(mut self, name: &str) -> Self {
+        self.backtrace.push(name.to_string());
+        self
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.span) {
+            (Some(file), Some(span)) => write!(f, "{}:{}: ", file.display(), span)?,
+            (Some(file), None) => write!(f, "{}: ", file.display())?,
+            (None, Some(span)) => write!(f, "{}: ", span)?,
+            (None, None) => {}
+        }
+        write!(f, "{}", self.kind)?;
+
+        // Recursion repeats the same frame, show it once with a count
+        let mut frames: Vec<(&str, usize)> = Vec::new();
+        for frame in &self.backtrace {
+            match frames.last_mut() {
+                Some((name, count)) if name == frame => *count += 1,
+                _ => frames.push((frame, 1)),
+            }
+        }
+
+        for (name, count) in frames.iter().take(BACKTRACE_LIMIT) {
+            match count {
+                1 => write!(f, "\n  in {}", name)?,
+                _ => write!(f, "\n  in {} (x{})", name, count)?,
+            }
+        }
+
+        if frames.len() > BACKTRACE_LIMIT {
+            write!(f, "\n  ... {} more", frames.len() - BACKTRACE_LIMIT)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for EvalError {}
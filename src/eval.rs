@@ -1,6 +1,27 @@
+use crate::condition;
+use crate::continuation::{self, Callee, Continuation, Frame, Generator, GeneratorState};
 use crate::env::*;
+use crate::error::*;
+use crate::expand::*;
+use crate::format;
+use crate::generic::{Generic, Method, NextMethod, Qualifier, Specializer};
+use crate::load;
+use crate::module::{self, Module};
 use crate::object::*;
 use crate::parser::*;
+use crate::pattern;
+use crate::port::Port;
+use crate::promise::{self, Promise, PromiseState};
+use crate::regexp::{self, Regexp};
+use crate::strings;
+use crate::syntax;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::vec::IntoIter;
 
 #[derive(Debug)]
 enum Number {
@@ -8,9 +29,14 @@ enum Number {
     Integer(i64),
 }
 
-fn eval_string_op(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
+fn eval_string_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    match &list[0] {
+        Object::Symbol(s) if s != "concat" => return eval_string_library_op(s, list, env),
+        _ => {}
+    }
+
     if list.len() != 3 {
-        return Err("Invalid number of arguments for infix operator".to_string());
+        return Err(EvalError::arity("infix operator"));
     } 
 
     let operator = list[0].clone();
@@ -21,7 +47,7 @@ fn eval_string_op(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
         Object::Integer(i) => i.to_string(),
         Object::Float(f) => f.to_string(),
         Object::Bool(b) => b.to_string(),
-        _ => return Err(format!("Left operand must be an string {:?}", left)),
+        _ => return Err(EvalError::type_mismatch("a string", &left)),
     };
 
     let right_val = match right {
@@ -29,46 +55,231 @@ fn eval_string_op(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
         Object::Integer(i) => i.to_string(),
         Object::Bool(b) => b.to_string(),
         Object::Float(f) => f.to_string(),
-        _ => return Err(format!("Right operand must be an string {:?}", right)),
+        _ => return Err(EvalError::type_mismatch("a string", &right)),
     };
 
     match operator {
         Object::Symbol(s) => match s.as_str() {
-            "concat" => Ok(Object::Str(left_val + &right_val)),
-            _ => Err(format!("Invalid infix operator: {}", s)),
+            "concat" => {
+                env.budget().allocate(left_val.len() + right_val.len())?;
+                Ok(Object::Str(left_val + &right_val))
+            }
+            _ => Err(EvalError::syntax(format!("Invalid infix operator: {}", s))),
         },
-        _ => Err("Operator must be a symbol".to_string()),
+        _ => Err(EvalError::type_mismatch("a symbol", &operator)),
+    }
+}
+
+fn string_arg(obj: &Object) -> Result<&str, EvalError> {
+    match obj {
+        Object::Str(s) => Ok(s),
+        other => Err(EvalError::type_mismatch("a string", other)),
+    }
+}
+
+fn index_arg(obj: &Object) -> Result<usize, EvalError> {
+    match obj {
+        Object::Integer(n) if *n >= 0 => Ok(*n as usize),
+        other => Err(EvalError::type_mismatch("a non-negative integer", other)),
+    }
+}
+
+fn radix_arg(radix: Option<&Object>) -> Result<u32, EvalError> {
+    match radix {
+        None => Ok(10),
+        Some(Object::Integer(n)) if (2..=36).contains(n) => Ok(*n as u32),
+        Some(Object::Integer(n)) => {
+            let message = format!("Radix {} is not from 2 to 36", n);
+            Err(EvalError::new(ErrorKind::Domain(message)))
+        }
+        Some(other) => Err(EvalError::type_mismatch("an integer", other)),
     }
 }
 
-fn get_float_op(op: &str, numbers: (f64, f64)) -> Result<Object, String> {
+/// String for a result, charged to the quota before it is copied
+fn new_string(s: &str, env: &Env) -> Result<Object, EvalError> {
+    env.budget().allocate(s.len())?;
+    Ok(Object::Str(s.to_string()))
+}
+
+/// The string library, indices and lengths count chars as documented
+/// in `strings`. The strings it makes are charged to the quota before
+/// they are made
+fn eval_string_library_op(name: &str, list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let args = eval_args(list, env)?;
+    let string = |i: usize| args.get(i).map(string_arg).unwrap_or(Err(EvalError::arity(name)));
+
+    let result = match (name, args.as_slice()) {
+        ("string-length", [_]) => Object::Integer(strings::char_count(string(0)?) as i64),
+        ("substring", [s, start, end @ ..]) if end.len() <= 1 => {
+            let s = string_arg(s)?;
+            let start = index_arg(start)?;
+            let end = match end.first() {
+                Some(end) => index_arg(end)?,
+                None => strings::char_count(s),
+            };
+
+            match strings::substring(s, start, end) {
+                Some(sub) => new_string(sub, env)?,
+                None => {
+                    let length = strings::char_count(s);
+                    let message = format!("Substring from {} to {} of a string of length {}", start, end, length);
+                    return Err(EvalError::new(ErrorKind::Domain(message)));
+                }
+            }
+        }
+        ("string-append", _) => {
+            let parts = (0..args.len()).map(string).collect::<Result<Vec<_>, _>>()?;
+            env.budget().allocate(parts.iter().map(|part| part.len()).sum())?;
+            Object::Str(parts.concat())
+        }
+        ("string-split", [_]) => {
+            let items = string(0)?.split_whitespace().map(|item| Object::Str(item.to_string()));
+            Object::List(items.collect())
+        }
+        ("string-split", [_, _]) => Object::List(strings::split(string(0)?, string(1)?)),
+        ("string-join", [Object::List(items), separator @ ..]) if separator.len() <= 1 => {
+            let separator = separator.first().map(string_arg).unwrap_or(Ok(""))?;
+            let parts = items.iter().map(string_arg).collect::<Result<Vec<_>, _>>()?;
+            let separators = separator.len().saturating_mul(parts.len().saturating_sub(1));
+            env.budget().allocate(parts.iter().map(|part| part.len()).sum::<usize>().saturating_add(separators))?;
+            Object::Str(parts.join(separator))
+        }
+        ("string-join", [other, ..]) => return Err(EvalError::type_mismatch("a list", other)),
+        ("string-trim", [_]) => new_string(string(0)?.trim(), env)?,
+        ("string-trim-left", [_]) => new_string(string(0)?.trim_start(), env)?,
+        ("string-trim-right", [_]) => new_string(string(0)?.trim_end(), env)?,
+        ("string-upcase" | "string-downcase", [_]) => {
+            let upper = name == "string-upcase";
+            env.budget().allocate(strings::case_len(string(0)?, upper))?;
+            match upper {
+                true => Object::Str(string(0)?.to_uppercase()),
+                false => Object::Str(string(0)?.to_lowercase()),
+            }
+        }
+        ("string-graphemes", [_]) => Object::List(strings::graphemes(string(0)?)),
+        ("string-contains", [_, _]) => match strings::find(string(0)?, string(1)?) {
+            Some(index) => Object::Integer(index as i64),
+            None => Object::Bool(false),
+        },
+        ("string-replace", [_, _, _]) => match string(1)? {
+            "" => {
+                let message = "Replacing an empty string".to_string();
+                return Err(EvalError::new(ErrorKind::Domain(message)));
+            }
+            from => {
+                env.budget().allocate(strings::replaced_len(string(0)?, from, string(2)?))?;
+                Object::Str(string(0)?.replace(from, string(2)?))
+            }
+        },
+        ("string-index", [s, Object::Str(c)]) => {
+            let c = c.clone();
+            let index = string_arg(s)?.chars().position(|ch| c.chars().eq([ch]));
+            index.map_or(Object::Bool(false), |i| Object::Integer(i as i64))
+        }
+        ("string-index", [s, pred]) => {
+            let mut found = Object::Bool(false);
+            for (i, c) in string_arg(s)?.chars().enumerate() {
+                if call_procedure(name, pred, vec![Object::Str(c.to_string())], env)? != Object::Bool(false) {
+                    found = Object::Integer(i as i64);
+                    break;
+                }
+            }
+            found
+        }
+        ("string->number", [_, radix @ ..]) if radix.len() <= 1 => {
+            let radix = radix_arg(radix.first())?;
+            strings::parse_number(string(0)?, radix).unwrap_or(Object::Bool(false))
+        }
+        ("number->string", [number, radix @ ..]) if radix.len() <= 1 => match (number, radix_arg(radix.first())?) {
+            (Object::Integer(n), radix) => new_string(&strings::format_radix(*n, radix), env)?,
+            (Object::Float(f), 10) => new_string(&f.to_string(), env)?,
+            (Object::Float(_), radix) => {
+                let message = format!("A float can only be written in radix 10, not {}", radix);
+                return Err(EvalError::new(ErrorKind::Domain(message)));
+            }
+            (other, _) => return Err(EvalError::type_mismatch("a number", other)),
+        },
+        ("format", [destination, Object::Str(control), args @ ..]) => {
+            let text = format::format(control, args, env.budget())?;
+            match destination {
+                Object::Bool(true) => {
+                    print!("{}", text);
+                    io::stdout().flush().map_err(|e| EvalError::io(e.to_string()))?;
+                    Object::Void
+                }
+                Object::Bool(false) => Object::Str(text),
+                Object::Port(port) => {
+                    port.write(&text);
+                    Object::Void
+                }
+                other => return Err(EvalError::type_mismatch("true, false or a port", other)),
+            }
+        }
+        ("format", [_, other, ..]) => return Err(EvalError::type_mismatch("a string", other)),
+        ("open-output-string", []) => Object::Port(Rc::new(Port::default())),
+        ("get-output-string", [Object::Port(port)]) => new_string(&port.buffer.borrow(), env)?,
+        ("get-output-string", [other]) => return Err(EvalError::type_mismatch("a port", other)),
+        (comparison, [_, ..]) if comparison.starts_with("string") && comparison.ends_with('?') => {
+            let parts = (0..args.len()).map(string).collect::<Result<Vec<_>, _>>()?;
+            let fold_case = comparison.starts_with("string-ci");
+            let relation = comparison.trim_start_matches("string-ci").trim_start_matches("string");
+            let holds = |ordering: Ordering| match relation {
+                "=?" => ordering == Ordering::Equal,
+                "<?" => ordering == Ordering::Less,
+                ">?" => ordering == Ordering::Greater,
+                "<=?" => ordering != Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            };
+            let ordered = parts.windows(2).all(|pair| holds(strings::compare(pair[0], pair[1], fold_case)));
+            Object::Bool(ordered)
+        }
+        _ => return Err(EvalError::arity(name)),
+    };
+
+    Ok(result)
+}
+
+fn get_float_op(op: &str, numbers: (f64, f64), strict: bool) -> Result<Object, EvalError> {
     let (l, r) = numbers;
 
-    match op {
-        "+" => Ok(Object::Float(l + r)),
-        "-" => Ok(Object::Float(l - r)),
-        "*" => Ok(Object::Float(l * r)),
-        "/" => Ok(Object::Float(l / r)),
-        ">" => Ok(Object::Bool(l > r)),
-        "<" => Ok(Object::Bool(l < r)),
-        "==" => Ok(Object::Bool(l == r)),
-        "<>" => Ok(Object::Bool(l != r)),
-        "!=" => Ok(Object::Bool(l != r)),
-        ">=" => Ok(Object::Bool(l >= r)),
-        "<=" => Ok(Object::Bool(l <= r)),
-        "^" => Ok(Object::Float(l.powf(r))),
-        _ => Err(format!("Invalid infix operator: {}", op)),
+    let result = match op {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        "/" if strict && r == 0.0 => return Err(EvalError::new(ErrorKind::DivideByZero)),
+        "/" => l / r,
+        ">" => return Ok(Object::Bool(l > r)),
+        "<" => return Ok(Object::Bool(l < r)),
+        "==" => return Ok(Object::Bool(l == r)),
+        "<>" => return Ok(Object::Bool(l != r)),
+        "!=" => return Ok(Object::Bool(l != r)),
+        ">=" => return Ok(Object::Bool(l >= r)),
+        "<=" => return Ok(Object::Bool(l <= r)),
+        "^" => l.powf(r),
+        _ => return Err(EvalError::syntax(format!("Invalid infix operator: {}", op))),
+    };
+
+    if strict && !result.is_finite() {
+        return Err(EvalError::new(ErrorKind::Domain(format!("({} {} {}) produced {}", op, l, r, result))));
     }
+
+    Ok(Object::Float(result))
+}
+
+fn overflow(op: &str) -> EvalError {
+    EvalError::new(ErrorKind::Overflow(op.to_string()))
 }
 
-fn get_int_op(op: &str, numbers: (i64, i64)) -> Result<Object, String> {
+fn get_int_op(op: &str, numbers: (i64, i64)) -> Result<Object, EvalError> {
     let (l, r) = numbers;
 
     match op {
-        "+" => Ok(Object::Integer(l + r)),
-        "-" => Ok(Object::Integer(l - r)),
-        "*" => Ok(Object::Integer(l * r)),
-        "/" => Ok(Object::Integer(l / r)),
+        "+" => l.checked_add(r).map(Object::Integer).ok_or_else(|| overflow(op)),
+        "-" => l.checked_sub(r).map(Object::Integer).ok_or_else(|| overflow(op)),
+        "*" => l.checked_mul(r).map(Object::Integer).ok_or_else(|| overflow(op)),
+        "/" if r == 0 => Err(EvalError::new(ErrorKind::DivideByZero)),
+        "/" => l.checked_div(r).map(Object::Integer).ok_or_else(|| overflow(op)),
         ">" => Ok(Object::Bool(l > r)),
         "<" => Ok(Object::Bool(l < r)),
         "<>" => Ok(Object::Bool(l != r)),
@@ -76,12 +287,19 @@ fn get_int_op(op: &str, numbers: (i64, i64)) -> Result<Object, String> {
         "==" => Ok(Object::Bool(l == r)),
         ">=" => Ok(Object::Bool(l >= r)),
         "<=" => Ok(Object::Bool(l <= r)),
-        "^" => Ok(Object::Integer(l.pow(r as u32))),
-        _ => Err(format!("Invalid infix operator: {}", op)),
+        "^" if r < 0 => Err(EvalError::new(ErrorKind::Domain(format!(
+            "Negative integer exponent {}, use a float base",
+            r
+        )))),
+        "^" => match u32::try_from(r) {
+            Ok(exp) => l.checked_pow(exp).map(Object::Integer).ok_or_else(|| overflow(op)),
+            Err(_) => Err(EvalError::new(ErrorKind::Domain(format!("Exponent {} is too large", r)))),
+        },
+        _ => Err(EvalError::syntax(format!("Invalid infix operator: {}", op))),
     }
 }
 
-fn num_operations(operator: Object, numbers: (Number, Number)) -> Result<Object, String> {
+fn num_operations(operator: Object, numbers: (Number, Number), strict: bool) -> Result<Object, EvalError> {
     let (left, right) = numbers;
 
     match operator {
@@ -92,28 +310,28 @@ fn num_operations(operator: Object, numbers: (Number, Number)) -> Result<Object,
                     Number::Integer(i) => i as f64,
                 };
 
-                return get_float_op(s.as_str(), (l, r));
+                get_float_op(s.as_str(), (l, r), strict)
             } else if let Number::Integer(l) = left {
                 let r = match right {
                     Number::Float(r) => {
                         let left = l as f64;
-                        return get_float_op(s.as_str(), (left, r));
+                        return get_float_op(s.as_str(), (left, r), strict);
                     }
                     Number::Integer(i) => i,
                 };
 
-                return get_int_op(s.as_str(), (l, r));
+                get_int_op(s.as_str(), (l, r))
             } else {
-                Err(format!("Left operand must be a number {:?}", left))
+                Err(EvalError::syntax(format!("Left operand must be a number {:?}", left)))
             }
         }
-        _ => Err("Operator must be a symbol".to_string()),
+        _ => Err(EvalError::type_mismatch("a symbol", &operator)),
     }
 }
 
-fn eval_binary_op(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
+fn eval_binary_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
     if list.len() != 3 {
-        return Err("Invalid number of arguments for infix operator".to_string());
+        return Err(EvalError::arity("infix operator"));
     }
 
     let operator = list[0].clone();
@@ -124,61 +342,42 @@ fn eval_binary_op(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
         let r = match right {
             Object::Float(g) => Number::Float(g),
             Object::Integer(i) => Number::Integer(i),
-            _ => return Err(format!("Right operand must be a number {:?}", right)),
+            _ => return Err(EvalError::type_mismatch("a number", &right)),
         };
 
-        return num_operations(operator, (Number::Float(f), r));
+        return num_operations(operator, (Number::Float(f), r), env.strict_float());
     }
 
     if let Object::Integer(f) = left {
         let r = match right {
             Object::Float(i) => Number::Float(i),
             Object::Integer(i) => Number::Integer(i),
-            _ => return Err(format!("Right operand must be a number {:?}", right)),
+            _ => return Err(EvalError::type_mismatch("a number", &right)),
         };
 
-        num_operations(operator, (Number::Integer(f), r))
+        num_operations(operator, (Number::Integer(f), r), env.strict_float())
     } else {
-        Err("Operands must be a numbers".to_string())
-    }
-}
-
-fn eval_define(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err("Invalid number of arguments for define".to_string());
+        Err(EvalError::type_mismatch("a number", &left))
     }
-
-    let sym = match &list[1] {
-        Object::Symbol(s) => s.clone(),
-        Object::Keyword(k) => return Err(format!("Cannot define the keyword `{}`", k)),
-        _ => return Err("Invalid define".to_string()),
-    };
-    let val = eval_obj(&list[2], env)?;
-    env.set(&sym, val);
-    Ok(Object::Void)
 }
 
-fn eval_if(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
-    if list.len() != 4 {
-        return Err("Invalid number of arguments for if statement".to_string());
-    }
-
-    let cond_obj = eval_obj(&list[1], env)?;
-    let cond = match cond_obj {
-        Object::Bool(b) => b,
+/// Whether the value of the condition of an `if` is true
+fn condition_value(cond_obj: Object, env: &mut Env) -> Result<bool, EvalError> {
+    match cond_obj {
+        Object::Bool(b) => Ok(b),
         Object::List(l) => {
             let val = eval_list(&l, env)?;
 
             match val {
-                Object::Bool(b) => b,
+                Object::Bool(b) => Ok(b),
                 Object::Symbol(s) => {
                     if let Some(Object::Bool(b)) = env.get(&s) {
-                        b
+                        Ok(b)
                     } else {
-                        return Err(format!("Unbound variable: {}", s));
+                        Err(EvalError::unbound(&s))
                     }
                 }
-                _ => return Err("Var must be a boolean".to_string()),
+                _ => Err(EvalError::type_mismatch("a boolean", &val)),
             }
         }
         Object::Symbol(s) => {
@@ -186,90 +385,212 @@ fn eval_if(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
 
             if let Some(val) = value {
                 match val {
-                    Object::Bool(b) => b,
-                    _ => return Err("Variable need to be a boolean".to_string()),
+                    Object::Bool(b) => Ok(b),
+                    _ => Err(EvalError::type_mismatch("a boolean", &val)),
                 }
             } else {
-                return Err(format!("Unbound variable: {}", s));
+                Err(EvalError::unbound(&s))
             }
         }
-        _ => return Err("Condition must be a boolean".to_string()),
-    };
-
-    if cond {
-        eval_obj(&list[2], env)
-    } else {
-        eval_obj(&list[3], env)
+        _ => Err(EvalError::type_mismatch("a boolean", &cond_obj)),
     }
 }
 
-fn eval_function_definition(list: &[Object]) -> Result<Object, String> {
+fn eval_function_definition(list: &[Object]) -> Result<Object, EvalError> {
+    if list.len() != 3 {
+        return Err(EvalError::arity("lambda"));
+    }
+
     let params = match &list[1] {
-        Object::List(list) => {
-            let mut params = Vec::new();
-            for param in list {
-                match param {
-                    Object::Symbol(s) => params.push(s.clone()),
-                    _ => return Err("Invalid lambda parameter".to_string()),
-                }
+        Object::List(params) => {
+            for param in params {
+                check_param(param)?;
             }
-            params
+            params.clone()
         }
-        _ => return Err("Invalid lambda".to_string()),
+        _ => return Err(EvalError::syntax("Invalid lambda")),
     };
 
     let body = match &list[2] {
         Object::List(list) => list.clone(),
-        _ => return Err("Invalid lambda".to_string()),
+        _ => return Err(EvalError::syntax("Invalid lambda")),
     };
 
     Ok(Object::Lambda(params, body))
 }
 
-fn eval_function_call(s: &str, list: &[Object], env: &mut Env) -> Result<Object, String> {
-    let lamdba = env.get(s);
-    if lamdba.is_none() {
-        return Err(format!("Unbound symbol: {}", s));
+/// A parameter is a symbol or a list of parameters, which may end
+/// in `. rest` for the remaining items
+fn check_param(param: &Object) -> Result<(), EvalError> {
+    match param {
+        Object::Symbol(s) if s != "." => Ok(()),
+        Object::List(items) => {
+            let fixed = match items.iter().position(|p| *p == Object::Symbol(".".to_string())) {
+                Some(dot) if dot + 2 == items.len() => dot,
+                Some(_) => return Err(EvalError::syntax(format!("Invalid lambda parameter: {}", param))),
+                None => items.len(),
+            };
+            items.iter().take(fixed).try_for_each(check_param)?;
+            items.get(fixed + 1).map_or(Ok(()), check_param)
+        }
+        _ => Err(EvalError::syntax(format!("Invalid lambda parameter: {}", param))),
     }
+}
 
-    let func = lamdba.unwrap();
-    match func {
-        Object::Lambda(params, body) => {
-            let mut new_env = env.clone();
-            for (i, param) in params.iter().enumerate() {
-                let arg = match list.get(i + 1) {
-                    Some(a) => a,
-                    None => return Err(format!("Invalid number of arguments for lambda: {}", s)),
-                };
-                let val = eval_obj(arg, env)?;
-                new_env.set(param, val);
+/// Bind a parameter to its argument, a list parameter binds the
+/// items of the argument element-wise
+fn bind_param(name: &str, param: &Object, value: Object, env: &mut Env) -> Result<(), EvalError> {
+    let params = match param {
+        Object::Symbol(s) => {
+            env.set(s, value);
+            return Ok(());
+        }
+        Object::List(params) => params,
+        _ => return Err(EvalError::syntax(format!("Invalid lambda parameter: {}", param))),
+    };
+
+    let values = match value {
+        Object::List(values) => values,
+        other => return Err(EvalError::type_mismatch("a list", &other)),
+    };
+
+    match params.iter().position(|p| *p == Object::Symbol(".".to_string())) {
+        Some(dot) => {
+            if values.len() < dot {
+                return Err(EvalError::arity(name));
+            }
+            let mut values = values;
+            let rest = values.split_off(dot);
+            for (param, value) in params[..dot].iter().zip(values) {
+                bind_param(name, param, value, env)?;
+            }
+            bind_param(name, &params[dot + 1], Object::List(rest), env)
+        }
+        None if params.len() == values.len() => {
+            for (param, value) in params.iter().zip(values) {
+                bind_param(name, param, value, env)?;
             }
-            eval_obj(&Object::List(body), &mut new_env)
+            Ok(())
         }
-        Object::Str(str) => Ok(Object::Str(str)),
-        Object::Bool(b) => Ok(Object::Bool(b)),
-        Object::Integer(i) => Ok(Object::Integer(i)),
-        Object::Float(f) => Ok(Object::Float(f)),
-        Object::List(l) => eval_list(&l, env),
-        Object::Keyword(k) => eval_keyword(k.as_str(), &Vec::from(list), env),
-        _ => Err(format!("Not a lambda: {}", s)),
+        None => Err(EvalError::arity(name)),
+    }
+}
+
+/// Whether a value has the shape of a parameter, a list in it needs
+/// as many items as it has names
+fn fits(param: &Object, value: &Object) -> bool {
+    let (params, values) = match (param, value) {
+        (Object::List(params), Object::List(values)) => (params, values),
+        (Object::List(_), _) => return false,
+        _ => return true,
+    };
+
+    match params.iter().position(|p| *p == Object::Symbol(".".to_string())) {
+        Some(dot) => values.len() >= dot && params[..dot].iter().zip(values).all(|(p, v)| fits(p, v)),
+        None => params.len() == values.len() && params.iter().zip(values).all(|(p, v)| fits(p, v)),
+    }
+}
+
+/// Bind the pattern of a `let` or a `destructuring-bind`, a value of
+/// another shape is reported with the form and the pattern
+fn bind_pattern(form: &str, pattern: &Object, value: Object, env: &mut Env) -> Result<(), EvalError> {
+    if !fits(pattern, &value) {
+        let expected = format!("a value matching the {} pattern {}", form, pattern);
+        return Err(EvalError::type_mismatch(&expected, &value));
+    }
+
+    bind_param(form, pattern, value, env)
+}
+
+/// Copy of the environment for a new scope, it is charged as one
+/// scope since its bindings are values allocated already
+fn new_scope(env: &Env) -> Result<Env, EvalError> {
+    env.budget().allocate(std::mem::size_of::<Env>())?;
+    Ok(env.clone())
+}
+
+/// Run a lambda with arguments that are already evaluated
+fn call_lambda(name: &str, params: &[Object], body: Vec<Object>, values: Vec<Object>, env: &mut Env) -> Result<Object, EvalError> {
+    if values.len() < params.len() {
+        return Err(EvalError::arity(name));
+    }
+
+    let mut new_env = new_scope(env)?;
+    for (param, val) in params.iter().zip(values) {
+        bind_param(name, param, val, &mut new_env)?;
+    }
+    eval_obj(&Object::List(body), &mut new_env).map_err(|e| e.with_frame(name))
+}
+
+/// Call a procedure value, like the thunks given to `dynamic-wind`
+fn call_procedure(name: &str, func: &Object, values: Vec<Object>, env: &mut Env) -> Result<Object, EvalError> {
+    match func {
+        Object::Lambda(params, body) => call_lambda(name, params, body.clone(), values, env),
+        Object::RecordProc(proc) => call_record_proc(proc, values, env),
+        Object::Generic(generic) => call_generic(generic, values, env),
+        Object::NextMethod(next) => call_next_method(next, values, env),
+        Object::Continuation(k) => Machine::run(Next::Resume(k.clone(), self::values(values)), env),
+        Object::Generator(_) => Machine::run(Next::Apply(name.to_string(), func.clone(), values), env),
+        _ => Err(EvalError::type_mismatch("a lambda", func)),
     }
 }
 
-fn eval_symbol(s: &str, env: &mut Env) -> Result<Object, String> {
-    let val = env.get(s);
-    if val.is_none() {
-        return Err(format!("Unbound symbol: {}", s));
+fn eval_symbol(s: &str, env: &mut Env) -> Result<Object, EvalError> {
+    match env.get(s) {
+        Some(value) => Ok(value),
+        // `:name` evaluates to itself, as the initargs of `make-instance`
+        None if s.len() > 1 && s.starts_with(':') => Ok(Object::Symbol(s.to_string())),
+        None => Err(EvalError::unbound(s)),
     }
-    Ok(val.unwrap())
 }
 
-fn get_type(obj: &Object) -> String {
+/// Names `get_type` gives, methods can specialize on them
+const TYPE_NAMES: [&str; 23] = [
+    "Keyword",
+    "List",
+    "Symbol",
+    "Lambda",
+    "Macro",
+    "SyntaxRules",
+    "Condition",
+    "RecordType",
+    "Record",
+    "RecordProc",
+    "Generic",
+    "NextMethod",
+    "Continuation",
+    "Generator",
+    "Promise",
+    "Regex",
+    "Port",
+    "Values",
+    "Str",
+    "Bool",
+    "Integer",
+    "Float",
+    "Void",
+];
+
+pub fn get_type(obj: &Object) -> String {
     match obj {
         Object::Keyword(_) => "Keyword".to_string(),
         Object::List(_) => "List".to_string(),
         Object::Symbol(_) => "Symbol".to_string(),
         Object::Lambda(_, _) => "Lambda".to_string(),
+        Object::Macro(_, _) => "Macro".to_string(),
+        Object::SyntaxRules(_, _) => "SyntaxRules".to_string(),
+        Object::Condition(_) => "Condition".to_string(),
+        Object::RecordType(_) => "RecordType".to_string(),
+        Object::Record(_) => "Record".to_string(),
+        Object::RecordProc(_) => "RecordProc".to_string(),
+        Object::Generic(_) => "Generic".to_string(),
+        Object::NextMethod(_) => "NextMethod".to_string(),
+        Object::Continuation(_) => "Continuation".to_string(),
+        Object::Generator(_) => "Generator".to_string(),
+        Object::Promise(_) => "Promise".to_string(),
+        Object::Regex(_) => "Regex".to_string(),
+        Object::Port(_) => "Port".to_string(),
+        Object::Values(_) => "Values".to_string(),
         Object::Str(_) => "Str".to_string(),
         Object::Bool(_) => "Bool".to_string(),
         Object::Integer(_) => "Integer".to_string(),
@@ -288,7 +609,46 @@ fn get_doc(k: String) -> String {
         "debug" => "Print debug info".to_string(),
         "equal" => "Check if two values are equal".to_string(),
         "print" => "Print a value".to_string(),
-        "load" => "Load a file".to_string(),
+        "load" => "Load a file, returning the result of each form".to_string(),
+        "quote" => "Return a form without evaluating it".to_string(),
+        "quasiquote" => "Quote a template, evaluating the unquoted parts".to_string(),
+        "unquote" => "Evaluate a form inside a quasiquote".to_string(),
+        "unquote-splicing" => "Evaluate a list and splice it inside a quasiquote".to_string(),
+        "defmacro" => "Define a macro".to_string(),
+        "macroexpand" => "Expand a macro form until it is not a macro".to_string(),
+        "macroexpand-1" => "Expand a macro form once".to_string(),
+        "define-syntax" => "Define a hygienic macro".to_string(),
+        "let-syntax" => "Define hygienic macros for a body".to_string(),
+        "syntax-rules" => "Create a pattern based macro transformer".to_string(),
+        "begin" => "Evaluate forms in order, returning the last value".to_string(),
+        "guard" => "Handle the errors raised by a body".to_string(),
+        "handler-bind" => "Run handlers for conditions without unwinding".to_string(),
+        "restart-case" => "Establish restarts for a form".to_string(),
+        "module" => "Define a module and the names it exports".to_string(),
+        "import" => "Bind the names exported by modules".to_string(),
+        "require" => "Load a library unless it was already loaded".to_string(),
+        "define-record-type" => "Define a record type and its procedures".to_string(),
+        "defclass" => "Define a class with slots and superclasses".to_string(),
+        "defgeneric" => "Define a generic function".to_string(),
+        "defmethod" => "Add a method to a generic function".to_string(),
+        "match" => "Evaluate the clause whose pattern matches a value".to_string(),
+        "reset" => "Delimit the continuations captured by shift".to_string(),
+        "shift" => "Capture the continuation up to the enclosing reset".to_string(),
+        "delay" => "Make a promise of the value of a form".to_string(),
+        "delay-force" => "Make a promise of the value of a form that returns a promise".to_string(),
+        "stream-cons" => "Make a stream of a value and a form for the rest".to_string(),
+        "let" => "Bind local variables".to_string(),
+        "let*" => "Bind local variables in sequence".to_string(),
+        "letrec" => "Bind local variables that can refer to each other".to_string(),
+        "destructuring-bind" => "Bind the variables of a pattern to the items of a list".to_string(),
+        "let-values" => "Bind local variables to the values of forms".to_string(),
+        "define-values" => "Define a symbol for each value of a form".to_string(),
+        "receive" => "Bind the values of a form".to_string(),
+        "cond" => "Conditional with several clauses".to_string(),
+        "when" => "Evaluate the body when the condition is true".to_string(),
+        "unless" => "Evaluate the body when the condition is false".to_string(),
+        "and" => "True when every value is true".to_string(),
+        "or" => "True when any value is true".to_string(),
         _ => "".to_string(),
     }
 }
@@ -310,8 +670,12 @@ Example:
         "lambda" => r#"
 lambda (params) (body)
 
+A parameter may be a list of parameters, ending in . rest or not,
+that binds the items of its argument
+
 Example:
 (lambda (a b) (+ a b))
+(lambda ((x y) . rest) (+ x y))
         "#.to_string(),
         "true" => "Boolean value true".to_string(),
         "false" => "Boolean value false".to_string(),
@@ -331,13 +695,277 @@ Example:
 Print a value
 Example:
 (print age) //1
+        "#.to_string(),
+        "quote" => r#"
+quote (form) or 'form
+
+Example:
+(quote (1 2 3))
+'(1 2 3)
+        "#.to_string(),
+        "quasiquote" => r#"
+quasiquote (template) or `template
+
+Example:
+`(if ,cond ,@body)
+        "#.to_string(),
+        "defmacro" => r#"
+defmacro (name) (params) (body)
+
+Example:
+(defmacro my-unless (c x y) `(if ,c ,y ,x))
+(defmacro my-list (&rest xs) `(,@xs))
+        "#.to_string(),
+        "macroexpand" => r#"
+Expand a macro form until its head is not a macro
+
+Example:
+(macroexpand '(my-unless true 1 2)) // (if true 2 1)
+        "#.to_string(),
+        "macroexpand-1" => r#"
+Expand a macro form once
+
+Example:
+(macroexpand-1 '(my-unless true 1 2)) // (if true 2 1)
+        "#.to_string(),
+        "define-syntax" => r#"
+define-syntax (name) (syntax-rules (literals) (pattern template)...)
+
+Example:
+(define-syntax my-if
+  (syntax-rules (then else)
+    ((_ c then t else e) (if c t e))))
+        "#.to_string(),
+        "let-syntax" => r#"
+let-syntax ((name transformer)...) (body)
+
+Example:
+(let-syntax ((first (syntax-rules () ((_ a b ...) a)))) (first 1 2 3))
+        "#.to_string(),
+        "syntax-rules" => r#"
+syntax-rules (literals) (pattern template)...
+
+Patterns support `_`, literals, `...` repetition and `(a . rest)`,
+the variables bound by the template are renamed so they do not
+capture the variables of the caller
+
+Example:
+(syntax-rules () ((_ x ...) (+ x ...)))
+        "#.to_string(),
+        "begin" => r#"
+begin (forms)...
+
+Example:
+(begin (print "hi") (+ 1 2)) // 3
+        "#.to_string(),
+        "guard" => r#"
+guard (var (test (body)...)... (else (body)...)) (body)...
+
+The clauses see the condition as `var`, with no matching clause
+the error is raised again
+
+Example:
+(guard (e ((condition? e) (condition-message e)))
+  (error "Something failed" 42))
+        "#.to_string(),
+        "handler-bind" => r#"
+handler-bind ((kind handler)...) (body)...
+
+The handlers run where the condition is signaled, before unwinding.
+A handler that returns declines, `invoke-restart` handles it.
+`condition` matches every condition and `error` every error
+
+Example:
+(handler-bind ((divide-by-zero (lambda (c) (invoke-restart 'use-value 0))))
+  (+ 1 (/ 1 0)))
+        "#.to_string(),
+        "restart-case" => r#"
+restart-case (form) (name (params...) (body)...)...
+
+Evaluate the form, `(invoke-restart 'name args...)` returns from
+restart-case with the value of the restart body
+
+Example:
+(restart-case (error "Bad value") (use-default () 0))
+        "#.to_string(),
+        "module" => r#"
+module (name) (export (symbol)...) (body)...
+
+Evaluate the body once, in its own scope. The exported definitions
+are also reachable as `name:symbol`, the others stay private
+
+Example:
+(module counter (export next)
+  (define step 1)
+  (define next (lambda (n) (+ n step))))
+        "#.to_string(),
+        "import" => r#"
+import (spec)...
+
+A spec is a module name, a file path, `(only spec sym...)`, `(except spec sym...)`,
+`(rename spec (from to)...)` or `(prefix-in prefix spec)`.
+A module that is not defined yet is loaded from `name.lisp`, once
+
+Example:
+(import (prefix-in c/ counter))
+(c/next 1) // 2
+        "#.to_string(),
+        "require" => r#"
+require (name)
+
+Load `name.lisp`, or `name.cl`, the first time it is required.
+Files are looked up next to the file being loaded, then in the
+directories of `*load-path*`
+
+Example:
+(define *load-path* '("lib"))
+(require 'util)
+        "#.to_string(),
+        "define-record-type" => r#"
+define-record-type (name) ((constructor) (field)...) (predicate) ((field) (accessor) (modifier))...
+
+Define a record type, the constructor takes the fields it lists and
+the modifier of a field is optional
+
+Example:
+(define-record-type point (make-point x y) point? (x point-x set-point-x!) (y point-y))
+(define p (make-point 1 2))
+(set-point-x! p 5)
+(point-x p) // 5
+        "#.to_string(),
+        "defclass" => r#"
+defclass (name) ((super)...) ((slot) | ((slot) :initform (form) :accessor (name))...)
+
+Define a class, it inherits the slots of its superclasses.
+`(make-instance 'name :slot value...)` creates an instance,
+`slot-value` and `set-slot-value!` read and change its slots
+
+Example:
+(defclass circle (shape) ((r :initform 1 :accessor radius)))
+(radius (make-instance 'circle :r 2)) // 2
+        "#.to_string(),
+        "defmethod" => r#"
+defmethod (name) [:before | :after] ((param) | ((param) (type))...) (body)...
+
+Add a method to a generic function. A type is a class, a record type
+or a type shown by `debug`, as Integer or Str. The most specific method
+runs and `(call-next-method)` runs the next one, the :before and
+:after methods run around them
+
+Example:
+(defmethod area ((c circle)) (* 3 (radius c) (radius c)))
+(defmethod combine ((a Str) (b Str)) (concat a b))
+        "#.to_string(),
+        "match" => r#"
+match (value) ((pattern) (body)...)...
+
+Patterns: literals, 'datum, _ for anything, a symbol binds the value,
+(p1 p2 . rest) for lists, (? pred p...) when (pred value) is true,
+($ type p...) for records and `(tag ,p ,@rest) quasiquoted shapes
+
+Example:
+(match expr
+  (`(add ,a ,b) (+ a b))
+  (($ point x y) (+ x y))
+  ((? condition? c) (condition-message c))
+  (_ 0))
+        "#.to_string(),
+        "reset" => r#"
+reset (body)...
+
+Example:
+(+ 1 (reset (+ 10 (shift k (k (k 100)))))) // 121
+        "#.to_string(),
+        "shift" => r#"
+shift (k) (body)...
+
+Binds k to the continuation up to the enclosing reset and evaluates the
+body in its place, calling k returns what the reset would return
+
+Example:
+(reset (* 2 (shift k (+ (k 1) (k 10))))) // 22
+        "#.to_string(),
+        "delay" => r#"
+delay (form)
+
+The form is evaluated the first time the promise is forced, with the
+values its symbols had when the promise was made
+
+Example:
+(force (delay (+ 1 2))) // 3
+        "#.to_string(),
+        "delay-force" => r#"
+delay-force (form)
+
+Like delay for a form that returns a promise, a chain of them is forced
+in constant stack
+
+Example:
+(define countdown (lambda (n) (delay-force (if (< n 1) (delay 'done) (countdown (- n 1))))))
+(force (countdown 100000)) // done
+        "#.to_string(),
+        "stream-cons" => r#"
+stream-cons (first) (rest)
+
+A stream is '() or the list of its first item and a promise of the rest
+
+Example:
+(define ints (lambda (n) (stream-cons n (ints (+ n 1)))))
+(stream-take (stream-map (lambda (n) (* n n)) (ints 1)) 3) // (1 4 9)
+        "#.to_string(),
+        "let" => r#"
+let ((name value)...) (body)...
+let (loop) ((name value)...) (body)...
+
+Example:
+(let ((a 1) (b 2)) (+ a b)) // 3
+(let loop ((i 0)) (if (< i 10) (loop (+ i 1)) i)) // 10
+(let (((a b) '(1 2))) (+ a b)) // 3
+        "#.to_string(),
+        "destructuring-bind" => r#"
+destructuring-bind (pattern) (value) (body)...
+
+Example:
+(destructuring-bind (a (b c) . rest) '(1 (2 3) 4 5) (+ (+ a b) c)) // 6
+        "#.to_string(),
+        "let-values" => r#"
+let-values (((formals) (value))...) (body)...
+
+Formals are like lambda parameters, a single symbol gets every value as a list
+
+Example:
+(let-values (((q r) (floor/ 7 2)) (all (values 1 2))) (+ q r)) // 4
+        "#.to_string(),
+        "define-values" => r#"
+define-values (formals) (value)
+
+Example:
+(define-values (small large) (partition (lambda (n) (< n 3)) '(1 2 3 4)))
+        "#.to_string(),
+        "receive" => r#"
+receive (formals) (value) (body)...
+
+Example:
+(receive (q . rest) (values 1 2 3) rest) // (2 3)
+        "#.to_string(),
+        "cond" => r#"
+cond ((condition) (body)...)... (else (body)...)
+
+Example:
+(cond ((< n 0) "negative") ((> n 0) "positive") (else "zero"))
+        "#.to_string(),
+        "when" => r#"
+when (condition) (body)...
+
+Example:
+(when (> age 18) (print "adult"))
         "#.to_string(),
         _ => "".to_string(),
     }
 }
-fn eval_print(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
+fn eval_print(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
     if list.len() == 1 {
-        return Err("Invalid number of arguments for print".to_string());
+        return Err(EvalError::arity("print"));
     }
 
     let obj = list[1].clone();
@@ -353,11 +981,7 @@ fn eval_print(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
             println!("{}", val);
         }
         Object::Symbol(s) => {
-            let val = env.get(&s);
-            if val.is_none() {
-                return Err(format!("Unbound symbol: {}", s));
-            }
-            let val = val.unwrap();
+            let val = env.get(&s).ok_or_else(|| EvalError::unbound(&s))?;
             println!("{}", val);
         }
         _ => {
@@ -367,9 +991,9 @@ fn eval_print(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
     Ok(Object::Void)
 }
 
-fn eval_debug(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
+fn eval_debug(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
     if list.len() == 1 {
-        return Err("Invalid number of arguments for print".to_string());
+        return Err(EvalError::arity("print"));
     }
 
     let obj = list[1].clone();
@@ -380,11 +1004,7 @@ fn eval_debug(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
             Ok(Object::Void)
         }
         Object::Symbol(s) => {
-            let val = env.get(&s);
-            if val.is_none() {
-                return Err(format!("Unbound symbol: {}", s));
-            }
-            let val = val.unwrap();
+            let val = env.get(&s).ok_or_else(|| EvalError::unbound(&s))?;
             let t = get_type(&val);
 
             println!("Type: {t}, Var {s}: {}", val);
@@ -415,330 +1035,4424 @@ fn eval_debug(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
             println!("{}", obj);
             Ok(Object::Void)
         }
-        _ => Err("Invalid print argument".to_string()),
+        _ => Err(EvalError::syntax("Invalid print argument")),
     }
 }
 
-fn eval_load(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
+fn eval_load(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
     if list.len() == 1 {
-        return Err("Invalid number of arguments for load".to_string());
+        return Err(EvalError::arity("load"));
     }
 
     let obj = list[1].clone();
 
-    let mut file = match obj {
+    let file = match obj {
         Object::Str(s) => s,
         Object::Symbol(sym) => {
-            let val = env.get(&sym);
-            if val.is_none() {
-                return Err(format!("Unbound symbol: {}", sym));
-            }
-            match val.unwrap() {
+            match env.get(&sym).ok_or_else(|| EvalError::unbound(&sym))? {
                 Object::Str(s) => s,
-                _ => return Err("Load arg must be a String".to_string()),
+                other => return Err(EvalError::type_mismatch("a string", &other)),
             }
         }
-        _ => return Err("Load argument must be a String or Symbol to String".to_string()),
+        _ => return Err(EvalError::type_mismatch("a string or a symbol", &obj)),
     };
 
-    let ext = match file.split('.').last() {
-        Some(s) => s,
-        _ => "",
-    };
+    let path = load::resolve(&file, env)?;
+    env.sandbox().check_load(&path)?;
 
-    if ext != "lisp" && ext != "cl" && file.contains('.') {
-        return Err(format!("Invalid file extension: {}", ext));
-    }
+    load_file(&path, env)
+}
 
-    if ext != "cl" && ext != "lisp" {
-        file = file.to_string() + ".lisp";
+/// `(require name)` loads the library `name` unless it was already required,
+/// the name is a string or a symbol
+fn eval_require(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() != 2 {
+        return Err(EvalError::arity("require"));
     }
 
-    let data = match std::fs::read_to_string(&file) {
-        Ok(s) => s,
-        Err(_) => {
-            return Err(format!("Module {} not found", file));
-        }
+    let name = match eval_obj(&list[1], env)? {
+        Object::Str(s) | Object::Symbol(s) => s,
+        other => return Err(EvalError::type_mismatch("a string or a symbol", &other)),
     };
 
-    let parsed_list = parse(&data);
-    let ls = match parsed_list {
-        Ok(list) => list,
-        Err(e) => return Err(e.to_string()),
-    };
+    let path = load::resolve(&name, env)?;
+    env.sandbox().check_load(&path)?;
 
-    Ok(eval_obj(&ls, env).unwrap())
-}
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| EvalError::io(format!("Could not read {}: {}", path.display(), err)))?;
 
-fn eval_equal(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err("Invalid number of arguments for equal".to_string());
+    if !env.modules().borrow_mut().require(canonical.clone()) {
+        return Ok(Object::Void);
     }
 
-    let left = eval_obj(&list[1], env)?;
-    let right = eval_obj(&list[2], env)?;
+    if let Err(err) = load_file(&canonical, env) {
+        // A library that failed to load can be required again
+        env.modules().borrow_mut().forget_required(&canonical);
+        return Err(err);
+    }
 
-    Ok(Object::Bool(left == right))
+    Ok(Object::Void)
 }
 
-fn eval_keyword(kw: &str, list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
-    match kw {
-        "define" => eval_define(list, env),
-        "load" => eval_load(list, env),
-        "print" => eval_print(list, env),
-        "debug" => eval_debug(list, env),
-        "if" => eval_if(list, env),
-        "lambda" => eval_function_definition(list),
-        "equal" => eval_equal(list, env),
-        "true" => Ok(Object::Bool(true)),
-        "false" => Ok(Object::Bool(false)),
-        _ => Err(format!("Invalid keyword: {}", kw)),
+/// Evaluate a file in `env` with `run`, it is the current file while
+/// it runs and loading it again from itself is an error
+fn in_file(
+    path: &Path,
+    env: &mut Env,
+    run: fn(&Program, &Path, &mut Env) -> Result<Object, EvalError>,
+) -> Result<Object, EvalError> {
+    let path = path
+        .canonicalize()
+        .map_err(|err| EvalError::io(format!("Could not read {}: {}", path.display(), err)))?;
+    let data = std::fs::read_to_string(&path)
+        .map_err(|err| EvalError::io(format!("Could not read {}: {}", path.display(), err)))?;
+    let program = parse_program(&data).map_err(|err| EvalError::from(err).with_file(&path))?;
+
+    if !env.modules().borrow_mut().start_loading(path.clone()) {
+        return Err(EvalError::io(format!("Circular load of {}", path.display())));
     }
+
+    let result = run(&program, &path, env).map_err(|err| err.with_file(&path));
+    env.modules().borrow_mut().finish_loading();
+    result
 }
 
-pub fn eval_list(list: &Vec<Object>, env: &mut Env) -> Result<Object, String> {
-    if list.is_empty() {
-        return Ok(Object::Void);
-    }
+fn load_file(path: &Path, env: &mut Env) -> Result<Object, EvalError> {
+    in_file(path, env, load_forms)
+}
 
-    let head = &list[0];
-    let operators = ["+", "-", "*", "/", "<", ">", "=", "!=", "^", ">=", "<=", "<>", "!="];
-    let str_op = ["concat"];
-    match head {
-        Object::Keyword(k) => eval_keyword(k, list, env),
-        Object::Symbol(s) => match s.as_str() {
-            ref oper if operators.contains(oper) => eval_binary_op(list, env),
-            ref op if str_op.contains(op) => eval_string_op(list, env),
-            _ => eval_function_call(s, list, env),
-        },
-        _ => {
-            let mut new_list = Vec::new();
-            for obj in list {
-                let result = eval_obj(obj, env)?;
-                match result {
+/// Evaluate the forms of a loaded file one by one, stopping at the first
+/// error. The result has the value of each form, or the name it defines,
+/// and with `*load-verbose*` the definitions are printed as they are loaded
+fn load_forms(program: &Program, path: &Path, env: &mut Env) -> Result<Object, EvalError> {
+    let forms = match &program.body {
+        Object::List(forms) if is_sequence(forms) => forms.iter().zip(program.forms.iter().copied()).collect(),
+        body => vec![(body, program.span)],
+    };
+
+    let mut results = Vec::new();
+    for (form, span) in forms {
+        let value = expand(form, env)
+            .and_then(|core| eval_obj(&core, env))
+            .map_err(|e| e.with_span(span))?;
+
+        match (value, defined_name(form)) {
+            (Object::Void, Some(name)) => {
+                if env.get(LOAD_VERBOSE) == Some(Object::Bool(true)) {
+                    println!("{}:{}: {}", path.display(), span, name);
+                }
+                results.push(Object::Symbol(name));
+            }
+            (Object::Void, None) => {}
+            (value, _) => results.push(value),
+        }
+    }
+
+    Ok(Object::List(results))
+}
+
+/// Name bound by a top-level definition, as in `(define name ...)`
+fn defined_name(form: &Object) -> Option<String> {
+    let Object::List(items) = form else {
+        return None;
+    };
+
+    match (items.first(), items.get(1)) {
+        (Some(Object::Keyword(k)), Some(Object::Symbol(name)))
+            if matches!(k.as_str(), "define" | "defmacro" | "define-syntax" | "define-record-type" | "module") =>
+        {
+            Some(name.clone())
+        }
+        _ => None,
+    }
+}
+
+fn eval_quote(list: &[Object]) -> Result<Object, EvalError> {
+    if list.len() != 2 {
+        return Err(EvalError::arity("quote"));
+    }
+
+    Ok(list[1].clone())
+}
+
+fn is_quasi_form(items: &[Object], name: &str) -> bool {
+    items.len() == 2 && items[0] == Object::Keyword(name.to_string())
+}
+
+/// Forms of the unquotes of a quasiquote template, `depth` counts the
+/// nested quasiquotes so only the ones of the outer level are evaluated
+fn unquoted(template: &Object, depth: usize, found: &mut Vec<Object>) {
+    let items = match template {
+        Object::List(items) => items,
+        _ => return,
+    };
+
+    if is_quasi_form(items, "unquote") {
+        match depth {
+            1 => found.push(items[1].clone()),
+            _ => unquoted(&items[1], depth - 1, found),
+        }
+        return;
+    }
+
+    if is_quasi_form(items, "quasiquote") {
+        return unquoted(&items[1], depth + 1, found);
+    }
+
+    for item in items {
+        match item {
+            Object::List(inner) if depth == 1 && is_quasi_form(inner, "unquote-splicing") => {
+                found.push(inner[1].clone())
+            }
+            _ => unquoted(item, depth, found),
+        }
+    }
+}
+
+/// Build a quasiquote template with the values of the forms `unquoted`
+/// found, in the same order
+fn quasi_expand(
+    template: &Object,
+    depth: usize,
+    values: &mut IntoIter<Object>,
+    env: &Env,
+) -> Result<Object, EvalError> {
+    let items = match template {
+        Object::List(items) => items,
+        _ => return Ok(template.clone()),
+    };
+
+    if is_quasi_form(items, "unquote") {
+        if depth == 1 {
+            return Ok(values.next().unwrap_or(Object::Void));
+        }
+
+        let inner = quasi_expand(&items[1], depth - 1, values, env)?;
+        return Ok(Object::List(vec![items[0].clone(), inner]));
+    }
+
+    if is_quasi_form(items, "quasiquote") {
+        let inner = quasi_expand(&items[1], depth + 1, values, env)?;
+        return Ok(Object::List(vec![items[0].clone(), inner]));
+    }
+
+    let mut expanded = Vec::new();
+    for item in items {
+        if let Object::List(inner) = item {
+            if depth == 1 && is_quasi_form(inner, "unquote-splicing") {
+                match values.next().unwrap_or(Object::Void) {
+                    Object::List(spliced) => expanded.extend(spliced),
                     Object::Void => {}
-                    _ => new_list.push(result),
+                    other => return Err(EvalError::type_mismatch("a list for unquote-splicing", &other)),
                 }
+                continue;
             }
-            Ok(Object::List(new_list))
         }
+
+        expanded.push(quasi_expand(item, depth, values, env)?);
     }
+
+    env.budget().allocate(expanded.len() * std::mem::size_of::<Object>())?;
+    Ok(Object::List(expanded))
 }
 
-fn eval_obj(obj: &Object, env: &mut Env) -> Result<Object, String> {
+/// Macros can not take the name of a keyword, the special forms
+/// are evaluated before the macro table is consulted
+pub fn macro_name(obj: &Object) -> Result<String, EvalError> {
     match obj {
-        Object::List(list) => eval_list(list, env),
-        Object::Void => Ok(Object::Void),
-        Object::Keyword(k) => Ok(Object::Keyword(k.clone())),
-        Object::Lambda(_params, _body) => Ok(Object::Void),
-        Object::Bool(_) => Ok(obj.clone()),
-        Object::Integer(n) => Ok(Object::Integer(*n)),
-        Object::Float(f) => Ok(Object::Float(*f)),
-        Object::Str(s) => Ok(Object::Str(s.clone())),
-        Object::Symbol(s) => eval_symbol(s, env),
+        Object::Symbol(s) if KEYWORDS.contains(&s.as_str()) => {
+            Err(EvalError::syntax(format!("Cannot define the keyword `{}`", s)))
+        }
+        Object::Symbol(s) => Ok(s.clone()),
+        Object::Keyword(k) => Err(EvalError::syntax(format!("Cannot define the keyword `{}`", k))),
+        _ => Err(EvalError::syntax("Invalid macro name")),
+    }
+}
+
+pub fn eval_defmacro(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() != 4 {
+        return Err(EvalError::arity("defmacro"));
+    }
+
+    let name = macro_name(&list[1])?;
+
+    let params = match &list[2] {
+        Object::List(list) => {
+            let mut params = Vec::new();
+            for param in list {
+                match param {
+                    Object::Symbol(s) => params.push(s.clone()),
+                    _ => return Err(EvalError::syntax("Invalid macro parameter")),
+                }
+            }
+            params
+        }
+        _ => return Err(EvalError::syntax("Invalid macro parameters")),
+    };
+
+    if let Some(pos) = params.iter().position(|p| p == "&rest") {
+        if pos + 2 != params.len() {
+            return Err(EvalError::syntax("&rest must be followed by exactly one parameter"));
+        }
+    }
+
+    let body = match &list[3] {
+        Object::List(list) => list.clone(),
+        _ => return Err(EvalError::syntax("Invalid macro body")),
+    };
+
+    env.set_macro(&name, Object::Macro(params, body));
+    Ok(Object::Void)
+}
+
+/// Bind the unevaluated argument forms of a macro call,
+/// a `&rest` parameter collects the remaining forms in a list
+fn bind_macro_args(name: &str, params: &[String], args: &[Object], env: &mut Env) -> Result<(), EvalError> {
+    for (i, param) in params.iter().enumerate() {
+        if param == "&rest" {
+            let rest = args.get(i..).unwrap_or(&[]).to_vec();
+            env.set(&params[i + 1], Object::List(rest));
+            return Ok(());
+        }
+
+        match args.get(i) {
+            Some(arg) => env.set(param, arg.clone()),
+            None => return Err(EvalError::arity(name)),
+        }
+    }
+
+    if args.len() > params.len() {
+        return Err(EvalError::arity(name));
+    }
+
+    Ok(())
+}
+
+/// Expand `form` once if its head names a macro, `None` means
+/// the form is not a macro call
+pub fn macroexpand_1(form: &Object, env: &mut Env) -> Result<Option<Object>, EvalError> {
+    let list = match form {
+        Object::List(list) => list,
+        _ => return Ok(None),
+    };
+
+    let name = match list.first() {
+        Some(Object::Symbol(s)) => s,
+        _ => return Ok(None),
+    };
+
+    match env.get_macro(name) {
+        Some(Object::Macro(params, body)) => {
+            let mut macro_env = env.clone();
+            bind_macro_args(name, &params, &list[1..], &mut macro_env)?;
+            eval_obj(&Object::List(body), &mut macro_env).map(Some)
+        }
+        Some(Object::SyntaxRules(literals, rules)) => syntax::expand(list, &literals, &rules).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn macroexpand(form: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    let mut form = form.clone();
+
+    while let Some(expanded) = macroexpand_1(&form, env)? {
+        form = expanded;
+    }
+
+    Ok(form)
+}
+
+fn eval_macroexpand(list: &[Object], env: &mut Env, once: bool) -> Result<Object, EvalError> {
+    if list.len() != 2 {
+        return Err(EvalError::arity("macroexpand"));
+    }
+
+    let form = eval_obj(&list[1], env)?;
+
+    if once {
+        return Ok(macroexpand_1(&form, env)?.unwrap_or(form));
+    }
+
+    macroexpand(&form, env)
+}
+
+fn eval_syntax_rules(list: &[Object]) -> Result<Object, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::arity("syntax-rules"));
+    }
+
+    let literals = match &list[1] {
+        Object::List(items) => {
+            let mut literals = Vec::new();
+            for item in items {
+                match item {
+                    Object::Symbol(s) => literals.push(s.clone()),
+                    _ => return Err(EvalError::syntax(format!("Invalid syntax-rules literal: {}", item))),
+                }
+            }
+            literals
+        }
+        _ => return Err(EvalError::syntax("syntax-rules literals must be a list")),
+    };
+
+    let rules = list[2..].to_vec();
+    for rule in &rules {
+        syntax::validate_rule(rule)?;
+    }
+
+    Ok(Object::SyntaxRules(literals, rules))
+}
+
+pub fn eval_transformer(obj: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    match eval_obj(obj, env)? {
+        Object::SyntaxRules(literals, rules) => Ok(Object::SyntaxRules(literals, rules)),
+        other => Err(EvalError::type_mismatch("a syntax-rules transformer", &other)),
+    }
+}
+
+pub fn eval_define_syntax(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() != 3 {
+        return Err(EvalError::arity("define-syntax"));
+    }
+
+    let name = macro_name(&list[1])?;
+    let transformer = eval_transformer(&list[2], env)?;
+    env.set_macro(&name, transformer);
+    Ok(Object::Void)
+}
+
+fn eval_args(list: &[Object], env: &mut Env) -> Result<Vec<Object>, EvalError> {
+    let mut args = Vec::new();
+
+    for obj in &list[1..] {
+        args.push(eval_obj(obj, env)?);
+    }
+
+    Ok(args)
+}
+
+fn raise(obj: Object) -> EvalError {
+    EvalError::new(ErrorKind::User(Box::new(obj)))
+}
+
+fn eval_condition_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let name = match &list[0] {
+        Object::Symbol(s) => s.as_str(),
+        other => return Err(EvalError::type_mismatch("a symbol", other)),
+    };
+    let args = eval_args(list, env)?;
+
+    match (name, args.as_slice()) {
+        ("error", [Object::Str(message), irritants @ ..]) => Err(raise(Object::Condition(Box::new(Condition {
+            kind: "error".to_string(),
+            message: message.clone(),
+            irritants: irritants.to_vec(),
+        })))),
+        ("error", [other, ..]) => Err(EvalError::type_mismatch("a string", other)),
+        ("raise", [obj]) => Err(raise(obj.clone())),
+        ("condition?", [obj]) => Ok(Object::Bool(matches!(obj, Object::Condition(_)))),
+        ("condition-kind", [Object::Condition(c)]) => Ok(Object::Symbol(c.kind.clone())),
+        ("condition-message", [Object::Condition(c)]) => Ok(Object::Str(c.message.clone())),
+        ("condition-irritants", [Object::Condition(c)]) => Ok(Object::List(c.irritants.clone())),
+        ("condition-kind" | "condition-message" | "condition-irritants", [other]) => {
+            Err(EvalError::type_mismatch("a condition", other))
+        }
+        ("make-condition", [Object::Symbol(kind), Object::Str(message), irritants @ ..]) => {
+            Ok(Object::Condition(Box::new(Condition {
+                kind: kind.clone(),
+                message: message.clone(),
+                irritants: irritants.to_vec(),
+            })))
+        }
+        ("make-condition", [Object::Symbol(_), other, ..]) => Err(EvalError::type_mismatch("a string", other)),
+        ("make-condition", [other, ..]) => Err(EvalError::type_mismatch("a symbol", other)),
+        ("signal", [obj]) => {
+            signal(obj, false, env)?;
+            Ok(Object::Void)
+        }
+        ("invoke-restart", [Object::Symbol(name), args @ ..]) => match condition::find_restart(name) {
+            Some(restart) => Err(EvalError::restart(restart.id, name, args.to_vec())),
+            None => Err(EvalError::new(ErrorKind::Control(format!("No active restart named {}", name)))),
+        },
+        ("invoke-restart", [other, ..]) => Err(EvalError::type_mismatch("a symbol", other)),
+        ("compute-restarts", []) => Ok(Object::List(
+            condition::restarts().into_iter().map(|r| Object::Symbol(r.name)).collect(),
+        )),
+        _ => Err(EvalError::arity(name)),
+    }
+}
+
+/// Variable and clauses of `(guard (var clause...) body...)`
+fn guard_spec(list: &[Object]) -> Result<(String, Vec<Object>), EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::arity("guard"));
+    }
+
+    match &list[1] {
+        Object::List(spec) => match spec.first() {
+            Some(Object::Symbol(var)) => Ok((var.clone(), spec[1..].to_vec())),
+            _ => Err(EvalError::syntax("guard needs a variable for the condition")),
+        },
+        _ => Err(EvalError::syntax("Invalid guard")),
+    }
+}
+
+/// Whether a `guard` catches an error, restarts, jumps to continuations
+/// and exceeded limits go through it
+fn is_catchable(error: &EvalError) -> bool {
+    !matches!(
+        error.kind,
+        ErrorKind::Restart { .. } | ErrorKind::Continuation { .. } | ErrorKind::ResourceLimit(_)
+    )
+}
+
+/// Handle the error of the body of a `guard`: bind the condition to
+/// `var` and run the first clause whose test is true, the error is
+/// raised again when no clause matches
+fn guard_clauses(var: &str, clauses: &[Object], mut error: EvalError, env: &mut Env) -> Result<Object, EvalError> {
+    let mut handler_env = new_scope(env)?;
+    handler_env.set(var, error.to_condition());
+
+    for clause in clauses {
+        let items = match clause {
+            Object::List(items) if !items.is_empty() => items,
+            _ => return Err(EvalError::syntax(format!("Invalid guard clause: {}", clause))),
+        };
+
+        if items[0] == Object::Symbol("else".to_string()) {
+            return eval_begin(items, &mut handler_env);
+        }
+
+        match eval_obj(&items[0], &mut handler_env)? {
+            Object::Bool(false) => continue,
+            Object::Bool(true) if items.len() == 1 => return Ok(Object::Bool(true)),
+            Object::Bool(true) => return eval_begin(items, &mut handler_env),
+            other => return Err(EvalError::type_mismatch("a boolean", &other)),
+        }
+    }
+
+    // The handlers outside of the guard have not seen it yet
+    error.signaled = false;
+    Err(error)
+}
+
+fn handler_applies(kind: &str, condition: &Object, is_error: bool) -> bool {
+    match kind {
+        "condition" => true,
+        "error" => is_error,
+        _ => matches!(condition, Object::Condition(c) if c.kind == kind),
+    }
+}
+
+/// Run the handlers of `handler-bind` for `condition`, innermost first
+///
+/// A handler declines by returning and handles the condition by leaving
+/// with a restart or an error. Errors nobody handles go to the debugger
+fn signal(condition: &Object, is_error: bool, env: &mut Env) -> Result<(), EvalError> {
+    for index in (0..condition::depth()).rev() {
+        let handlers = match condition::frame(index) {
+            condition::Frame::Guard => return Ok(()),
+            condition::Frame::Handlers(handlers) => handlers,
+        };
+
+        for (kind, handler) in handlers {
+            if !handler_applies(&kind, condition, is_error) {
+                continue;
+            }
+
+            let hidden = condition::hide_frames(index);
+            let result = call_procedure("handler", &handler, vec![condition.clone()], env);
+            condition::restore_frames(hidden);
+            result?;
+        }
+    }
+
+    if !is_error {
+        return Ok(());
+    }
+
+    match condition::debugger().and_then(|debugger| debugger(condition, &condition::restarts(), env)) {
+        Some((restart, args)) => Err(EvalError::restart(restart.id, &restart.name, args)),
+        None => Ok(()),
+    }
+}
+
+/// Signal an error where it happens, before the stack unwinds, with a
+/// `use-value` restart that makes the failing form return a value
+fn signal_error(mut error: EvalError, env: &mut Env) -> Result<Object, EvalError> {
+    error.signaled = true;
+
+    let id = condition::push_restart("use-value", vec!["value".to_string()]);
+    let result = signal(&error.to_condition(), true, env);
+    condition::pop_restarts(1);
+
+    match result {
+        Ok(()) => Err(error),
+        Err(EvalError {
+            kind: ErrorKind::Restart { id: target, args, .. },
+            ..
+        }) if target == id => Ok(args.into_iter().next().unwrap_or(Object::Void)),
+        Err(other) => Err(other),
+    }
+}
+
+/// `(handler-bind ((kind handler)...) body...)` runs the body with the
+/// handlers active, `condition` matches everything and `error` every error
+fn eval_handler_bind(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::arity("handler-bind"));
+    }
+
+    let bindings = match &list[1] {
+        Object::List(bindings) => bindings,
+        _ => return Err(EvalError::syntax("handler-bind bindings must be a list")),
+    };
+
+    let mut handlers = Vec::new();
+    for binding in bindings {
+        match binding {
+            Object::List(pair) => match pair.as_slice() {
+                [Object::Symbol(kind), handler] => handlers.push((kind.clone(), eval_obj(handler, env)?)),
+                _ => return Err(EvalError::syntax(format!("Invalid handler-bind binding: {}", binding))),
+            },
+            _ => return Err(EvalError::syntax(format!("Invalid handler-bind binding: {}", binding))),
+        }
+    }
+
+    condition::push_frame(condition::Frame::Handlers(handlers));
+    let result = eval_begin(&list[1..], env);
+    condition::pop_frame();
+
+    result
+}
+
+/// `(restart-case form (name (params...) body...)...)` evaluates the form,
+/// `invoke-restart` leaves it and returns the value of the restart body
+fn eval_restart_case(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::arity("restart-case"));
+    }
+
+    let mut clauses = Vec::new();
+    for clause in &list[2..] {
+        let items = match clause {
+            Object::List(items) if items.len() == 3 => items,
+            _ => return Err(EvalError::syntax(format!("Invalid restart-case clause: {}", clause))),
+        };
+
+        let name = match &items[0] {
+            Object::Symbol(name) => name.clone(),
+            other => return Err(EvalError::type_mismatch("a symbol", other)),
+        };
+
+        let mut params = Vec::new();
+        match &items[1] {
+            Object::List(list) => {
+                for param in list {
+                    match param {
+                        Object::Symbol(s) => params.push(s.clone()),
+                        other => return Err(EvalError::type_mismatch("a symbol", other)),
+                    }
+                }
+            }
+            Object::Void => {}
+            other => return Err(EvalError::type_mismatch("a parameter list", other)),
+        }
+
+        clauses.push((name, params, &items[2]));
+    }
+
+    // The first clause is the innermost restart
+    let ids = clauses
+        .iter()
+        .rev()
+        .map(|(name, params, _)| condition::push_restart(name, params.clone()))
+        .collect::<Vec<_>>();
+    let result = eval_obj(&list[1], env);
+    condition::pop_restarts(ids.len());
+
+    let (target, args) = match result {
+        Err(EvalError {
+            kind: ErrorKind::Restart { id, args, .. },
+            ..
+        }) if ids.contains(&id) => (id, args),
+        result => return result,
+    };
+
+    let index = clauses.len() - 1 - ids.iter().position(|id| *id == target).unwrap_or_default();
+    let (name, params, body) = &clauses[index];
+    if args.len() < params.len() {
+        return Err(EvalError::arity(name));
+    }
+
+    let mut restart_env = new_scope(env)?;
+    for (param, val) in params.iter().zip(args) {
+        restart_env.set(param, val);
+    }
+    eval_obj(body, &mut restart_env)
+}
+
+fn symbol_list(obj: &Object, what: &str) -> Result<Vec<String>, EvalError> {
+    match obj {
+        Object::Symbol(s) => Ok(vec![s.clone()]),
+        Object::List(items) => items
+            .iter()
+            .map(|item| match item {
+                Object::Symbol(s) => Ok(s.clone()),
+                other => Err(EvalError::type_mismatch(what, other)),
+            })
+            .collect(),
+        Object::Void => Ok(Vec::new()),
+        other => Err(EvalError::type_mismatch(what, other)),
+    }
+}
+
+fn is_import(form: &Object) -> bool {
+    matches!(form, Object::List(items) if items.first() == Some(&Object::Keyword("import".to_string())))
+}
+
+/// `(module name (export sym...) body...)` evaluates the body once, in
+/// its own scope, and registers the module for `import`
+fn eval_module(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::arity("module"));
+    }
+
+    let name = match &list[1] {
+        Object::Symbol(name) => name.clone(),
+        other => return Err(EvalError::type_mismatch("a module name", other)),
+    };
+
+    let exports = match &list[2] {
+        Object::List(items) if items.first() == Some(&Object::Symbol("export".to_string())) => {
+            symbol_list(&Object::List(items[1..].to_vec()), "an exported symbol")?
+        }
+        _ => return Err(EvalError::syntax(format!("Module {} needs an (export ...) clause", name))),
+    };
+
+    let mut scope = env.module_scope();
+    let mut renames = HashMap::new();
+    let mut bindings = Vec::new();
+    let mut forms = Vec::new();
+
+    for form in &list[3..] {
+        // Imported names refer to the global names of the other modules
+        if let Object::List(items) = form {
+            if is_import(form) {
+                for (module, names) in resolve_imports(&items[1..], &mut scope)? {
+                    bind_module(&module, &mut scope);
+                    bindings.extend(module.bindings.iter().cloned());
+                    for (local, export) in names {
+                        renames.insert(local, module.qualified(&export));
+                    }
+                }
+                continue;
+            }
+        }
+
+        forms.push(expand(form, &mut scope)?);
+    }
+
+    let mut defined = Vec::new();
+    module::definitions(&forms, &mut defined);
+
+    for export in &exports {
+        if !defined.contains(export) && !renames.contains_key(export) {
+            return Err(EvalError::syntax(format!("Module {} exports {} but does not define it", name, export)));
+        }
+    }
+
+    for def in &defined {
+        let global = match exports.contains(def) {
+            true => module::qualified(&name, def),
+            false => module::private(&name, def),
+        };
+        renames.insert(def.clone(), global);
+    }
+
+    for form in &forms {
+        eval_obj(&module::rename(form, &renames, &HashSet::new()), &mut scope)?;
+    }
+
+    for def in &defined {
+        let global = &renames[def];
+        if let Some(value) = scope.get(global) {
+            bindings.push((global.clone(), value));
+        }
+    }
+
+    // Types keep their names, `make-instance` and methods refer to them
+    for type_name in module::type_names(&forms) {
+        if let Some(value @ Object::RecordType(_)) = scope.get(&type_name) {
+            bindings.push((type_name, value));
+        }
+    }
+
+    // Exported imports are bound again under the name of this module
+    for export in exports.iter().filter(|e| !defined.contains(e)) {
+        if let Some(value) = scope.get(&renames[export]) {
+            bindings.push((module::qualified(&name, export), value));
+        }
     }
+
+    env.modules().borrow_mut().insert(Module { name, exports, bindings });
+
+    Ok(Object::Void)
 }
 
-pub fn eval(program: &str, env: &mut Env) -> Result<Object, String> {
-    let parsed_list = parse(program);
-    if parsed_list.is_err() {
-        return Err(format!("{}", parsed_list.err().unwrap()));
+/// `(import spec...)` binds the exported names of modules, a spec is a
+/// module name, the path of the file defining it, `(only spec sym...)`, `(except spec sym...)`,
+/// `(rename spec (from to)...)` or `(prefix-in prefix spec)`
+fn eval_import(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    for (module, names) in resolve_imports(&list[1..], env)? {
+        bind_module(&module, env);
+        for (local, export) in names {
+            if let Some(value) = env.get(&module.qualified(&export)) {
+                env.set(&local, value);
+            }
+        }
+    }
+
+    Ok(Object::Void)
+}
+
+/// Bind every global name of the module, its values may refer to any of them
+fn bind_module(module: &Module, env: &mut Env) {
+    for (name, value) in &module.bindings {
+        env.set(name, value.clone());
+    }
+}
+
+type ImportNames = Vec<(String, String)>;
+
+fn resolve_imports(specs: &[Object], env: &mut Env) -> Result<Vec<(Rc<Module>, ImportNames)>, EvalError> {
+    if specs.is_empty() {
+        return Err(EvalError::arity("import"));
+    }
+
+    specs.iter().map(|spec| resolve_import(spec, env)).collect()
+}
+
+/// The module of an import spec and its `(local, exported)` names
+fn resolve_import(spec: &Object, env: &mut Env) -> Result<(Rc<Module>, ImportNames), EvalError> {
+    let items = match spec {
+        Object::Symbol(name) => {
+            let module = find_module(name, env)?;
+            let names = module.exports.iter().map(|e| (e.clone(), e.clone())).collect();
+            return Ok((module, names));
+        }
+        Object::Str(file) => {
+            // The module is named after the file
+            let path = load::resolve(file, env)?;
+            let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_string();
+            let module = load_module(&name, &path, env)?;
+            let names = module.exports.iter().map(|e| (e.clone(), e.clone())).collect();
+            return Ok((module, names));
+        }
+        Object::List(items) if items.len() >= 2 => items,
+        other => return Err(EvalError::syntax(format!("Invalid import spec: {}", other))),
+    };
+
+    let op = match &items[0] {
+        Object::Symbol(op) => op.as_str(),
+        other => return Err(EvalError::syntax(format!("Invalid import spec: {}", other))),
+    };
+
+    if op == "prefix-in" {
+        let prefix = match (&items[1], items.get(2)) {
+            (Object::Symbol(prefix), Some(_)) if items.len() == 3 => prefix,
+            _ => return Err(EvalError::syntax(format!("Invalid import spec: {}", spec))),
+        };
+        let (module, names) = resolve_import(&items[2], env)?;
+        let names = names.into_iter().map(|(local, e)| (format!("{}{}", prefix, local), e)).collect();
+        return Ok((module, names));
+    }
+
+    let (module, names) = resolve_import(&items[1], env)?;
+    let check = |local: &str| match names.iter().any(|(l, _)| l == local) {
+        true => Ok(()),
+        false => Err(EvalError::syntax(format!("{} is not exported by {}", local, module.name))),
+    };
+
+    match op {
+        "only" | "except" => {
+            let ids = symbol_list(&Object::List(items[2..].to_vec()), "a symbol")?;
+            for id in &ids {
+                check(id)?;
+            }
+            let keep = op == "only";
+            let names = names.iter().filter(|(l, _)| ids.contains(l) == keep).cloned().collect();
+            Ok((module, names))
+        }
+        "rename" => {
+            let mut renamed = names.clone();
+            for pair in &items[2..] {
+                match symbol_list(pair, "a symbol")?.as_slice() {
+                    [from, to] => {
+                        check(from)?;
+                        for (local, _) in renamed.iter_mut().filter(|(l, _)| l == from) {
+                            *local = to.clone();
+                        }
+                    }
+                    _ => return Err(EvalError::syntax(format!("Invalid rename: {}", pair))),
+                }
+            }
+            Ok((module, renamed))
+        }
+        _ => Err(EvalError::syntax(format!("Invalid import spec: {}", spec))),
+    }
+}
+
+/// A module already evaluated or the one defined by the file `name.lisp`
+fn find_module(name: &str, env: &mut Env) -> Result<Rc<Module>, EvalError> {
+    if let Some(module) = env.modules().borrow().get(name) {
+        return Ok(module);
+    }
+
+    let file = load::resolve(name, env)?;
+    load_module(name, &file, env)
+}
+
+/// The module defined by `file`, the file is evaluated only the first time
+/// and its top level does not leak into `env`
+fn load_module(name: &str, file: &Path, env: &mut Env) -> Result<Rc<Module>, EvalError> {
+    env.sandbox().check_load(file)?;
+
+    let path = file
+        .canonicalize()
+        .map_err(|_| EvalError::io(format!("Module {} not found", name)))?;
+
+    if let Some(module) = env.modules().borrow().from_file(&path) {
+        return Ok(module);
+    }
+
+    load_file(&path, &mut env.module_scope())?;
+
+    let module = env.modules().borrow().get(name);
+    match module {
+        Some(module) => {
+            env.modules().borrow_mut().set_file(path, name);
+            Ok(module)
+        }
+        None => Err(EvalError::io(format!("{} does not define the module {}", path.display(), name))),
+    }
+}
+
+fn symbol_name(obj: &Object, what: &str) -> Result<String, EvalError> {
+    match obj {
+        Object::Symbol(s) => Ok(s.clone()),
+        other => Err(EvalError::type_mismatch(what, other)),
+    }
+}
+
+/// `(define-record-type name (constructor field...) predicate (field accessor [modifier])...)`
+/// binds the type descriptor, the constructor, the predicate and the
+/// accessors and modifiers of the fields
+fn eval_define_record_type(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() < 4 {
+        return Err(EvalError::arity("define-record-type"));
+    }
+
+    let name = symbol_name(&list[1], "a record type name")?;
+    let constructor = match &list[2] {
+        Object::List(items) if !items.is_empty() => symbol_list(&list[2], "a constructor spec")?,
+        other => return Err(EvalError::type_mismatch("a constructor spec", other)),
+    };
+    let predicate = symbol_name(&list[3], "a predicate name")?;
+
+    let mut fields = Vec::new();
+    let mut procs = Vec::new();
+    for spec in &list[4..] {
+        let (field, accessor, modifier) = match symbol_list(spec, "a field spec")?.as_slice() {
+            [field, accessor] => (field.clone(), accessor.clone(), None),
+            [field, accessor, modifier] => (field.clone(), accessor.clone(), Some(modifier.clone())),
+            _ => return Err(EvalError::syntax(format!("Invalid field spec: {}", spec))),
+        };
+
+        if fields.contains(&field) {
+            return Err(EvalError::syntax(format!("Duplicate field {} in {}", field, name)));
+        }
+
+        procs.push((accessor, RecordOp::Accessor(fields.len())));
+        if let Some(modifier) = modifier {
+            procs.push((modifier, RecordOp::Modifier(fields.len())));
+        }
+        fields.push(field);
+    }
+
+    let mut indices = Vec::new();
+    for arg in &constructor[1..] {
+        match fields.iter().position(|field| field == arg) {
+            Some(index) => indices.push(index),
+            None => return Err(EvalError::syntax(format!("{} is not a field of {}", arg, name))),
+        }
+    }
+    procs.push((constructor[0].clone(), RecordOp::Constructor(indices)));
+    procs.push((predicate, RecordOp::Predicate));
+
+    let rtype = Rc::new(RecordType {
+        name: name.clone(),
+        initforms: vec![None; fields.len()],
+        fields,
+        supers: Vec::new(),
+    });
+    env.set(&name, Object::RecordType(rtype.clone()));
+
+    for (proc_name, op) in procs {
+        let proc = RecordProc {
+            name: proc_name.clone(),
+            rtype: rtype.clone(),
+            op,
+        };
+        env.set(&proc_name, Object::RecordProc(Rc::new(proc)));
+    }
+
+    Ok(Object::Void)
+}
+
+fn call_record_proc(proc: &RecordProc, values: Vec<Object>, env: &mut Env) -> Result<Object, EvalError> {
+    let arity = match &proc.op {
+        RecordOp::Constructor(indices) => indices.len(),
+        RecordOp::Predicate | RecordOp::Accessor(_) => 1,
+        RecordOp::Modifier(_) => 2,
+    };
+
+    if values.len() != arity {
+        return Err(EvalError::arity(&proc.name));
+    }
+
+    let fields = match (&proc.op, &values.first()) {
+        (RecordOp::Constructor(_), _) => None,
+        (_, Some(Object::Record(r))) if Rc::ptr_eq(&r.rtype, &proc.rtype) => Some(r.fields.clone()),
+        (RecordOp::Predicate, _) => return Ok(Object::Bool(false)),
+        (_, value) => {
+            let expected = format!("a {} record", proc.rtype.short_name());
+            return Err(EvalError::type_mismatch(&expected, value.unwrap_or(&Object::Void)));
+        }
+    };
+
+    match (&proc.op, fields) {
+        (RecordOp::Constructor(indices), _) => {
+            // Fields the constructor does not take start as false
+            let mut fields = vec![Object::Bool(false); proc.rtype.fields.len()];
+            for (index, value) in indices.iter().zip(values) {
+                fields[*index] = value;
+            }
+
+            let record = Object::Record(Record {
+                rtype: proc.rtype.clone(),
+                fields: Rc::new(RefCell::new(fields)),
+            });
+            env.budget().allocate(record.size())?;
+            Ok(record)
+        }
+        (RecordOp::Predicate, _) => Ok(Object::Bool(true)),
+        (RecordOp::Accessor(index), Some(fields)) => Ok(fields.borrow()[*index].clone()),
+        (RecordOp::Modifier(index), Some(fields)) => {
+            fields.borrow_mut()[*index] = values[1].clone();
+            Ok(Object::Void)
+        }
+        _ => Err(EvalError::arity(&proc.name)),
+    }
+}
+
+/// `(defclass name (super...) (slot...))` defines a class, a slot is a
+/// name or `(name option value...)` with the options `:initform` and
+/// `:accessor`. Slots are inherited and accessors are generic functions
+fn eval_defclass(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() != 4 {
+        return Err(EvalError::arity("defclass"));
+    }
+
+    let name = symbol_name(&list[1], "a class name")?;
+
+    let mut supers = Vec::new();
+    for sup in symbol_list(&list[2], "a class name")? {
+        match env.get(&sup) {
+            Some(Object::RecordType(class)) => supers.push(class),
+            _ => return Err(EvalError::syntax(format!("Unknown class: {}", sup))),
+        }
+    }
+
+    let mut fields: Vec<String> = Vec::new();
+    let mut initforms = Vec::new();
+    for sup in &supers {
+        for (field, initform) in sup.fields.iter().zip(&sup.initforms) {
+            if !fields.contains(field) {
+                fields.push(field.clone());
+                initforms.push(initform.clone());
+            }
+        }
+    }
+
+    let slots = match &list[3] {
+        Object::List(slots) => slots.clone(),
+        Object::Void => Vec::new(),
+        other => return Err(EvalError::type_mismatch("a list of slots", other)),
+    };
+
+    let mut accessors = Vec::new();
+    for slot in &slots {
+        let (field, options) = match slot {
+            Object::Symbol(field) => (field.clone(), &[][..]),
+            Object::List(items) if items.len() % 2 == 1 => (symbol_name(&items[0], "a slot name")?, &items[1..]),
+            other => return Err(EvalError::syntax(format!("Invalid slot: {}", other))),
+        };
+
+        let mut initform = None;
+        for option in options.chunks(2) {
+            match (&option[0], &option[1]) {
+                (Object::Symbol(o), form) if o == ":initform" => initform = Some(expand(form, env)?),
+                (Object::Symbol(o), Object::Symbol(accessor)) if o == ":accessor" => {
+                    accessors.push((accessor.clone(), field.clone()));
+                }
+                (other, _) => return Err(EvalError::syntax(format!("Invalid slot option: {}", other))),
+            }
+        }
+
+        match fields.iter().position(|f| *f == field) {
+            // A redefined slot keeps the inherited initform unless it has one
+            Some(index) => {
+                if initform.is_some() {
+                    initforms[index] = initform;
+                }
+            }
+            None => {
+                fields.push(field);
+                initforms.push(initform);
+            }
+        }
+    }
+
+    let class = Rc::new(RecordType {
+        name: name.clone(),
+        fields,
+        supers,
+        initforms,
+    });
+    env.set(&name, Object::RecordType(class.clone()));
+
+    for (accessor, field) in accessors {
+        let generic = ensure_generic(&accessor, 1, env)?;
+        generic.add_method(Method {
+            qualifier: Qualifier::Primary,
+            specializers: vec![Specializer::Type(class.clone())],
+            params: vec!["object".to_string()],
+            body: Object::List(vec![
+                Object::Symbol("slot-value".to_string()),
+                Object::Symbol("object".to_string()),
+                Object::List(vec![Object::Keyword("quote".to_string()), Object::Symbol(field)]),
+            ]),
+        });
+    }
+
+    Ok(Object::Void)
+}
+
+/// The generic function bound to `name`, a new one when it is not bound to one
+fn ensure_generic(name: &str, arity: usize, env: &mut Env) -> Result<Rc<Generic>, EvalError> {
+    match env.get(name) {
+        Some(Object::Generic(generic)) if generic.arity == arity => Ok(generic),
+        Some(Object::Generic(generic)) => Err(EvalError::syntax(format!(
+            "The methods of {} take {} arguments",
+            name, generic.arity
+        ))),
+        _ => {
+            let generic = Rc::new(Generic::new(name, arity));
+            env.set(name, Object::Generic(generic.clone()));
+            Ok(generic)
+        }
+    }
+}
+
+/// `(defgeneric name (params...))` defines a generic function without methods,
+/// the methods of a generic with the same parameters are kept
+fn eval_defgeneric(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() != 3 {
+        return Err(EvalError::arity("defgeneric"));
+    }
+
+    let name = symbol_name(&list[1], "a generic function name")?;
+    let arity = symbol_list(&list[2], "a parameter")?.len();
+
+    if !matches!(env.get(&name), Some(Object::Generic(g)) if g.arity == arity) {
+        env.set(&name, Object::Generic(Rc::new(Generic::new(&name, arity))));
+    }
+
+    Ok(Object::Void)
+}
+
+/// `(defmethod name [:before | :after] (param | (param type)...) body...)`
+/// adds a method to a generic function, creating it if needed
+fn eval_defmethod(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() < 4 {
+        return Err(EvalError::arity("defmethod"));
+    }
+
+    let name = symbol_name(&list[1], "a generic function name")?;
+
+    let (qualifier, rest) = match &list[2] {
+        Object::Symbol(q) => match Qualifier::from_name(q) {
+            Some(qualifier) => (qualifier, &list[3..]),
+            None => return Err(EvalError::syntax(format!("Unsupported method qualifier: {}", q))),
+        },
+        _ => (Qualifier::Primary, &list[2..]),
+    };
+
+    if rest.len() < 2 {
+        return Err(EvalError::arity("defmethod"));
+    }
+
+    let specs = match &rest[0] {
+        Object::List(specs) => specs.clone(),
+        Object::Void => Vec::new(),
+        other => return Err(EvalError::type_mismatch("a parameter list", other)),
+    };
+
+    let mut params = Vec::new();
+    let mut specializers = Vec::new();
+    for spec in &specs {
+        let (param, specializer) = match spec {
+            Object::Symbol(param) => (param.clone(), Specializer::Any),
+            Object::List(items) => match items.as_slice() {
+                [Object::Symbol(param), Object::Symbol(type_name)] => (param.clone(), specializer(type_name, env)?),
+                _ => return Err(EvalError::syntax(format!("Invalid method parameter: {}", spec))),
+            },
+            other => return Err(EvalError::syntax(format!("Invalid method parameter: {}", other))),
+        };
+        params.push(param);
+        specializers.push(specializer);
+    }
+
+    let generic = ensure_generic(&name, params.len(), env)?;
+    generic.add_method(Method {
+        qualifier,
+        specializers,
+        params,
+        body: lambda_body(&rest[1..]),
+    });
+
+    Ok(Object::Void)
+}
+
+/// A class or record type bound to `name`, or one of the types of `get_type`
+fn specializer(name: &str, env: &Env) -> Result<Specializer, EvalError> {
+    match env.get(name) {
+        Some(Object::RecordType(class)) => Ok(Specializer::Type(class)),
+        _ if TYPE_NAMES.contains(&name) => Ok(Specializer::Builtin(name.to_string())),
+        _ => Err(EvalError::syntax(format!("Unknown type: {}", name))),
+    }
+}
+
+fn no_method(msg: String) -> EvalError {
+    EvalError::new(ErrorKind::NoMethod(msg))
+}
+
+/// Run the `:before` methods, the most specific primary method and the
+/// `:after` methods that apply, returning the value of the primary one
+fn call_generic(generic: &Generic, values: Vec<Object>, env: &mut Env) -> Result<Object, EvalError> {
+    if values.len() != generic.arity {
+        return Err(EvalError::arity(&generic.name));
+    }
+
+    let methods = generic.applicable(&values);
+    let of_kind = |qualifier| methods.iter().filter(|m| m.qualifier == qualifier).cloned().collect::<Vec<_>>();
+    let primaries = of_kind(Qualifier::Primary);
+
+    if primaries.is_empty() {
+        let args = values.iter().map(Object::repr).collect::<Vec<_>>();
+        return Err(no_method(format!(
+            "No applicable method for {} with {}",
+            generic.name,
+            args.join(" ")
+        )));
+    }
+
+    for before in of_kind(Qualifier::Before) {
+        call_method(&generic.name, &before, values.clone(), Vec::new(), env)?;
+    }
+
+    let result = call_method(&generic.name, &primaries[0], values.clone(), primaries[1..].to_vec(), env)?;
+
+    for after in of_kind(Qualifier::After).iter().rev() {
+        call_method(&generic.name, after, values.clone(), Vec::new(), env)?;
+    }
+
+    Ok(result)
+}
+
+/// Run a method, a primary one can use `call-next-method`
+fn call_method(
+    name: &str,
+    method: &Method,
+    values: Vec<Object>,
+    next: Vec<Rc<Method>>,
+    env: &mut Env,
+) -> Result<Object, EvalError> {
+    let mut scope = new_scope(env)?;
+
+    if method.qualifier == Qualifier::Primary {
+        let next = NextMethod {
+            name: name.to_string(),
+            methods: next,
+            args: values.clone(),
+        };
+        scope.set("call-next-method", Object::NextMethod(Rc::new(next)));
+    }
+
+    for (param, value) in method.params.iter().zip(values) {
+        scope.set(param, value);
+    }
+
+    eval_obj(&method.body, &mut scope).map_err(|e| e.with_frame(name))
+}
+
+/// `(call-next-method)` runs the next primary method with the same
+/// arguments, or with the ones it is given
+fn call_next_method(next: &NextMethod, values: Vec<Object>, env: &mut Env) -> Result<Object, EvalError> {
+    let values = match values.is_empty() {
+        true => next.args.clone(),
+        false => values,
+    };
+
+    match next.methods.split_first() {
+        Some((method, rest)) if values.len() == method.params.len() => {
+            call_method(&next.name, method, values, rest.to_vec(), env)
+        }
+        Some(_) => Err(EvalError::arity("call-next-method")),
+        None => Err(no_method(format!("No next method for {}", next.name))),
+    }
+}
+
+/// `make-instance`, `slot-value` and `set-slot-value!`
+fn eval_object_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let name = match &list[0] {
+        Object::Symbol(s) => s.as_str(),
+        other => return Err(EvalError::type_mismatch("a symbol", other)),
+    };
+    let args = eval_args(list, env)?;
+
+    match (name, args.as_slice()) {
+        ("make-instance", [class, initargs @ ..]) => make_instance(class, initargs, env),
+        ("slot-value", [Object::Record(record), Object::Symbol(slot)]) => {
+            let index = slot_index(record, slot)?;
+            Ok(record.fields.borrow()[index].clone())
+        }
+        ("set-slot-value!", [Object::Record(record), Object::Symbol(slot), value]) => {
+            let index = slot_index(record, slot)?;
+            record.fields.borrow_mut()[index] = value.clone();
+            Ok(Object::Void)
+        }
+        ("slot-value" | "set-slot-value!", [Object::Record(_), other, ..]) => Err(EvalError::type_mismatch("a symbol", other)),
+        ("slot-value" | "set-slot-value!", [other, ..]) => Err(EvalError::type_mismatch("an instance", other)),
+        (name, _) => Err(EvalError::arity(name)),
+    }
+}
+
+fn slot_index(record: &Record, slot: &str) -> Result<usize, EvalError> {
+    record.rtype.fields.iter().position(|field| field == slot).ok_or_else(|| {
+        let expected = format!("a slot of {}", record.rtype.short_name());
+        EvalError::type_mismatch(&expected, &Object::Symbol(slot.to_string()))
+    })
+}
+
+/// `(make-instance class :slot value...)`, the slots that are not given
+/// get the value of their initform or false
+fn make_instance(class: &Object, initargs: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let class = match class {
+        Object::RecordType(class) => class.clone(),
+        Object::Symbol(name) => match env.get(name) {
+            Some(Object::RecordType(class)) => class,
+            _ => return Err(EvalError::syntax(format!("Unknown class: {}", name))),
+        },
+        other => return Err(EvalError::type_mismatch("a class", other)),
+    };
+
+    if !initargs.len().is_multiple_of(2) {
+        return Err(EvalError::arity("make-instance"));
+    }
+
+    let mut fields = vec![None; class.fields.len()];
+    for pair in initargs.chunks(2) {
+        let index = match &pair[0] {
+            Object::Symbol(initarg) if initarg.starts_with(':') => {
+                class.fields.iter().position(|field| *field == initarg[1..])
+            }
+            _ => None,
+        };
+
+        match index {
+            Some(index) => fields[index] = Some(pair[1].clone()),
+            None => {
+                let expected = format!("a slot of {}", class.short_name());
+                return Err(EvalError::type_mismatch(&expected, &pair[0]));
+            }
+        }
+    }
+
+    let mut values = Vec::new();
+    for (value, initform) in fields.into_iter().zip(&class.initforms) {
+        values.push(match (value, initform) {
+            (Some(value), _) => value,
+            (None, Some(initform)) => eval_obj(initform, env)?,
+            (None, None) => Object::Bool(false),
+        });
+    }
+
+    let record = Object::Record(Record {
+        rtype: class,
+        fields: Rc::new(RefCell::new(values)),
+    });
+    env.budget().allocate(record.size())?;
+    Ok(record)
+}
+
+/// Scope and body of the first clause of a `match` whose pattern
+/// accepts the value
+fn match_clause(value: &Object, clauses: &[Object], env: &mut Env) -> Result<(Env, Object), EvalError> {
+    for clause in clauses {
+        let (pattern, body) = match clause {
+            Object::List(items) if items.len() >= 2 => (&items[0], &items[1..]),
+            _ => return Err(EvalError::syntax(format!("Invalid match clause: {}", clause))),
+        };
+
+        let mut bindings = Vec::new();
+        if pattern::matches(pattern, value, &mut bindings, env)? {
+            let mut scope = new_scope(env)?;
+            for (name, value) in bindings {
+                scope.set(&name, value);
+            }
+            return Ok((scope, lambda_body(body)));
+        }
+    }
+
+    Err(EvalError::new(ErrorKind::NoMatch(value.repr())))
+}
+
+/// `(bytes-allocated)` returns the bytes the current evaluation allocated so
+/// far, including what it freed
+fn eval_resource_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    match list {
+        [Object::Symbol(s)] if s == "bytes-allocated" => Ok(Object::Integer(env.bytes_allocated() as i64)),
+        [head, ..] => Err(EvalError::arity(&head.to_string())),
+        [] => Ok(Object::Void),
+    }
+}
+
+/// Values a form returned, one unless it returned `Values`
+fn values_of(obj: Object) -> Vec<Object> {
+    match obj {
+        Object::Values(values) => values,
+        other => vec![other],
+    }
+}
+
+/// `(values 1)` is just 1, any other number of values is kept together
+fn values(mut values: Vec<Object>) -> Object {
+    match values.len() {
+        1 => values.remove(0),
+        _ => Object::Values(values),
+    }
+}
+
+/// Quotient rounded down and the remainder with the sign of the divisor
+fn floor_div(n: i64, d: i64) -> Result<(i64, i64), EvalError> {
+    if d == 0 {
+        return Err(EvalError::new(ErrorKind::DivideByZero));
+    }
+
+    let (q, r) = (n.wrapping_div(d), n.wrapping_rem(d));
+    match r != 0 && (r < 0) != (d < 0) {
+        true => Ok((q - 1, r + d)),
+        false => Ok((q, r)),
+    }
+}
+
+fn eval_values_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let name = match &list[0] {
+        Object::Symbol(s) => s.as_str(),
+        other => return Err(EvalError::type_mismatch("a symbol", other)),
+    };
+    let args = eval_args(list, env)?;
+
+    match (name, args.as_slice()) {
+        ("values", _) => {
+            env.budget().allocate(args.len() * std::mem::size_of::<Object>())?;
+            Ok(values(args))
+        }
+        ("floor/", [Object::Integer(n), Object::Integer(d)]) => {
+            let (q, r) = floor_div(*n, *d)?;
+            Ok(Object::Values(vec![Object::Integer(q), Object::Integer(r)]))
+        }
+        ("floor/", [Object::Integer(_), other] | [other, _]) => Err(EvalError::type_mismatch("an integer", other)),
+        ("partition", [pred, Object::List(items)]) => {
+            let (mut accepted, mut rejected) = (Vec::new(), Vec::new());
+            for item in items {
+                match call_procedure(name, pred, vec![item.clone()], env)? {
+                    Object::Bool(false) => rejected.push(item.clone()),
+                    _ => accepted.push(item.clone()),
+                }
+            }
+            Ok(Object::Values(vec![Object::List(accepted), Object::List(rejected)]))
+        }
+        ("partition", [_, other]) => Err(EvalError::type_mismatch("a list", other)),
+        _ => Err(EvalError::arity(name)),
+    }
+}
+
+fn eval_generator_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let name = match &list[0] {
+        Object::Symbol(s) => s.as_str(),
+        other => return Err(EvalError::type_mismatch("a symbol", other)),
+    };
+    let args = eval_args(list, env)?;
+
+    match (name, args.as_slice()) {
+        ("make-generator", [producer, args @ ..]) => {
+            let generator = Generator::new(producer.clone(), args.to_vec());
+            Ok(Object::Generator(Rc::new(generator)))
+        }
+        ("generator-done?", [Object::Generator(generator)]) => Ok(Object::Bool(generator.is_done())),
+        ("generator->list", [func @ Object::Generator(generator), limit @ ..]) => {
+            let limit = match limit {
+                [] => None,
+                [Object::Integer(n)] if *n >= 0 => Some(*n as usize),
+                [other] => return Err(EvalError::type_mismatch("a non-negative integer", other)),
+                _ => return Err(EvalError::arity(name)),
+            };
+
+            let mut items = Vec::new();
+            while limit.is_none_or(|limit| items.len() < limit) {
+                let value = call_procedure(name, func, vec![], env)?;
+                if generator.is_done() {
+                    break;
+                }
+                env.budget().allocate(value.size())?;
+                items.push(value);
+            }
+            Ok(Object::List(items))
+        }
+        ("generator-done?" | "generator->list", [other, ..]) => Err(EvalError::type_mismatch("a generator", other)),
+        _ => Err(EvalError::arity(name)),
+    }
+}
+
+/// `(delay form)` keeps the values the symbols of the form have now,
+/// it is evaluated with them when the promise is forced
+fn eval_delay(kw: &str, list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let form = match list {
+        [_, form] => form.clone(),
+        _ => return Err(EvalError::arity(kw)),
+    };
+
+    let mut names = Vec::new();
+    promise::symbols(&form, &mut names);
+    let bindings: Vec<_> = names
+        .into_iter()
+        .filter_map(|name| env.get(&name).map(|value| (name, value)))
+        .collect();
+    let size = bindings.iter().map(|(name, value)| name.len() + value.size()).sum();
+    env.budget().allocate(size)?;
+
+    let lazy = kw == "delay-force";
+    Ok(Object::Promise(Rc::new(Promise::delayed(form, bindings, lazy))))
+}
+
+/// Value of a promise, forcing the promises a `delay-force` returns in
+/// a loop so a long chain of them runs in constant stack
+fn force(promise: &Rc<Promise>, env: &mut Env) -> Result<Object, EvalError> {
+    let mut promise = promise.clone();
+
+    loop {
+        let state = promise.state.borrow().clone();
+        let (form, bindings, lazy) = match state {
+            PromiseState::Forced(value) => return Ok(value),
+            PromiseState::Shared(other) => {
+                promise = other;
+                continue;
+            }
+            PromiseState::Delayed { form, bindings, lazy } => (form, bindings, lazy),
+        };
+
+        let mut scope = new_scope(env)?;
+        for (name, value) in bindings {
+            scope.set(&name, value);
+        }
+        let value = eval_obj(&form, &mut scope)?;
+
+        // Forcing the form may have forced this promise already
+        if !matches!(*promise.state.borrow(), PromiseState::Delayed { .. }) {
+            continue;
+        }
+
+        match (lazy, value) {
+            (true, Object::Promise(other)) if !Rc::ptr_eq(&other, &promise) => {
+                let taken = other.state.replace(PromiseState::Shared(promise.clone()));
+                promise.state.replace(taken);
+            }
+            (true, Object::Promise(_)) => {}
+            (true, other) => return Err(EvalError::type_mismatch("a promise", &other)),
+            (false, value) => {
+                promise.state.replace(PromiseState::Forced(value.clone()));
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// First item and promise of the rest of a stream, none when it is
+/// empty. A promise of a stream is forced
+fn stream_parts(stream: &Object, env: &mut Env) -> Result<Option<(Object, Rc<Promise>)>, EvalError> {
+    match stream {
+        Object::List(items) if items.is_empty() => Ok(None),
+        Object::List(items) => match items.as_slice() {
+            [first, Object::Promise(rest)] => Ok(Some((first.clone(), rest.clone()))),
+            _ => Err(EvalError::type_mismatch("a stream", stream)),
+        },
+        Object::Promise(promise) => {
+            let forced = force(promise, env)?;
+            match forced {
+                Object::Promise(_) => Err(EvalError::type_mismatch("a stream", &forced)),
+                _ => stream_parts(&forced, env),
+            }
+        }
+        other => Err(EvalError::type_mismatch("a stream", other)),
+    }
+}
+
+/// Promise of `(op 'func 'rest)`, the rest of a stream made by `stream-map`
+/// or `stream-filter`
+fn lazy_stream(op: &str, func: &Object, rest: Rc<Promise>) -> Object {
+    let form = Object::List(vec![
+        Object::Symbol(op.to_string()),
+        quoted(func.clone()),
+        quoted(Object::Promise(rest)),
+    ]);
+    Object::Promise(Rc::new(Promise::delayed(form, Vec::new(), false)))
+}
+
+fn eval_lazy_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let name = match &list[0] {
+        Object::Symbol(s) => s.as_str(),
+        other => return Err(EvalError::type_mismatch("a symbol", other)),
+    };
+    let args = eval_args(list, env)?;
+
+    match (name, args.as_slice()) {
+        ("force", [Object::Promise(promise)]) => force(promise, env),
+        ("force", [value]) => Ok(value.clone()),
+        ("make-promise", [value @ Object::Promise(_)]) => Ok(value.clone()),
+        ("make-promise", [value]) => Ok(Object::Promise(Rc::new(Promise::forced(value.clone())))),
+        ("promise?", [value]) => Ok(Object::Bool(matches!(value, Object::Promise(_)))),
+        ("stream-null?", [stream]) => Ok(Object::Bool(stream_parts(stream, env)?.is_none())),
+        ("stream-pair?", [stream]) => Ok(Object::Bool(matches!(stream_parts(stream, env), Ok(Some(_))))),
+        ("stream-car" | "stream-cdr", [stream]) => match stream_parts(stream, env)? {
+            Some((first, _)) if name == "stream-car" => Ok(first),
+            Some((_, rest)) => force(&rest, env),
+            None => Err(EvalError::type_mismatch("a non-empty stream", stream)),
+        },
+        ("stream-take", [stream, Object::Integer(n)]) if *n >= 0 => {
+            let mut items = Vec::new();
+            let mut stream = stream.clone();
+            while items.len() < *n as usize {
+                match stream_parts(&stream, env)? {
+                    Some((first, rest)) => {
+                        env.budget().allocate(first.size())?;
+                        items.push(first);
+                        // The rest of the last item is not forced, it may never end
+                        if items.len() < *n as usize {
+                            stream = force(&rest, env)?;
+                        }
+                    }
+                    None => break,
+                }
+            }
+            Ok(Object::List(items))
+        }
+        ("stream-take", [_, other]) => Err(EvalError::type_mismatch("a non-negative integer", other)),
+        ("stream-map", [func, stream]) => match stream_parts(stream, env)? {
+            Some((first, rest)) => {
+                let value = call_procedure(name, func, vec![first], env)?;
+                Ok(Object::List(vec![value, lazy_stream(name, func, rest)]))
+            }
+            None => Ok(Object::List(Vec::new())),
+        },
+        ("stream-filter", [pred, stream]) => {
+            let mut stream = stream.clone();
+            while let Some((first, rest)) = stream_parts(&stream, env)? {
+                match call_procedure(name, pred, vec![first.clone()], env)? {
+                    Object::Bool(false) => stream = force(&rest, env)?,
+                    _ => return Ok(Object::List(vec![first, lazy_stream(name, pred, rest)])),
+                }
+            }
+            Ok(Object::List(Vec::new()))
+        }
+        _ => Err(EvalError::arity(name)),
+    }
+}
+
+/// Regex of an argument, a string is compiled as a pattern
+fn regex_arg(obj: &Object) -> Result<Rc<Regexp>, EvalError> {
+    match obj {
+        Object::Regex(regex) => Ok(regex.clone()),
+        Object::Str(pattern) => Ok(Rc::new(Regexp::new(pattern)?)),
+        other => Err(EvalError::type_mismatch("a regex", other)),
+    }
+}
+
+fn eval_regex_op(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let name = match &list[0] {
+        Object::Symbol(s) => s.as_str(),
+        other => return Err(EvalError::type_mismatch("a symbol", other)),
+    };
+    let args = eval_args(list, env)?;
+
+    let result = match (name, args.as_slice()) {
+        ("regex", [regex]) => Object::Regex(regex_arg(regex)?),
+        ("regex?", [value]) => Object::Bool(matches!(value, Object::Regex(_))),
+        ("regex-match" | "regex-search" | "regex-named-groups", [regex, s]) => {
+            let regex = regex_arg(regex)?;
+            let s = string_arg(s)?;
+            let matcher = match name {
+                "regex-match" => &regex.anchored,
+                _ => &regex.regex,
+            };
+            match matcher.captures(s) {
+                Some(captures) if name == "regex-named-groups" => regexp::named_groups(&regex.regex, &captures),
+                Some(captures) => regexp::groups(&captures),
+                None => Object::Bool(false),
+            }
+        }
+        ("regex-match-all", [regex, s]) => {
+            let regex = regex_arg(regex)?;
+            let s = string_arg(s)?;
+            let matches = match regex.regex.captures_len() {
+                1 => regex.regex.find_iter(s).map(|m| Object::Str(m.as_str().to_string())).collect(),
+                _ => regex.regex.captures_iter(s).map(|c| regexp::groups(&c)).collect(),
+            };
+            Object::List(matches)
+        }
+        ("regex-replace" | "regex-replace-all", [regex, s, replacement]) => {
+            let regex = regex_arg(regex)?;
+            let replacement = regexp::replacement(string_arg(replacement)?);
+            let limit = if name == "regex-replace" { 1 } else { usize::MAX };
+            // The text is charged as it is written, not once it is made
+            let replaced = regexp::replace(&regex.regex, string_arg(s)?, limit, &replacement, env.budget())?;
+            return Ok(Object::Str(replaced));
+        }
+        ("regex-split", [regex, s]) => {
+            let regex = regex_arg(regex)?;
+            let items = regex.regex.split(string_arg(s)?);
+            Object::List(items.map(|item| Object::Str(item.to_string())).collect())
+        }
+        _ => return Err(EvalError::arity(name)),
+    };
+
+    env.budget().allocate(result.size())?;
+    Ok(result)
+}
+
+/// `(define-values (q r) (floor/ 7 2))` defines a symbol for each value
+fn eval_define_values(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if list.len() != 3 {
+        return Err(EvalError::arity("define-values"));
+    }
+
+    check_param(&list[1])?;
+    let value = eval_obj(&list[2], env)?;
+    bind_param("define-values", &list[1], Object::List(values_of(value)), env)?;
+    Ok(Object::Void)
+}
+
+fn eval_begin(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let mut result = Object::Void;
+
+    for obj in &list[1..] {
+        result = eval_obj(obj, env)?;
+    }
+
+    Ok(result)
+}
+
+/// Special forms evaluated by recursion, `if`, `define`, `begin`, `quote`,
+/// `lambda`, `match`, `let-values`, `destructuring-bind`, `reset`, `shift`,
+/// `equal`, `quasiquote`, `guard` and the derived forms are evaluated by
+/// the machine
+fn eval_keyword(kw: &str, list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    match kw {
+        "load" => eval_load(list, env),
+        "print" => eval_print(list, env),
+        "debug" => eval_debug(list, env),
+        "unquote" | "unquote-splicing" => Err(EvalError::syntax(format!("{} outside of quasiquote", kw))),
+        "defmacro" => eval_defmacro(list, env),
+        "macroexpand" => eval_macroexpand(list, env, false),
+        "macroexpand-1" => eval_macroexpand(list, env, true),
+        "syntax-rules" => eval_syntax_rules(list),
+        "define-syntax" => eval_define_syntax(list, env),
+        "handler-bind" => eval_handler_bind(list, env),
+        "restart-case" => eval_restart_case(list, env),
+        "module" => eval_module(list, env),
+        "import" => eval_import(list, env),
+        "require" => eval_require(list, env),
+        "delay" | "delay-force" => eval_delay(kw, list, env),
+        "define-record-type" => eval_define_record_type(list, env),
+        "defclass" => eval_defclass(list, env),
+        "defgeneric" => eval_defgeneric(list, env),
+        "defmethod" => eval_defmethod(list, env),
+        "define-values" => eval_define_values(list, env),
+        "true" => Ok(Object::Bool(true)),
+        "false" => Ok(Object::Bool(false)),
+        _ => Err(EvalError::syntax(format!("Invalid keyword: {}", kw))),
+    }
+}
+
+/// Operators of the machine itself, they call procedures or capture
+/// continuations
+const CONTROL_OPS: [&str; 5] = [
+    "call/cc",
+    "call-with-current-continuation",
+    "dynamic-wind",
+    "call-with-values",
+    "yield",
+];
+
+/// Operator of the interpreter that takes a list with its arguments
+type Builtin = fn(&[Object], &mut Env) -> Result<Object, EvalError>;
+
+/// Function of an operator, its arguments are evaluated before it is applied
+fn builtin(name: &str) -> Option<Builtin> {
+    let operators = ["+", "-", "*", "/", "<", ">", "=", "!=", "^", ">=", "<=", "<>", "!="];
+    let str_op = [
+        "concat",
+        "string-length",
+        "substring",
+        "string-append",
+        "string-split",
+        "string-join",
+        "string-trim",
+        "string-trim-left",
+        "string-trim-right",
+        "string-upcase",
+        "string-downcase",
+        "string-graphemes",
+        "string-contains",
+        "string-replace",
+        "string-index",
+        "string->number",
+        "number->string",
+        "string=?",
+        "string<?",
+        "string>?",
+        "string<=?",
+        "string>=?",
+        "string-ci=?",
+        "string-ci<?",
+        "string-ci>?",
+        "string-ci<=?",
+        "string-ci>=?",
+        "format",
+        "open-output-string",
+        "get-output-string",
+    ];
+    let condition_op = [
+        "error",
+        "raise",
+        "condition?",
+        "condition-kind",
+        "condition-message",
+        "condition-irritants",
+        "signal",
+        "make-condition",
+        "invoke-restart",
+        "compute-restarts",
+    ];
+    let resource_op = ["bytes-allocated"];
+    let object_op = ["make-instance", "slot-value", "set-slot-value!"];
+    let values_op = ["values", "floor/", "partition"];
+    let generator_op = ["make-generator", "generator-done?", "generator->list"];
+    let lazy_op = [
+        "force",
+        "make-promise",
+        "promise?",
+        "stream-car",
+        "stream-cdr",
+        "stream-null?",
+        "stream-pair?",
+        "stream-take",
+        "stream-map",
+        "stream-filter",
+    ];
+    let regex_op = [
+        "regex",
+        "regex?",
+        "regex-match",
+        "regex-search",
+        "regex-match-all",
+        "regex-named-groups",
+        "regex-replace",
+        "regex-replace-all",
+        "regex-split",
+    ];
+
+    match name {
+        oper if operators.contains(&oper) => Some(eval_binary_op),
+        op if str_op.contains(&op) => Some(eval_string_op),
+        op if condition_op.contains(&op) => Some(eval_condition_op),
+        op if resource_op.contains(&op) => Some(eval_resource_op),
+        op if object_op.contains(&op) => Some(eval_object_op),
+        op if values_op.contains(&op) => Some(eval_values_op),
+        op if generator_op.contains(&op) => Some(eval_generator_op),
+        op if lazy_op.contains(&op) => Some(eval_lazy_op),
+        op if regex_op.contains(&op) => Some(eval_regex_op),
+        _ => None,
+    }
+}
+
+fn quoted(value: Object) -> Object {
+    Object::List(vec![Object::Keyword("quote".to_string()), value])
+}
+
+pub fn eval_list(list: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    eval_obj(&Object::List(list.to_vec()), env)
+}
+
+fn eval_obj(obj: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    Machine::run(Next::Eval(obj.clone()), env)
+}
+
+fn eval_atom(obj: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    match obj {
+        Object::Void => Ok(Object::Void),
+        Object::Keyword(k) if k == "true" => Ok(Object::Bool(true)),
+        Object::Keyword(k) if k == "false" => Ok(Object::Bool(false)),
+        Object::Keyword(k) => Ok(Object::Keyword(k.clone())),
+        Object::Lambda(_params, _body) => Ok(Object::Void),
+        Object::Macro(_params, _body) => Ok(Object::Void),
+        Object::SyntaxRules(_, _) => Ok(obj.clone()),
+        Object::Condition(_) => Ok(obj.clone()),
+        Object::RecordType(_) | Object::Record(_) | Object::RecordProc(_) => Ok(obj.clone()),
+        Object::Generic(_) | Object::NextMethod(_) | Object::Values(_) => Ok(obj.clone()),
+        Object::Continuation(_) | Object::Generator(_) | Object::Promise(_) => Ok(obj.clone()),
+        Object::Regex(_) | Object::Port(_) => Ok(obj.clone()),
+        Object::Bool(_) => Ok(obj.clone()),
+        Object::Integer(n) => Ok(Object::Integer(*n)),
+        Object::Float(f) => Ok(Object::Float(*f)),
+        Object::Str(s) => Ok(Object::Str(s.clone())),
+        Object::Symbol(s) => eval_symbol(s, env),
+        Object::List(list) => eval_list(list, env),
+    }
+}
+
+/// How an evaluation goes on, with a form to evaluate, with a value
+/// for the innermost frame, by reinstating a continuation or by calling
+/// a procedure with values
+enum Next {
+    Eval(Object),
+    Return(Object),
+    Resume(Rc<Continuation>, Object),
+    Apply(String, Object, Vec<Object>),
+}
+
+/// Evaluation of a form with an explicit stack of frames instead of
+/// native recursion, so `call/cc` can capture the frames and reinstate
+/// them as many times as it likes
+///
+/// The special forms the machine does not know, like `handler-bind` or
+/// `define-values`, are evaluated by the recursive functions of this
+/// module. Their subforms run in machines of their own, a continuation
+/// captured there can leave them but extends only up to them, and it
+/// can not be resumed once they returned
+struct Machine {
+    id: usize,
+    /// Started by a form evaluated inside another machine
+    nested: bool,
+    frames: Vec<Frame>,
+    /// Scopes of the running procedures, one for each `Body` frame
+    scopes: Vec<Env>,
+}
+
+impl Machine {
+    fn run(next: Next, base: &mut Env) -> Result<Object, EvalError> {
+        base.budget().enter()?;
+
+        let mut machine = Machine {
+            id: continuation::next_id(),
+            nested: continuation::is_nested(),
+            frames: Vec::new(),
+            scopes: Vec::new(),
+        };
+
+        continuation::start(machine.id);
+        let result = machine.run_loop(next, base);
+        continuation::finish();
+
+        base.budget().exit();
+        result
+    }
+
+    fn run_loop(&mut self, mut next: Next, base: &mut Env) -> Result<Object, EvalError> {
+        loop {
+            let step = match next {
+                Next::Eval(obj) => self.eval(obj, base),
+                Next::Return(value) => match self.frames.pop() {
+                    Some(frame) => {
+                        base.budget().leave();
+                        self.deliver(frame, value, base)
+                    }
+                    None => return Ok(value),
+                },
+                Next::Resume(k, value) => self.resume(k, value, base),
+                Next::Apply(name, func, args) => self.apply(&name, func, args, base),
+            };
+
+            next = match step {
+                Ok(next) => next,
+                Err(error) => self.recover(error, base)?,
+            };
+        }
+    }
+
+    /// Environment of the innermost procedure, the one the machine
+    /// started with at the top
+    fn env<'a>(&'a mut self, base: &'a mut Env) -> &'a mut Env {
+        match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => base,
+        }
+    }
+
+    fn push(&mut self, frame: Frame, base: &Env) -> Result<(), EvalError> {
+        base.budget().descend()?;
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    /// Run a body in a scope of its own, the scope goes away with the
+    /// `Body` frame. A body entered as the last form of another one
+    /// replaces it, so loops written as tail calls run in constant depth
+    fn enter(&mut self, name: &str, scope: Env, base: &Env) -> Result<(), EvalError> {
+        if let Some(Frame::Body { .. }) = self.frames.last() {
+            self.frames.pop();
+            self.scopes.pop();
+            base.budget().leave();
+        }
+
+        let frame = Frame::Body { name: name.to_string() };
+        self.push(frame, base)?;
+        self.scopes.push(scope);
+        Ok(())
+    }
+
+    fn eval(&mut self, obj: Object, base: &mut Env) -> Result<Next, EvalError> {
+        base.budget().step()?;
+
+        let items = match obj {
+            Object::List(items) if items.is_empty() => return Ok(Next::Return(Object::Void)),
+            Object::List(items) => items,
+            atom => return eval_atom(&atom, self.env(base)).map(Next::Return),
+        };
+
+        match &items[0] {
+            Object::Keyword(k) => {
+                let k = k.clone();
+                self.eval_keyword(&k, items, base)
+            }
+            Object::Symbol(s) if CONTROL_OPS.contains(&s.as_str()) || builtin(s).is_some() => {
+                let callee = Callee::Builtin(s.clone());
+                self.call(callee, items, base)
+            }
+            Object::Symbol(s) => {
+                let name = s.clone();
+                self.eval_call(name, items, base)
+            }
+            Object::List(inner) if is_lambda_form(inner) => {
+                let func = eval_function_definition(inner)?;
+                self.call(
+                    Callee::Procedure {
+                        name: "lambda".to_string(),
+                        func,
+                    },
+                    items,
+                    base,
+                )
+            }
+            _ => {
+                // A sequence, every item is evaluated
+                let mut rest = items.into_iter();
+                let first = rest.next().unwrap_or(Object::Void);
+                self.push(
+                    Frame::Sequence {
+                        rest,
+                        values: Vec::new(),
+                    },
+                    base,
+                )?;
+                Ok(Next::Eval(first))
+            }
+        }
+    }
+
+    fn eval_keyword(&mut self, kw: &str, items: Vec<Object>, base: &mut Env) -> Result<Next, EvalError> {
+        match kw {
+            "if" => {
+                let [_, cond, then, otherwise] =
+                    <[Object; 4]>::try_from(items).map_err(|_| EvalError::arity("if statement"))?;
+                self.push(Frame::If { then, otherwise }, base)?;
+                Ok(Next::Eval(cond))
+            }
+            "define" => {
+                if items.len() != 3 {
+                    return Err(EvalError::arity("define"));
+                }
+
+                let name = match &items[1] {
+                    Object::Symbol(s) => s.clone(),
+                    Object::Keyword(k) => return Err(EvalError::syntax(format!("Cannot define the keyword `{}`", k))),
+                    _ => return Err(EvalError::syntax("Invalid define")),
+                };
+                let value = items.into_iter().nth(2).unwrap_or(Object::Void);
+                self.push(Frame::Define { name }, base)?;
+                Ok(Next::Eval(value))
+            }
+            "begin" => {
+                let mut rest = items.into_iter();
+                rest.next();
+                match rest.next() {
+                    Some(first) => self.begin(first, rest, base),
+                    None => Ok(Next::Return(Object::Void)),
+                }
+            }
+            "quote" => eval_quote(&items).map(Next::Return),
+            "lambda" => eval_function_definition(&items).map(Next::Return),
+            "match" => {
+                if items.len() < 2 {
+                    return Err(EvalError::arity("match"));
+                }
+
+                let mut items = items.into_iter();
+                let value = items.nth(1).unwrap_or(Object::Void);
+                let clauses = items.collect();
+                self.push(Frame::Match { clauses }, base)?;
+                Ok(Next::Eval(value))
+            }
+            k if k == "let-values" || k == "let" && is_pattern_let(&items) => {
+                let (bindings, body) = match items.as_slice() {
+                    [_, Object::List(bindings), body @ ..] if !body.is_empty() => (bindings, lambda_body(body)),
+                    [_, other, _, ..] => return Err(EvalError::syntax(format!("Invalid {} bindings: {}", kw, other))),
+                    _ => return Err(EvalError::arity(kw)),
+                };
+
+                let mut formals = Vec::new();
+                let mut forms = Vec::new();
+                for binding in bindings {
+                    match binding {
+                        Object::List(pair) if pair.len() == 2 => {
+                            check_param(&pair[0])?;
+                            formals.push(pair[0].clone());
+                            forms.push(pair[1].clone());
+                        }
+                        _ => return Err(EvalError::syntax(format!("Invalid {} binding: {}", kw, binding))),
+                    }
+                }
+
+                let callee = Callee::Values {
+                    form: kw.to_string(),
+                    formals,
+                    body,
+                };
+                self.next_arg(callee, forms.into_iter(), Vec::new(), base)
+            }
+            "destructuring-bind" => {
+                let (pattern, form, body) = match items.as_slice() {
+                    [_, pattern, form, body @ ..] if !body.is_empty() => (pattern, form, lambda_body(body)),
+                    _ => return Err(EvalError::arity(kw)),
+                };
+                check_param(pattern)?;
+
+                let callee = Callee::Values {
+                    form: kw.to_string(),
+                    formals: vec![pattern.clone()],
+                    body,
+                };
+                self.next_arg(callee, vec![form.clone()].into_iter(), Vec::new(), base)
+            }
+            "equal" => {
+                if items.len() != 3 {
+                    return Err(EvalError::arity("equal"));
+                }
+                self.call(Callee::Builtin(kw.to_string()), items, base)
+            }
+            "quasiquote" => {
+                let template = match <[Object; 2]>::try_from(items) {
+                    Ok([_, template]) => template,
+                    Err(_) => return Err(EvalError::arity("quasiquote")),
+                };
+                let mut forms = Vec::new();
+                unquoted(&template, 1, &mut forms);
+                self.next_arg(Callee::Quasiquote { template }, forms.into_iter(), Vec::new(), base)
+            }
+            "guard" => {
+                let (var, clauses) = guard_spec(&items)?;
+                self.push(Frame::Guard { var, clauses }, base)?;
+                condition::push_frame(condition::Frame::Guard);
+                Ok(Next::Eval(lambda_body(&items[2..])))
+            }
+            "reset" => {
+                self.push(Frame::Prompt { generator: None }, base)?;
+                Ok(Next::Eval(lambda_body(&items[1..])))
+            }
+            "shift" => {
+                let name = match items.get(1) {
+                    Some(Object::Symbol(name)) if items.len() >= 3 => name.clone(),
+                    Some(_) if items.len() >= 3 => {
+                        return Err(EvalError::syntax("shift needs a variable for the continuation"))
+                    }
+                    _ => return Err(EvalError::arity("shift")),
+                };
+                let prompt = self
+                    .prompt(false)
+                    .ok_or_else(|| EvalError::new(ErrorKind::Control("shift outside of a reset".to_string())))?;
+
+                let mut scope = new_scope(self.env(base))?;
+                let k = self.cut(prompt, base)?;
+                scope.set(&name, Object::Continuation(k));
+
+                self.push(Frame::Prompt { generator: None }, base)?;
+                self.enter("shift", scope, base)?;
+                Ok(Next::Eval(lambda_body(&items[2..])))
+            }
+            k if k == "let-syntax" || DERIVED_FORMS.contains(&k) => {
+                let core = expand(&Object::List(items), self.env(base))?;
+                Ok(Next::Eval(core))
+            }
+            _ => eval_keyword(kw, &items, self.env(base)).map(Next::Return),
+        }
+    }
+
+    /// Evaluate a form of a `begin`, the last one takes the place of
+    /// the `begin` so a call there is a tail call
+    fn begin(&mut self, form: Object, rest: IntoIter<Object>, base: &mut Env) -> Result<Next, EvalError> {
+        if rest.len() > 0 {
+            self.push(Frame::Begin { rest }, base)?;
+        }
+        Ok(Next::Eval(form))
+    }
+
+    /// Call of the value bound to a symbol
+    fn eval_call(&mut self, name: String, mut items: Vec<Object>, base: &mut Env) -> Result<Next, EvalError> {
+        let env = self.env(base);
+        let func = match env.get(&name) {
+            Some(func) => func,
+            None => {
+                // Forms built at runtime did not go through the expander
+                return match macroexpand_1(&Object::List(items), env)? {
+                    Some(expanded) => Ok(Next::Eval(expanded)),
+                    None => Err(EvalError::unbound(&name)),
+                };
+            }
+        };
+
+        match func {
+            Object::Lambda(_, _)
+            | Object::RecordProc(_)
+            | Object::Generic(_)
+            | Object::NextMethod(_)
+            | Object::Continuation(_)
+            | Object::Generator(_) => self.call(Callee::Procedure { name, func }, items, base),
+            Object::Str(_) | Object::Bool(_) | Object::Integer(_) | Object::Float(_) => Ok(Next::Return(func)),
+            Object::List(l) => Ok(Next::Eval(Object::List(l))),
+            Object::Keyword(k) => {
+                items[0] = Object::Keyword(k);
+                Ok(Next::Eval(Object::List(items)))
+            }
+            _ => Err(EvalError::type_mismatch("a lambda", &func)),
+        }
+    }
+
+    /// Evaluate the arguments of a call, a lambda takes as many as it
+    /// has parameters
+    fn call(&mut self, callee: Callee, items: Vec<Object>, base: &mut Env) -> Result<Next, EvalError> {
+        let mut args = items;
+        args.remove(0);
+
+        if let Callee::Procedure {
+            name,
+            func: Object::Lambda(params, _),
+        } = &callee
+        {
+            if args.len() < params.len() {
+                return Err(EvalError::arity(name));
+            }
+            args.truncate(params.len());
+        }
+
+        self.next_arg(callee, args.into_iter(), Vec::new(), base)
+    }
+
+    fn next_arg(
+        &mut self,
+        callee: Callee,
+        mut rest: IntoIter<Object>,
+        values: Vec<Object>,
+        base: &mut Env,
+    ) -> Result<Next, EvalError> {
+        match rest.next() {
+            Some(arg) => {
+                self.push(Frame::Args { callee, rest, values }, base)?;
+                Ok(Next::Eval(arg))
+            }
+            None => match callee {
+                Callee::Procedure { name, func } => self.apply(&name, func, values, base),
+                Callee::Builtin(op) => self.apply_builtin(&op, values, base),
+                Callee::Values { form, formals, body } => {
+                    let mut scope = new_scope(self.env(base))?;
+                    for (formals, value) in formals.iter().zip(values) {
+                        match form.as_str() {
+                            "let-values" => bind_param(&form, formals, Object::List(values_of(value)), &mut scope)?,
+                            _ => bind_pattern(&form, formals, value, &mut scope)?,
+                        }
+                    }
+
+                    self.enter(&form, scope, base)?;
+                    Ok(Next::Eval(body))
+                }
+                Callee::Quasiquote { template } => {
+                    quasi_expand(&template, 1, &mut values.into_iter(), self.env(base)).map(Next::Return)
+                }
+            },
+        }
+    }
+
+    /// Give the value of a form to the frame that was waiting for it
+    fn deliver(&mut self, frame: Frame, value: Object, base: &mut Env) -> Result<Next, EvalError> {
+        match frame {
+            Frame::Sequence { mut rest, mut values } => {
+                if value != Object::Void {
+                    values.push(value);
+                }
+
+                match rest.next() {
+                    Some(item) => {
+                        self.push(Frame::Sequence { rest, values }, base)?;
+                        Ok(Next::Eval(item))
+                    }
+                    None => {
+                        base.budget().allocate(values.len() * std::mem::size_of::<Object>())?;
+                        Ok(Next::Return(Object::List(values)))
+                    }
+                }
+            }
+            Frame::Begin { mut rest } => match rest.next() {
+                Some(form) => self.begin(form, rest, base),
+                None => Ok(Next::Return(value)),
+            },
+            Frame::If { then, otherwise } => match condition_value(value, self.env(base))? {
+                true => Ok(Next::Eval(then)),
+                false => Ok(Next::Eval(otherwise)),
+            },
+            Frame::Match { clauses } => {
+                let (scope, body) = match_clause(&value, &clauses, self.env(base))?;
+                self.enter("match", scope, base)?;
+                Ok(Next::Eval(body))
+            }
+            Frame::Define { name } => {
+                let env = self.env(base);
+                env.budget().allocate(name.len() + std::mem::size_of::<Object>())?;
+                env.set(&name, value);
+                Ok(Next::Return(Object::Void))
+            }
+            Frame::Args {
+                callee,
+                rest,
+                mut values,
+            } => {
+                values.push(value);
+                self.next_arg(callee, rest, values, base)
+            }
+            Frame::Body { .. } => {
+                self.scopes.pop();
+                Ok(Next::Return(value))
+            }
+            Frame::Wind { after, .. } => {
+                call_procedure("dynamic-wind", &after, vec![], self.env(base))?;
+                Ok(Next::Return(value))
+            }
+            Frame::Consume { consumer } => self.apply("call-with-values", consumer, values_of(value), base),
+            Frame::Prompt {
+                generator: Some(generator),
+            } => {
+                generator.state.replace(GeneratorState::Done);
+                Ok(Next::Return(Object::Void))
+            }
+            Frame::Prompt { generator: None } => Ok(Next::Return(value)),
+            Frame::Guard { .. } => {
+                condition::pop_frame();
+                Ok(Next::Return(value))
+            }
+        }
+    }
+
+    /// Call a procedure with its arguments, the body of a lambda runs
+    /// in a copy of the environment of the caller
+    fn apply(&mut self, name: &str, func: Object, args: Vec<Object>, base: &mut Env) -> Result<Next, EvalError> {
+        match func {
+            Object::Lambda(params, body) => {
+                if args.len() < params.len() {
+                    return Err(EvalError::arity(name));
+                }
+
+                let mut scope = new_scope(self.env(base))?;
+                for (param, value) in params.iter().zip(args) {
+                    bind_param(name, param, value, &mut scope)?;
+                }
+
+                self.enter(name, scope, base)?;
+                Ok(Next::Eval(Object::List(body)))
+            }
+            Object::Continuation(k) => Ok(Next::Resume(k, values(args))),
+            Object::Generator(generator) => {
+                if !args.is_empty() {
+                    return Err(EvalError::arity(name));
+                }
+
+                let state = generator.state.replace(GeneratorState::Running);
+                let prompt = Frame::Prompt {
+                    generator: Some(generator.clone()),
+                };
+                match state {
+                    GeneratorState::Fresh => {
+                        self.push(prompt, base)?;
+                        self.apply(name, generator.producer.clone(), generator.args.clone(), base)
+                    }
+                    GeneratorState::Suspended(k) => {
+                        self.push(prompt, base)?;
+                        self.compose(&k, Object::Void, base)
+                    }
+                    GeneratorState::Running => {
+                        let message = format!("Generator {} is already running", name);
+                        Err(EvalError::new(ErrorKind::Control(message)))
+                    }
+                    GeneratorState::Done => {
+                        generator.state.replace(GeneratorState::Done);
+                        Ok(Next::Return(Object::Void))
+                    }
+                }
+            }
+            other => call_procedure(name, &other, args, self.env(base)).map(Next::Return),
+        }
+    }
+
+    fn apply_builtin(&mut self, op: &str, args: Vec<Object>, base: &mut Env) -> Result<Next, EvalError> {
+        match (op, args.as_slice()) {
+            ("call/cc" | "call-with-current-continuation", [func]) => {
+                let k = self.capture(base)?;
+                self.apply(op, func.clone(), vec![k], base)
+            }
+            ("dynamic-wind", [before, thunk, after]) => {
+                call_procedure(op, before, vec![], self.env(base))?;
+                let wind = Frame::Wind {
+                    id: continuation::next_id(),
+                    before: before.clone(),
+                    after: after.clone(),
+                };
+                self.push(wind, base)?;
+                self.apply(op, thunk.clone(), vec![], base)
+            }
+            ("call-with-values", [producer, consumer]) => {
+                self.push(
+                    Frame::Consume {
+                        consumer: consumer.clone(),
+                    },
+                    base,
+                )?;
+                self.apply(op, producer.clone(), vec![], base)
+            }
+            ("equal", [left, right]) => Ok(Next::Return(Object::Bool(left == right))),
+            ("yield", [value]) => {
+                let prompt = self
+                    .prompt(true)
+                    .ok_or_else(|| EvalError::new(ErrorKind::Control("yield outside of a generator".to_string())))?;
+
+                let k = self.cut(prompt + 1, base)?;
+                if let Some(Frame::Prompt {
+                    generator: Some(generator),
+                }) = self.frames.pop()
+                {
+                    base.budget().leave();
+                    generator.state.replace(GeneratorState::Suspended(k));
+                }
+                Ok(Next::Return(value.clone()))
+            }
+            _ => match builtin(op) {
+                Some(eval_op) => {
+                    let mut list = vec![Object::Symbol(op.to_string())];
+                    list.extend(args.into_iter().map(quoted));
+                    eval_op(&list, self.env(base)).map(Next::Return)
+                }
+                None => Err(EvalError::arity(op)),
+            },
+        }
+    }
+
+    /// Continuation of the form being evaluated, the scopes are copied
+    /// so it can be resumed after they are gone
+    fn capture(&self, base: &Env) -> Result<Object, EvalError> {
+        base.budget().allocate(self.scopes.iter().map(Env::size).sum())?;
+
+        Ok(Object::Continuation(Rc::new(Continuation {
+            evaluation: self.id,
+            frames: self.frames.clone(),
+            scopes: self.scopes.clone(),
+            delimited: false,
+            nested: self.nested,
+        })))
+    }
+
+    /// Innermost prompt of a `reset`, or of a generator
+    fn prompt(&self, generator: bool) -> Option<usize> {
+        self.frames
+            .iter()
+            .rposition(|frame| matches!(frame, Frame::Prompt { generator: g } if g.is_some() == generator))
+    }
+
+    /// Take the frames from `start` as a delimited continuation, leaving
+    /// the `dynamic-wind` thunks among them
+    fn cut(&mut self, start: usize, base: &mut Env) -> Result<Rc<Continuation>, EvalError> {
+        let afters = self.frames[start..].iter().rev().filter_map(|frame| match frame {
+            Frame::Wind { after, .. } => Some(after.clone()),
+            _ => None,
+        });
+        for after in afters.collect::<Vec<_>>() {
+            call_procedure("dynamic-wind", &after, vec![], self.env(base))?;
+        }
+
+        let outer = self.frames[..start]
+            .iter()
+            .filter(|frame| matches!(frame, Frame::Body { .. }))
+            .count();
+        let frames = self.frames.split_off(start);
+        let scopes = self.scopes.split_off(outer);
+
+        for _ in &frames {
+            base.budget().leave();
+        }
+        for _ in 0..continuation::guards(&frames) {
+            condition::pop_frame();
+        }
+        base.budget().allocate(scopes.iter().map(Env::size).sum())?;
+
+        Ok(Rc::new(Continuation {
+            evaluation: self.id,
+            frames,
+            scopes,
+            delimited: true,
+            nested: self.nested,
+        }))
+    }
+
+    /// Add the frames of a delimited continuation on top of the current
+    /// ones, entering the `dynamic-wind` thunks among them again
+    fn compose(&mut self, k: &Continuation, value: Object, base: &mut Env) -> Result<Next, EvalError> {
+        let befores = k.frames.iter().filter_map(|frame| match frame {
+            Frame::Wind { before, .. } => Some(before.clone()),
+            _ => None,
+        });
+        for before in befores.collect::<Vec<_>>() {
+            call_procedure("dynamic-wind", &before, vec![], self.env(base))?;
+        }
+
+        for frame in &k.frames {
+            self.push(frame.clone(), base)?;
+        }
+        self.scopes.extend(k.scopes.iter().cloned());
+        for _ in 0..continuation::guards(&k.frames) {
+            condition::push_frame(condition::Frame::Guard);
+        }
+
+        Ok(Next::Return(value))
+    }
+
+    /// Reinstate the frames of a continuation, leaving the `dynamic-wind`
+    /// thunks it is not in and entering again the ones it is in
+    ///
+    /// A continuation of an evaluation that is still running further
+    /// out is reached by unwinding to it, one of a nested evaluation that
+    /// returned is gone
+    fn resume(&mut self, k: Rc<Continuation>, value: Object, base: &mut Env) -> Result<Next, EvalError> {
+        if k.delimited {
+            return self.compose(&k, value, base);
+        }
+
+        if k.evaluation != self.id && continuation::is_running(k.evaluation) {
+            return Err(EvalError::jump(k, value));
+        }
+
+        if k.evaluation != self.id && k.nested {
+            let message = "Continuation of a form that already returned".to_string();
+            return Err(EvalError::new(ErrorKind::Control(message)));
+        }
+
+        let current = continuation::winds(&self.frames);
+        let target = continuation::winds(&k.frames);
+
+        let afters = self.frames.iter().rev().filter_map(|frame| match frame {
+            Frame::Wind { id, after, .. } if !target.contains(id) => Some(after.clone()),
+            _ => None,
+        });
+        for after in afters.collect::<Vec<_>>() {
+            call_procedure("dynamic-wind", &after, vec![], self.env(base))?;
+        }
+
+        let befores = k.frames.iter().filter_map(|frame| match frame {
+            Frame::Wind { id, before, .. } if !current.contains(id) => Some(before.clone()),
+            _ => None,
+        });
+        for before in befores.collect::<Vec<_>>() {
+            call_procedure("dynamic-wind", &before, vec![], self.env(base))?;
+        }
+
+        for _ in &self.frames {
+            base.budget().leave();
+        }
+        for _ in 0..continuation::guards(&self.frames) {
+            condition::pop_frame();
+        }
+        self.frames = k.frames.clone();
+        self.scopes = k.scopes.clone();
+        for _ in 0..continuation::guards(&self.frames) {
+            condition::push_frame(condition::Frame::Guard);
+        }
+        for _ in &self.frames {
+            base.budget().descend()?;
+        }
+
+        Ok(Next::Return(value))
+    }
+
+    /// Handle an error of a step: the handlers see it first, a jump to
+    /// a continuation of this evaluation resumes it, other errors unwind
+    /// the frames, running the after thunks of `dynamic-wind`
+    fn recover(&mut self, error: EvalError, base: &mut Env) -> Result<Next, EvalError> {
+        let mut error = match error.signaled {
+            true => error,
+            false => match signal_error(error, self.env(base)) {
+                Ok(value) => return Ok(Next::Return(value)),
+                Err(error) => error,
+            },
+        };
+
+        if let ErrorKind::Continuation { target, value } = &error.kind {
+            if target.evaluation == self.id {
+                return Ok(Next::Resume(target.clone(), value.as_ref().clone()));
+            }
+        }
+
+        while let Some(frame) = self.frames.pop() {
+            base.budget().leave();
+
+            match frame {
+                Frame::Body { name } => {
+                    self.scopes.pop();
+                    error = error.with_frame(&name);
+                }
+                Frame::Wind { after, .. } => {
+                    // A limit, a restart or a jump goes on past the error of the thunk
+                    if let Err(failed) = call_procedure("dynamic-wind", &after, vec![], self.env(base)) {
+                        if is_catchable(&error) {
+                            error = failed;
+                        }
+                    }
+                }
+                Frame::Prompt {
+                    generator: Some(generator),
+                } => {
+                    generator.state.replace(GeneratorState::Done);
+                }
+                Frame::Guard { var, clauses } => {
+                    condition::pop_frame();
+                    if is_catchable(&error) {
+                        return match guard_clauses(&var, &clauses, error, self.env(base)) {
+                            Ok(value) => Ok(Next::Return(value)),
+                            Err(error) => self.recover(error, base),
+                        };
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(error)
+    }
+}
+
+/// Top-level forms of a sequence are expanded and evaluated one at a
+/// time, so a form can use the macros and definitions made before it
+fn eval_program(program: &Program, env: &mut Env) -> Result<Object, EvalError> {
+    match &program.body {
+        Object::List(forms) if is_sequence(forms) => {
+            let mut results = Vec::new();
+            for (form, span) in forms.iter().zip(&program.forms) {
+                let value = expand(form, env)
+                    .and_then(|core| eval_obj(&core, env))
+                    .map_err(|e| e.with_span(*span))?;
+
+                match value {
+                    Object::Void => {}
+                    result => results.push(result),
+                }
+            }
+            Ok(Object::List(results))
+        }
+        body => expand(body, env)
+            .and_then(|core| eval_obj(&core, env))
+            .map_err(|e| e.with_span(program.span)),
+    }
+}
+
+pub fn eval(program: &str, env: &mut Env) -> Result<Object, EvalError> {
+    let program = parse_program(program)?;
+
+    env.budget().start();
+
+    eval_program(&program, env)
+}
+
+/// Evaluate the program of a file, the files it loads are
+/// looked up from its directory
+pub fn eval_file(path: &Path, env: &mut Env) -> Result<Object, EvalError> {
+    env.budget().start();
+
+    in_file(path, env, |program, _, env| eval_program(program, env))
+}
+
+/// Parse and expand a program without evaluating it
+pub fn expand_source(program: &str, env: &mut Env) -> Result<Object, EvalError> {
+    let parsed = parse(program)?;
+
+    env.budget().start();
+    expand_program(&parsed, env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{Limits, DEFAULT_MAX_NESTING};
+    use crate::sandbox::Sandbox;
+    use std::time::Duration;
+
+    fn int_list(ns: &[i64]) -> Object {
+        Object::List(ns.iter().map(|n| Object::Integer(*n)).collect())
+    }
+
+    fn str_list(items: &[&str]) -> Object {
+        Object::List(items.iter().map(|s| Object::Str(s.to_string())).collect())
+    }
+
+    #[test]
+    fn test_simple_add() {
+        let mut env = Env::new();
+        let result = eval("(+ 1 2)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(3));
+    }
+
+    #[test]
+    fn test_area_of_a_circle() {
+        let mut env = Env::new();
+        let program = "(
+                        (define r 10)
+                        (define pi 314)
+                        (* pi (* r r))
+                      )";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Integer((314 * 10 * 10) as i64)])
+        );
+    }
+
+    #[test]
+    fn test_sqr_function() {
+        let mut env = Env::new();
+        let program = "(
+                        (define sqr (lambda (r) (* r r))) 
+                        (sqr 10)
+                       )";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Integer((10 * 10) as i64)])
+        );
+    }
+
+    #[test]
+    fn test_fibonacci() {
+        let mut env = Env::new();
+        let program = "
+            (
+                (define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))
+                (fib 10)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::List(vec![Object::Integer(89)]));
+    }
+
+    #[test]
+    fn test_factorial() {
+        let mut env = Env::new();
+        let program = "
+            (
+                (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
+                (fact 5)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::List(vec![Object::Integer(120)]));
+    }
+
+    #[test]
+    fn test_circle_area_function() {
+        let mut env = Env::new();
+        let program = "
+            (
+                (define pi 314)
+                (define r 10)
+                (define sqr (lambda (r) (* r r)))
+                (define area (lambda (r) (* pi (sqr r))))
+                (area r)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Integer((314 * 10 * 10) as i64)])
+        );
+    }
+
+    #[test]
+    fn test_print_correct_str() {
+        let mut env = Env::new();
+        let program = r#"
+            (
+                (define age 50)
+                (define old "Youre Old")
+                (define young "Youre Young")
+                (define res (lambda (age) (if (>= age 40) old young)))
+                (res 40)
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Str("Youre Old".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_concat_str() {
+        let mut env = Env::new();
+        let program = r#"
+            (
+                (define name "Midnight ")
+                (define phrase "esta fumado 🚬")
+                (concat name phrase)
+            )
+            "#;
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Str("Midnight esta fumado 🚬".to_string())])
+        );
+    }
+
+    #[test]
+    fn float_operations() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define PI 3.1416)
+                (define r 3)
+                (define area (lambda (r) (* PI (* r r))))
+                (area r)
+            )"#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(result, Object::List(vec![Object::Float(28.2744)]));
+    }
+
+    #[test]
+    fn negative_operations() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define debt -4000)
+                (define money 6000)
+                (+ money debt)
+            )"#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(result, Object::List(vec![Object::Integer(2000)]));
+    }
+
+    #[test]
+    fn equal_keyword() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define age 20)
+                (equal age 29)
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(result, Object::List(vec![Object::Bool(false)]));
+    }
+
+    #[test]
+    fn bool_variable() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define isCool true)
+                (if (isCool) "yeah it's so cool" "It's boring")
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Str("yeah it's so cool".to_string())])
+        );
+    }
+
+    #[test]
+    fn defmacro_my_unless() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (defmacro my-unless (c x y) `(if ,c ,y ,x))
+                (my-unless (> 1 2) "smaller" "bigger")
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Str("smaller".to_string())])
+        );
+    }
+
+    #[test]
+    fn macroexpand_rest_params() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (defmacro my-unless (c x y) `(if ,c ,y ,x))
+                (defmacro twice (f &rest args) `(,f (,f ,@args)))
+                (macroexpand-1 '(twice sqr 3))
+                (macroexpand '(my-unless c 1 2))
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![
+                parse("(sqr (sqr 3))").unwrap(),
+                parse("(if c 2 1)").unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn define_syntax_recursive_cond() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define-syntax my-cond
+                    (syntax-rules (else)
+                        ((_ (else e)) e)
+                        ((_ (c e) rest ...) (if c e (my-cond rest ...)))))
+                (define n 5)
+                (my-cond ((< n 0) "negative") ((< n 3) "small") (else "big"))
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(result, Object::List(vec![Object::Str("big".to_string())]));
+    }
+
+    #[test]
+    fn syntax_rules_hygiene() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define-syntax make-adder
+                    (syntax-rules () ((_ name n) (define name (lambda (x) (+ x n))))))
+                (define x 100)
+                (make-adder add-x x)
+                (add-x 1)
+                (let-syntax ((first (syntax-rules () ((_ a b ...) a)))) (first 7 8 9))
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Integer(101), Object::Integer(7)])
+        );
+    }
+
+    #[test]
+    fn syntax_cannot_shadow_keywords() {
+        let mut env = Env::new();
+
+        let result = eval("(define-syntax if (syntax-rules () ((_ a) a)))", &mut env);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn derived_forms() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define sum-to (lambda (n) (let loop ((i 0) (acc 0)) (if (> i n) acc (loop (+ i 1) (+ acc i))))))
+                (define sign (lambda (n) (cond ((< n 0) "negative") ((> n 0) "positive") (else "zero"))))
+                (sum-to 10)
+                (sign -3)
+                (let* ((a 2) (b (* a 3))) (+ a b))
+                (when (and (> 2 1) (or false true)) "both")
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![
+                Object::Integer(55),
+                Object::Str("negative".to_string()),
+                Object::Integer(8),
+                Object::Str("both".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn destructuring_parameters() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define add-pair (lambda ((x y) z) (+ (+ x y) z)))
+                (add-pair '(1 2) 3)
+                ((lambda ((a (b c) . rest)) `(,a ,b ,c ,rest)) '(1 (2 3) 4 5))
+                (let (((a b) '(10 20)) (c 3)) (+ (+ a b) c))
+                (let* (((a b) '(1 2)) ((c) `(,(+ a b)))) c)
+                (destructuring-bind (head . tail) '(1 2 3) tail)
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![
+                Object::Integer(6),
+                Object::List(vec![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    Object::Integer(3),
+                    Object::List(vec![Object::Integer(4), Object::Integer(5)]),
+                ]),
+                Object::Integer(33),
+                Object::Integer(3),
+                Object::List(vec![Object::Integer(2), Object::Integer(3)]),
+            ])
+        );
+
+        let error = eval("(add-pair '(1 2 3) 4)", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Arity("add-pair".to_string()));
+        let error = eval("(destructuring-bind (a b) '(1) a)", &mut env).unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::TypeMismatch {
+                expected: "a value matching the destructuring-bind pattern (a b)".to_string(),
+                found: "(1)".to_string()
+            }
+        );
+        let error = eval("(let (((a b) '(1 2 3))) a)", &mut env).unwrap_err();
+        assert_eq!(
+            error.kind.to_string(),
+            "Expected a value matching the let pattern (a b), found (1 2 3)"
+        );
+        let error = eval("(let ((x 1) ((a (b)) '(1 2))) a)", &mut env).unwrap_err();
+        assert!(error.to_string().contains("let pattern (a (b)), found (1 2)"), "{}", error);
+        assert!(eval("(add-pair 1 2)", &mut env).is_err());
+        assert!(eval("(lambda ((a . b c)) a)", &mut env).is_err());
+        assert!(eval("(letrec (((a b) '(1 2))) a)", &mut env).is_err());
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (call-with-values (lambda () (values 1 2)) (lambda (a b) (- a b)))
+                (let-values (((q r) (floor/ -7 2)) (all (values 1 2)) ((one) 1)) `(,q ,r ,all ,one))
+                (receive (first . rest) (values 1 2 3) rest)
+                (define-values (small large) (partition (lambda (n) (< n 3)) '(1 2 3 4)))
+                large
+                (values 5)
+            )
+        "#;
+
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(-1),
+                Object::List(vec![
+                    Object::Integer(-4),
+                    Object::Integer(1),
+                    int_list(&[1, 2]),
+                    Object::Integer(1),
+                ]),
+                int_list(&[2, 3]),
+                int_list(&[3, 4]),
+                Object::Integer(5),
+            ])
+        );
+        assert_eq!(env.get("small"), Some(int_list(&[1, 2])));
+
+        let result = eval("(floor/ 7 -2)", &mut env).unwrap();
+        assert_eq!(result, Object::Values(vec![Object::Integer(-4), Object::Integer(-1)]));
+        assert_eq!(result.to_string(), "-4 -1");
+        assert_eq!(eval("(values)", &mut env).unwrap(), Object::Values(vec![]));
+
+        let error = eval("(receive (a b) (values 1 2 3) a)", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Arity("let-values".to_string()));
+        assert_eq!(eval("(floor/ 1 0)", &mut env).unwrap_err().kind, ErrorKind::DivideByZero);
+    }
+
+    #[test]
+    fn tail_calls() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (let loop ((i 0)) (if (< i 20000) (loop (+ i 1)) i))
+                (define count (lambda (n acc) (if (< n 1) acc (begin (define m (- n 1)) (count m (+ acc 1))))))
+                (count 20000 0)
+            )
+        "#;
+
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![Object::Integer(20000), Object::Integer(20000)])
+        );
+    }
+
+    #[test]
+    fn continuations() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (define-record-type box (make-box value) box? (value box-value set-box-value!))
+                (define counter (make-box 0))
+                (define log (make-box ""))
+                (define note (lambda (s) (set-box-value! log (concat (box-value log) s))))
+                (define find
+                    (lambda (pred items)
+                        (call/cc (lambda (return)
+                            (let loop ((rest items))
+                                (if (equal rest '())
+                                    false
+                                    (destructuring-bind (head . tail) rest
+                                        (if (pred head) (return head) (loop tail)))))))))
+                (find (lambda (n) (< 2 n)) '(1 2 3 4))
+                (+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))
+                (call/cc (lambda (k) (guard (e (true 'caught)) (k 'escaped))))
+                (begin
+                    (define r (call/cc (lambda (k) k)))
+                    (set-box-value! counter (+ (box-value counter) 1))
+                    (if (< (box-value counter) 3) (r r) (box-value counter)))
+                (call/cc (lambda (k) (dynamic-wind (lambda () (note "before ")) (lambda () (k 1)) (lambda () (note "after ")))))
+                (box-value log)
+            )
+        "#;
+
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(3),
+                Object::Integer(3),
+                Object::Symbol("escaped".to_string()),
+                Object::Integer(3),
+                Object::Integer(1),
+                Object::Str("before after ".to_string()),
+            ])
+        );
+
+        let program = r#"
+            (
+                (set-box-value! counter 0)
+                (set-box-value! log "")
+                (begin
+                    (define again (dynamic-wind (lambda () (note "in ")) (lambda () (call/cc (lambda (k) k))) (lambda () (note "out "))))
+                    (set-box-value! counter (+ (box-value counter) 1))
+                    (if (< (box-value counter) 3) (again again) (box-value log)))
+            )
+        "#;
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::List(vec![Object::Str("in out in out in out ".to_string())])
+        );
+
+        // Going back into `equal`, `guard` or a quasiquote goes on with
+        // the rest of the form around them
+        let program = r#"
+            (
+                (define saved (make-box false))
+                (define jumped (make-box false))
+                (define keep (lambda (k) (begin (set-box-value! saved k) 1)))
+                (define jump
+                    (lambda (v)
+                        (if (box-value jumped)
+                            3
+                            (begin (set-box-value! jumped true) (define k (box-value saved)) (k v)))))
+                ((equal 10 (call/cc keep)) (jump 10))
+                (set-box-value! jumped false)
+                ((guard (e (true 0)) (+ 1 (call/cc keep))) (jump 10))
+                (set-box-value! jumped false)
+                (`(a ,(call/cc keep)) (jump 10))
+                (set-box-value! jumped false)
+                (guard (e (true (condition-message e))) (define-values (x) (call/cc keep)) (jump 10))
+            )
+        "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::List(vec![Object::Bool(true), Object::Integer(3)]),
+                Object::List(vec![Object::Integer(11), Object::Integer(3)]),
+                Object::List(vec![
+                    Object::List(vec![Object::Symbol("a".to_string()), Object::Integer(10)]),
+                    Object::Integer(3),
+                ]),
+                Object::Str("Continuation of a form that already returned".to_string()),
+            ])
+        );
+
+        eval("(begin (define k0 (call/cc (lambda (k) k))) 0)", &mut env).unwrap();
+        assert_eq!(eval("(begin (k0 10) 1)", &mut env).unwrap(), Object::Integer(0));
+        assert_eq!(env.get("k0"), Some(Object::Integer(10)));
+
+        let error = eval("(call/cc 3)", &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Expected a lambda, found 3");
+    }
+
+    #[test]
+    fn delimited_continuations() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (+ 1 (reset (+ 10 (shift k (k (k 100))))))
+                (reset (* 2 (shift k (+ (k 1) (k 10)))))
+                (reset (+ 1 (shift k 5)))
+                (define numbers (make-generator (lambda (n) (let loop ((i n)) (yield i) (loop (+ i 1)))) 5))
+                (generator->list numbers 3)
+                (numbers)
+                (define walk
+                    (lambda (tree)
+                        (match tree
+                            ((left right) (begin (walk left) (walk right)))
+                            (leaf (yield leaf)))))
+                (generator->list (make-generator walk '((1 2) (3 (4 5)))))
+            )
+        "#;
+
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(121),
+                Object::Integer(22),
+                Object::Integer(5),
+                int_list(&[5, 6, 7]),
+                Object::Integer(8),
+                int_list(&[1, 2, 3, 4, 5]),
+            ])
+        );
+
+        let program = r#"
+            (
+                (define g (make-generator (lambda () (yield 1) (yield 2))))
+                (g)
+                (generator-done? g)
+                (g)
+                (g)
+                (generator-done? g)
+                (g)
+            )
+        "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(1),
+                Object::Bool(false),
+                Object::Integer(2),
+                Object::Bool(true),
+            ])
+        );
+
+        // Pulling an item does not leave frames behind in the producer
+        let count = crate::limits::DEFAULT_MAX_DEPTH + 10;
+        let program = format!(
+            "(generator->list (make-generator (lambda () (let loop ((i 1)) (yield i) (loop (+ i 1))))) {})",
+            count
+        );
+        let numbers: Vec<i64> = (1..=count as i64).collect();
+        assert_eq!(eval(&program, &mut env).unwrap(), int_list(&numbers));
+
+        let error = eval("(yield 1)", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Control("yield outside of a generator".to_string()));
+        let error = eval("(shift k 1)", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Control("shift outside of a reset".to_string()));
+        let error = eval("((define r (make-generator (lambda () (r)))) (r))", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Control("Generator r is already running".to_string()));
+    }
+
+    #[test]
+    fn promises_and_streams() {
+        let mut env = Env::new();
+        env.set_limits(Limits {
+            max_steps: None,
+            max_depth: Some(200),
+            max_nesting: None,
+            max_time: None,
+            max_allocation: None,
+        });
+
+        let program = r#"
+            (
+                (define-record-type box (make-box value) box? (value box-value set-box-value!))
+                (define forced (make-box 0))
+                (define make-p (lambda (x) (delay (begin (set-box-value! forced (+ (box-value forced) 1)) (+ x 1)))))
+                (define p (make-p 41))
+                (force p)
+                (force p)
+                (box-value forced)
+                (define countdown (lambda (n) (delay-force (if (< n 1) (delay 'done) (countdown (- n 1))))))
+                (force (countdown 2000))
+                (define ints (lambda (n) (stream-cons n (ints (+ n 1)))))
+                (stream-take (stream-map (lambda (n) (* n n)) (ints 1)) 4)
+                (stream-take (stream-filter (lambda (n) (< 1000 n)) (ints 0)) 2)
+                (stream-car (stream-cdr (ints 7)))
+                (stream-take (stream-cons 1 '()) 5)
+                (force (make-promise 3))
+                (force 4)
+            )
+        "#;
+
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(42),
+                Object::Integer(42),
+                Object::Integer(1),
+                Object::Symbol("done".to_string()),
+                int_list(&[1, 4, 9, 16]),
+                int_list(&[1001, 1002]),
+                Object::Integer(8),
+                int_list(&[1]),
+                Object::Integer(3),
+                Object::Integer(4),
+            ])
+        );
+
+        // Only one item of the filter exists, its rest is never forced
+        env.set_limits(Limits {
+            max_steps: Some(100_000),
+            ..Limits::default()
+        });
+        let program = "(stream-take (stream-filter (lambda (x) (equal x 3)) (ints 0)) 1)";
+        assert_eq!(eval(program, &mut env).unwrap(), int_list(&[3]));
+        env.set_limits(Limits::default());
+
+        let error = eval("(stream-car '())", &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Expected a non-empty stream, found ()");
+        let error = eval("(force (delay-force 1))", &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Expected a promise, found 1");
+    }
+
+    #[test]
+    fn string_library() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (string-append "a" "b" "c")
+                (substring "héllo wörld" 1 4)
+                (string-split "a,b,,c" ",")
+                (string-split "  one two ")
+                (string-join '("x" "y" "z") ", ")
+                (string-trim "  hi  ")
+                (string-upcase "straße")
+                (string-contains "héllo" "llo")
+                (string-contains "abc" "z")
+                (string-replace "a-b-c" "-" "+")
+                (string-index "abc1" (lambda (c) (string<? "/" c ":")))
+                (string->number "ff" 16)
+                (string->number "-3.5e2")
+                (string->number "nan")
+                (number->string -255 2)
+                (string<? "a" "b" "c")
+                (string-ci=? "HeLLo" "hello")
+            )
+        "#;
+
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Str("abc".to_string()),
+                Object::Str("éll".to_string()),
+                str_list(&["a", "b", "", "c"]),
+                str_list(&["one", "two"]),
+                Object::Str("x, y, z".to_string()),
+                Object::Str("hi".to_string()),
+                Object::Str("STRASSE".to_string()),
+                Object::Integer(2),
+                Object::Bool(false),
+                Object::Str("a+b+c".to_string()),
+                Object::Integer(3),
+                Object::Integer(255),
+                Object::Float(-350.0),
+                Object::Bool(false),
+                Object::Str("-11111111".to_string()),
+                Object::Bool(true),
+                Object::Bool(true),
+            ])
+        );
+
+        // Lengths and indices count chars, an accent that combines with
+        // the letter before it is a char of its own but not a grapheme
+        let decomposed = "e\u{301}t\u{e9}";
+        let program = format!(
+            r#"((string-length "{0}") (substring "{0}" 0 1) (string-graphemes "{0}") (string-index "{0}" "t"))"#,
+            decomposed
+        );
+        assert_eq!(
+            eval(&program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(4),
+                Object::Str("e".to_string()),
+                str_list(&["e\u{301}", "t", "\u{e9}"]),
+                Object::Integer(2),
+            ])
+        );
+
+        let error = eval(r#"(substring "abc" 2 9)"#, &mut env).unwrap_err();
+        assert_eq!(error.kind.name(), "domain-error");
+        let error = eval("(number->string 10 1)", &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Radix 1 is not from 2 to 36");
+        let error = eval(r#"(string-length 5)"#, &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Expected a string, found 5");
+    }
+
+    #[test]
+    fn format() {
+        let mut env = Env::new();
+
+        let program = r#"(format false "~a: ~5,'0d ~{~a~^, ~}" "cart" 42 '("x" "y"))"#;
+        assert_eq!(eval(program, &mut env).unwrap(), Object::Str("cart: 00042 x, y".to_string()));
+        assert_eq!(eval(r#"(format true "~a~%" 1)"#, &mut env).unwrap(), Object::Void);
+        let error = eval(r#"(format "out" "~a" 1)"#, &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Expected true, false or a port, found \"out\"");
+        let program = r#"
+            ((define out (open-output-string)) (format out "~a-" 1) (format out "~d" 2) (get-output-string out))
+        "#;
+        assert_eq!(eval(program, &mut env).unwrap(), Object::List(vec![Object::Str("1-2".to_string())]));
+
+        // The text written into a port is charged once, as the returned one
+        let mut charged = Vec::new();
+        for destination in ["out", "false"] {
+            let program = format!(r#"(begin (define out (open-output-string)) (format {} "~1000a" "") 0)"#, destination);
+            eval(&program, &mut env).unwrap();
+            charged.push(env.bytes_allocated());
+        }
+        assert_eq!(charged[0], charged[1]);
+    }
+
+    #[test]
+    fn regular_expressions() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (regex-search #rx"(\d+)-(\d+)" "pages 10-20 and 30-40")
+                (regex-search #rx"x(y)?" "x")
+                (regex-match #rx"a|ab" "ab")
+                (regex-match "\d+" "12x")
+                (regex-match-all #rx"\d+" "a1 b22 c333")
+                (regex-match-all #rx"(\w)(\d)" "a1 b2")
+                (regex-replace #rx"(\w+)@(\w+)" "bob@home ann@work" "\2:\1")
+                (regex-replace-all #rx"(?P<user>\w+)@(\w+)" "bob@home ann@work" "${user} at $2")
+                (regex-split #rx"\s*,\s*" "a , b,c")
+                (regex-named-groups #rx"(?P<year>\d{4})-(?P<month>\d\d)(-(?P<day>\d\d))?" "on 2024-05")
+                (equal #rx"a+" (regex "a+"))
+            )
+        "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                str_list(&["10-20", "10", "20"]),
+                Object::List(vec![Object::Str("x".to_string()), Object::Bool(false)]),
+                str_list(&["ab"]),
+                Object::Bool(false),
+                str_list(&["1", "22", "333"]),
+                Object::List(vec![str_list(&["a1", "a", "1"]), str_list(&["b2", "b", "2"])]),
+                Object::Str("home:bob ann@work".to_string()),
+                Object::Str("bob at home ann at work".to_string()),
+                str_list(&["a", "b", "c"]),
+                Object::List(vec![
+                    str_list(&["year", "2024"]),
+                    str_list(&["month", "05"]),
+                    Object::List(vec![Object::Str("day".to_string()), Object::Bool(false)]),
+                ]),
+                Object::Bool(true),
+            ])
+        );
+
+        // The parens of a regex literal are not the ones of a list
+        assert_eq!(eval(r#"(print #rx"(a)")"#, &mut env).unwrap(), Object::Void);
+        // A trailing (?x) comment does not swallow the anchors of regex-match
+        let program = r#"((regex-match #rx"(?x)a # c" "a"))"#;
+        assert_eq!(eval(program, &mut env).unwrap(), Object::List(vec![str_list(&["a"])]));
+        let error = eval(r#"(regex-search #rx"(a" "a")"#, &mut env).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Parse(_)));
+        let error = eval(r#"(regex-search 1 "a")"#, &mut env).unwrap_err();
+        assert_eq!(error.kind.to_string(), "Expected a regex, found 1");
+    }
+
+    #[test]
+    fn expand_source_has_no_macros() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (defmacro my-unless (c x y) `(if ,c ,y ,x))
+                (define f (lambda (x) (my-unless (> x 0) "neg" (let ((y x)) y))))
+            )
+        "#;
+
+        let expanded = expand_source(program, &mut env).unwrap();
+
+        assert_eq!(
+            expanded,
+            parse(r#"((define f (lambda (x) (if (> x 0) ((lambda (y) (begin y)) x) "neg"))))"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn error_kind_span_and_backtrace() {
+        let mut env = Env::new();
+
+        let program = "(
+            (define inner (lambda (x) (+ x missing)))
+            (define outer (lambda (x) (+ 1 (inner x))))
+            (outer 1)
+        )";
+
+        let error = eval(program, &mut env).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::UnboundSymbol("missing".to_string()));
+        assert_eq!(error.span, Some(crate::error::Span { line: 4, col: 13 }));
+        assert_eq!(error.backtrace, vec!["inner".to_string(), "outer".to_string()]);
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut env = Env::new();
+
+        let arity = eval("((define f (lambda (a b) a)) (f 1))", &mut env).unwrap_err();
+        let mismatch = eval(r#"(+ 1 "two")"#, &mut env).unwrap_err();
+        let zero = eval("(/ 1 0)", &mut env).unwrap_err();
+        let parse = eval("(+ 1 2", &mut env).unwrap_err();
+
+        assert_eq!(arity.kind, ErrorKind::Arity("f".to_string()));
+        assert_eq!(
+            mismatch.kind,
+            ErrorKind::TypeMismatch {
+                expected: "a number".to_string(),
+                found: "\"two\"".to_string(),
+            }
+        );
+        assert_eq!(zero.kind, ErrorKind::DivideByZero);
+        assert!(matches!(parse.kind, ErrorKind::Parse(_)));
+    }
+
+    #[test]
+    fn guard_catches_errors() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (guard (e ((condition? e) (condition-irritants e)))
+                    (error "Bad value" 1 2))
+                (guard (e ((equal (condition-kind e) 'divide-by-zero) "zero") (else "other"))
+                    (/ 10 0))
+                (guard (e ((equal e 42) "answer"))
+                    (raise 42))
+                (guard (e (true (condition-message e)))
+                    (+ 1 2))
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![
+                Object::List(vec![Object::Integer(1), Object::Integer(2)]),
+                Object::Str("zero".to_string()),
+                Object::Str("answer".to_string()),
+                Object::Integer(3),
+            ])
+        );
+    }
+
+    #[test]
+    fn guard_raises_again_without_matching_clause() {
+        let mut env = Env::new();
+
+        let error = eval("(guard (e (false 1)) (undefined-thing))", &mut env).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::UnboundSymbol("undefined-thing".to_string()));
+    }
+
+    #[test]
+    fn dynamic_wind_runs_after_thunk() {
+        let mut env = Env::new();
+
+        let program = r#"
+            (
+                (dynamic-wind (lambda () 1) (lambda () "body") (lambda () 3))
+                (guard (e (true (condition-message e)))
+                    (dynamic-wind
+                        (lambda () 1)
+                        (lambda () (error "from body"))
+                        (lambda () (error "from after"))))
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+
+        assert_eq!(
+            result,
+            Object::List(vec![
+                Object::Str("body".to_string()),
+                Object::Str("from after".to_string()),
+            ])
+        );
     }
 
-    eval_obj(&parsed_list.unwrap(), env)
-}
+    #[test]
+    fn handler_bind_use_value() {
+        let mut env = Env::new();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let program = r#"
+            (handler-bind ((divide-by-zero (lambda (c) (invoke-restart 'use-value 10))))
+                (+ 1 (/ 1 0)))
+        "#;
+
+        assert_eq!(eval(program, &mut env).unwrap(), Object::Integer(11));
+    }
 
     #[test]
-    fn test_simple_add() {
+    fn restart_case_invoked_by_handler() {
         let mut env = Env::new();
-        let result = eval("(+ 1 2)", &mut env).unwrap();
-        assert_eq!(result, Object::Integer(3));
+
+        let program = r#"
+            (
+                (define parse-num (lambda (x)
+                    (restart-case (error "Bad number" x)
+                        (use-default () 0)
+                        (use-other (v) v))))
+                (handler-bind ((error (lambda (c) (invoke-restart 'use-other 7))))
+                    (+ 1 (parse-num 3)))
+                (handler-bind ((error (lambda (c) (invoke-restart 'use-value (compute-restarts)))))
+                    (parse-num 3))
+            )
+        "#;
+
+        let result = eval(program, &mut env).unwrap();
+        let restarts = ["use-value", "use-default", "use-other"]
+            .iter()
+            .map(|r| Object::Symbol(r.to_string()))
+            .collect();
+
+        assert_eq!(result, Object::List(vec![Object::Integer(8), Object::List(restarts)]));
     }
 
     #[test]
-    fn test_area_of_a_circle() {
+    fn handlers_decline_and_guard_comes_first() {
         let mut env = Env::new();
-        let program = "(
-                        (define r 10)
-                        (define pi 314)
-                        (* pi (* r r))
-                      )";
+
+        let program = r#"
+            (
+                (handler-bind ((error (lambda (c) (invoke-restart 'use-value "outer"))))
+                    (handler-bind ((warning (lambda (c) (invoke-restart 'use-value "warning")))
+                                   (condition (lambda (c) "declined")))
+                        (/ 1 0)))
+                (handler-bind ((error (lambda (c) (invoke-restart 'use-value "outer"))))
+                    (guard (e (true "guard")) (/ 1 0)))
+                (handler-bind ((error (lambda (c) (invoke-restart 'use-value "outer"))))
+                    (guard (e (false "guard")) (/ 1 0)))
+                (handler-bind ((note (lambda (c) (invoke-restart 'noted (condition-message c)))))
+                    (restart-case (signal (make-condition 'note "Just a note"))
+                        (noted (message) message)))
+                (signal (make-condition 'note "Nobody listens"))
+            )
+        "#;
+
         let result = eval(program, &mut env).unwrap();
+
         assert_eq!(
             result,
-            Object::List(vec![Object::Integer((314 * 10 * 10) as i64)])
+            Object::List(vec![
+                Object::Str("outer".to_string()),
+                Object::Str("guard".to_string()),
+                Object::Str("outer".to_string()),
+                Object::Str("Just a note".to_string()),
+            ])
         );
     }
 
     #[test]
-    fn test_sqr_function() {
+    fn invoke_restart_without_restart() {
         let mut env = Env::new();
-        let program = "(
-                        (define sqr (lambda (r) (* r r))) 
-                        (sqr 10)
-                       )";
-        let result = eval(program, &mut env).unwrap();
+
+        let error = eval("(invoke-restart 'nowhere)", &mut env).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::Control("No active restart named nowhere".to_string()));
+    }
+
+    #[test]
+    fn integer_arithmetic_errors() {
+        let mut env = Env::new();
+
+        let kind = |program: &str, env: &mut Env| eval(program, env).unwrap_err().kind;
+
+        assert_eq!(kind("(/ 1 0)", &mut env), ErrorKind::DivideByZero);
+        assert_eq!(kind("(* 9223372036854775807 2)", &mut env), ErrorKind::Overflow("*".to_string()));
+        assert_eq!(kind("(^ 10 40)", &mut env), ErrorKind::Overflow("^".to_string()));
+        assert_eq!(kind("(^ 2 4294967296)", &mut env).to_string(), "Exponent 4294967296 is too large");
         assert_eq!(
-            result,
-            Object::List(vec![Object::Integer((10 * 10) as i64)])
+            kind("(^ 2 -1)", &mut env).to_string(),
+            "Negative integer exponent -1, use a float base"
         );
+        assert_eq!(eval("(^ 2.0 -1)", &mut env).unwrap(), Object::Float(0.5));
     }
 
     #[test]
-    fn test_fibonacci() {
+    fn strict_float_mode() {
         let mut env = Env::new();
-        let program = "
+
+        assert_eq!(eval("(/ 1.0 0)", &mut env).unwrap(), Object::Float(f64::INFINITY));
+
+        let program = r#"
             (
-                (define fib (lambda (n) (if (< n 2) 1 (+ (fib (- n 1)) (fib (- n 2))))))
-                (fib 10)
+                (define *strict-float* true)
+                (guard (e (true (condition-kind e))) (/ 1.0 0))
+                (guard (e (true (condition-kind e))) (^ -8.0 0.5))
+                (* 1.5 2)
             )
-        ";
+        "#;
 
-        let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::List(vec![Object::Integer(89)]));
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Symbol("divide-by-zero".to_string()),
+                Object::Symbol("domain-error".to_string()),
+                Object::Float(3.0),
+            ])
+        );
     }
 
     #[test]
-    fn test_factorial() {
+    fn malformed_programs_are_errors() {
+        let programs = [
+            "",
+            "(",
+            ")(",
+            "\"unterminated",
+            "'",
+            "(')",
+            "(,@x)",
+            "(1 2 . )",
+            "((lambda))",
+            "((lambda (x)))",
+            "(lambda x x)",
+            "(define)",
+            "(define 1 2)",
+            "(if)",
+            "(if 1 2 3)",
+            "(print)",
+            "(load)",
+            "(load 1)",
+            "(load \"missing-file.lisp\")",
+            "(load \"file.txt\")",
+            "(quote)",
+            "(quasiquote)",
+            "(unquote x)",
+            "`(,@1)",
+            "(defmacro)",
+            "(defmacro if (x) x)",
+            "(macroexpand)",
+            "(define-syntax m)",
+            "((define-syntax m (syntax-rules () ((_ x) (x ...)))) (m 1))",
+            "((define-syntax m (syntax-rules () ((_ x) x))) (m))",
+            "(let ((x)) x)",
+            "(let x)",
+            "(cond (1))",
+            "(guard)",
+            "(guard (1) 2)",
+            "(handler-bind ((1 2)) 3)",
+            "(restart-case)",
+            "(restart-case 1 (2))",
+            "(invoke-restart 1)",
+            "(make-condition 1 2)",
+            "(dynamic-wind 1 2 3)",
+            "(+ 1)",
+            "(+ \"a\" 1)",
+            "(concat (lambda (x) x) 1)",
+            "(undefined-function 1)",
+        ];
+
+        for program in programs {
+            let mut env = Env::new();
+            assert!(eval(program, &mut env).is_err(), "{} should fail", program);
+        }
+    }
+
+    #[test]
+    fn keywords_with_any_arguments_do_not_panic() {
+        let operators = [
+            "+",
+            "^",
+            "concat",
+            "error",
+            "raise",
+            "signal",
+            "make-condition",
+            "invoke-restart",
+            "yield",
+            "make-generator",
+            "generator->list",
+            "force",
+            "stream-take",
+            "stream-filter",
+            "substring",
+            "string-index",
+            "number->string",
+            "string<?",
+            "format",
+            "get-output-string",
+            "regex",
+            "regex-match",
+            "regex-replace",
+        ];
+        let arguments = ["", "1", "x", "()", "(1)", "1 2", "x y z", "(x 1) x", "((x 1)) x", "\"s\"", "'x"];
+
+        for head in KEYWORDS.iter().chain(operators.iter()) {
+            for args in arguments {
+                let mut env = Env::new();
+                let _ = eval(&format!("({} {})", head, args), &mut env);
+            }
+        }
+    }
+
+    #[test]
+    fn resource_limits() {
+        let limited = |limits: Limits, program: &str| {
+            let mut env = Env::new();
+            env.set_limits(limits);
+            eval(program, &mut env)
+        };
+        // The test threads have small stacks
+        let shallow = Limits {
+            max_steps: None,
+            max_depth: Some(300),
+            max_nesting: None,
+            max_time: None,
+            max_allocation: None,
+        };
+        let runaway = "((define f (lambda (n) (f (+ n 1)))) (f 0))";
+        let deep = "((define g (lambda (n) (+ 1 (g n)))) (g 0))";
+        let exponential = "((define h (lambda (n) (if (< n 1) 0 (+ (h (- n 1)) (h (- n 1)))))) (h 40))";
+
+        let steps = limited(Limits { max_steps: Some(200), ..shallow }, runaway).unwrap_err();
+        let depth = limited(Limits { max_depth: Some(100), ..shallow }, deep).unwrap_err();
+        let time = limited(Limits { max_time: Some(Duration::ZERO), ..shallow }, exponential).unwrap_err();
+
+        assert_eq!(steps.kind, ErrorKind::ResourceLimit("more than 200 steps".to_string()));
+        assert_eq!(depth.kind, ErrorKind::ResourceLimit("depth greater than 100".to_string()));
+        assert!(matches!(time.kind, ErrorKind::ResourceLimit(_)));
+    }
+
+    #[test]
+    fn nested_evaluations_fit_a_main_thread() {
+        // The default limits keep the evaluations that the interpreter
+        // nests on the native stack within the 8 MB of a main thread
+        let programs = [
+            "((defmethod down ((n Integer)) (if (< n 1) 0 (+ 1 (down (- n 1))))) (down 100000))",
+            "((define f (lambda (n) (if (< n 1) 0 (+ 1 (force (delay (f (- n 1)))))))) (f 100000))",
+        ];
+        let main_thread = std::thread::Builder::new().stack_size(8 * 1024 * 1024);
+        let errors = main_thread
+            .spawn(move || programs.map(|program| eval(program, &mut Env::new()).unwrap_err().kind.to_string()))
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let nested = ErrorKind::ResourceLimit(format!("more than {} nested evaluations", DEFAULT_MAX_NESTING));
+        assert_eq!(errors, [nested.to_string(), nested.to_string()]);
+    }
+
+    #[test]
+    fn resource_limits_are_not_handled() {
         let mut env = Env::new();
-        let program = "
+        env.set_limits(Limits {
+            max_steps: Some(200),
+            max_depth: Some(100),
+            max_nesting: None,
+            max_time: None,
+            max_allocation: None,
+        });
+
+        let program = r#"
             (
-                (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
-                (fact 5)
+                (define f (lambda (n) (f (+ n 1))))
+                (guard (e (true "caught")) (f 0))
             )
-        ";
+        "#;
 
-        let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::List(vec![Object::Integer(120)]));
+        let error = eval(program, &mut env).unwrap_err();
+        assert_eq!(error.kind.name(), "resource-limit");
+
+        // An after thunk that fails does not turn the limit into an error
+        // the guard can handle
+        env.set_limits(Limits {
+            max_depth: Some(200),
+            ..Limits::default()
+        });
+        let program = r#"
+            (
+                (define g (lambda (n) (+ 1 (g n))))
+                (guard (e (true 'caught))
+                    (dynamic-wind (lambda () 0) (lambda () (g 1)) (lambda () (raise 'after))))
+            )
+        "#;
+        let error = eval(program, &mut env).unwrap_err();
+        assert_eq!(error.kind.name(), "resource-limit");
+
+        // Every evaluation starts with the whole budget
+        assert_eq!(eval("(+ 1 2)", &mut env).unwrap(), Object::Integer(3));
     }
 
     #[test]
-    fn test_circle_area_function() {
+    fn allocation_limit() {
         let mut env = Env::new();
-        let program = "
+        env.set_limits(Limits {
+            max_allocation: Some(1 << 20),
+            ..Limits::default()
+        });
+
+        let program = r#"
             (
-                (define pi 314)
-                (define r 10)
-                (define sqr (lambda (r) (* r r)))
-                (define area (lambda (r) (* pi (sqr r))))
-                (area r)
+                (define s (concat "hello" " world"))
+                (bytes-allocated)
             )
-        ";
+        "#;
 
-        let result = eval(program, &mut env).unwrap();
+        let usage = match eval(program, &mut env).unwrap() {
+            Object::List(values) => values[0].clone(),
+            other => other,
+        };
+        assert!(matches!(usage, Object::Integer(n) if n > 11));
+        assert_eq!(usage, Object::Integer(env.bytes_allocated() as i64));
+
+        let error = eval("((define grow (lambda (s) (grow (concat s s)))) (grow \"ab\"))", &mut env).unwrap_err();
         assert_eq!(
-            result,
-            Object::List(vec![Object::Integer((314 * 10 * 10) as i64)])
+            error.kind,
+            ErrorKind::ResourceLimit(format!("more than {} bytes allocated", 1 << 20))
         );
-    }
 
-    #[test]
-    fn test_print_correct_str() {
-        let mut env = Env::new();
+        // A call is charged for its scope, not for the bindings it sees
         let program = r#"
             (
-                (define age 50)
-                (define old "Youre Old")
-                (define young "Youre Young")
-                (define res (lambda (age) (if (>= age 40) old young)))
-                (res 40)
+                (define big (format false "~4000a" "x"))
+                (define count (lambda (n) (if (< n 1) 'done (count (- n 1)))))
+                (count 2000)
             )
         "#;
-
         let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::List(vec![Object::Symbol("done".to_string())]));
+
+        // A replacement is charged before it is made, this one takes 4 GB
+        let program = r#"
+            (
+                (define s (format false "~65536,'aa" ""))
+                (string-replace s "a" s)
+            )
+        "#;
+        let error = eval(program, &mut env).unwrap_err();
         assert_eq!(
-            result,
-            Object::List(vec![Object::Str("Youre Old".to_string())])
+            error.kind,
+            ErrorKind::ResourceLimit(format!("more than {} bytes allocated", 1 << 20))
         );
+        let program = "((define s (format false \"~65536,'aa\" \"\")) (string-join `(,s ,s ,s ,s ,s ,s ,s ,s ,s) s))";
+        assert!(matches!(eval(program, &mut env).unwrap_err().kind, ErrorKind::ResourceLimit(_)));
+
+        // A regex replacement is charged as it is written, this one takes 400 MB
+        let program = "((define s (format false \"~20000,'aa\" \"\")) (regex-replace-all #rx\"\" s s))";
+        assert!(matches!(eval(program, &mut env).unwrap_err().kind, ErrorKind::ResourceLimit(_)));
+        assert!(env.bytes_allocated() <= 1 << 20);
     }
 
     #[test]
-    fn test_concat_str() {
+    fn sandbox_load_directories() {
+        let dir = std::env::temp_dir().join(format!("lisp-sandbox-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(lib.join("inside.lisp"), "((define inside 1))").unwrap();
+        std::fs::write(dir.join("outside.lisp"), "((define outside 2))").unwrap();
+
+        let mut env = Env::new();
+        env.set_sandbox(Sandbox::deny_all().allow_load_dir(&lib));
+
+        let inside = format!("((load \"{}\") inside)", lib.join("inside.lisp").display());
+        let outside = format!("(load \"{}\")", lib.join("../outside.lisp").display());
+        let caught = format!("(guard (e (true (condition-kind e))) (load \"{}\"))", dir.join("outside.lisp").display());
+
+        assert_eq!(
+            eval(&inside, &mut env).unwrap(),
+            Object::List(vec![Object::List(vec![Object::Symbol("inside".to_string())]), Object::Integer(1)])
+        );
+        assert_eq!(eval(&outside, &mut env).unwrap_err().kind.name(), "permission-denied");
+        assert_eq!(eval(&caught, &mut env).unwrap(), Object::Symbol("permission-denied".to_string()));
+
+        // Outside of the directories a missing file is denied like one that exists
+        let missing = format!("(load \"{}\")", dir.join("missing.lisp").display());
+        assert_eq!(eval(&missing, &mut env).unwrap_err().kind.name(), "permission-denied");
+        let missing = format!("(load \"{}\")", lib.join("missing.lisp").display());
+        assert!(eval(&missing, &mut env).unwrap_err().to_string().contains("not found"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn modules() {
         let mut env = Env::new();
         let program = r#"
             (
-                (define name "Midnight ")
-                (define phrase "esta fumado 🚬")
-                (concat name phrase)
+                (module counter (export next twice)
+                    (define step 1)
+                    (define next (lambda (n) (+ n step)))
+                    (define twice (lambda (n) (next (next n)))))
+                (define step 10)
+                (import counter)
+                (next 1)
+                (twice 1)
+                (counter:next 5)
+                step
             )
-            "#;
+        "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(2),
+                Object::Integer(3),
+                Object::Integer(6),
+                Object::Integer(10),
+            ])
+        );
+        // The private definitions of a module are bound under a name
+        // that source code can not write, nor import or replace
+        let private = crate::module::private("counter", "step");
+        assert_eq!(env.get(&private), Some(Object::Integer(1)));
+        let error = eval(&format!("({})", private), &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnboundSymbol("counter".to_string()));
+        assert!(eval("(import (only counter step))", &mut env).is_err());
+        let program = "((define counter%step 100) (define counter:step 100) (next 1))";
+        assert_eq!(eval(program, &mut env).unwrap(), Object::List(vec![Object::Integer(2)]));
+        assert_eq!(env.get(&private), Some(Object::Integer(1)));
 
-        let result = eval(program, &mut env).unwrap();
+        let mut env = Env::new();
+        let program = r#"
+            (
+                (module shapes (export area perimeter)
+                    (define area (lambda (w h) (* w h)))
+                    (define perimeter (lambda (w h) (* 2 (+ w h)))))
+                (import (prefix-in s/ (only shapes area)))
+                (import (rename (except shapes area) (perimeter around)))
+                (s/area 2 3)
+                (around 2 3)
+            )
+        "#;
         assert_eq!(
-            result,
-            Object::List(vec![Object::Str("Midnight esta fumado 🚬".to_string())])
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![Object::Integer(6), Object::Integer(10)])
         );
+        assert!(eval("area", &mut env).is_err());
+        assert!(eval("perimeter", &mut env).is_err());
+        assert!(eval("(import (only shapes volume))", &mut env).is_err());
+        assert!(eval("(module broken (export missing) (define other 1))", &mut env).is_err());
+
+        let program = r#"
+            (
+                (module squares (export area make-square)
+                    (defclass square () ((side :accessor side)))
+                    (define make-square (lambda (n) (make-instance 'square :side n)))
+                    (defmethod area ((s square)) (* (side s) (side s))))
+                (import squares)
+                (area (make-square 4))
+            )
+        "#;
+        assert_eq!(eval(program, &mut env).unwrap(), Object::List(vec![Object::Integer(16)]));
+        assert!(eval("(side (make-square 1))", &mut env).is_err());
     }
 
     #[test]
-    fn float_operations() {
+    fn modules_from_files() {
+        let dir = std::env::temp_dir().join(format!("lisp-modules-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("greet.lisp");
+        let module = |greeting: &str| {
+            format!(
+                "((define top 1) (module greet (export hello) (define hello (lambda (name) (concat \"{}\" name)))))",
+                greeting
+            )
+        };
+        std::fs::write(&file, module("hello ")).unwrap();
+
         let mut env = Env::new();
+        let import = format!("((import \"{}\") (hello \"you\"))", file.display());
+        let same_file = dir.join("..").join(dir.file_name().unwrap()).join("greet.lisp");
+        let again = format!("((import (prefix-in g/ \"{}\")) (g/hello \"you\"))", same_file.display());
 
-        let program = r#"
-            (
-                (define PI 3.1416)
-                (define r 3)
-                (define area (lambda (r) (* PI (* r r))))
-                (area r)
-            )"#;
+        assert_eq!(
+            eval(&import, &mut env).unwrap(),
+            Object::List(vec![Object::Str("hello you".to_string())])
+        );
 
-        let result = eval(program, &mut env).unwrap();
+        // The file is evaluated once, by its canonical path
+        std::fs::write(&file, module("bye ")).unwrap();
+        assert_eq!(
+            eval(&again, &mut env).unwrap(),
+            Object::List(vec![Object::Str("hello you".to_string())])
+        );
+        assert!(env.get("top").is_none());
 
-        assert_eq!(result, Object::List(vec![Object::Float(28.2744)]));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn negative_operations() {
+    fn load_path_and_require() {
+        let dir = std::env::temp_dir().join(format!("lisp-load-path-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(lib.join("util.lisp"), "((define loads (+ loads 1)))").unwrap();
+        std::fs::write(lib.join("ext.lisp"), "((define ext 'lisp))").unwrap();
+        std::fs::write(lib.join("ext.cl"), "((define ext 'cl))").unwrap();
+        std::fs::write(dir.join("main.lisp"), "((load \"helper\") (helper))").unwrap();
+        std::fs::write(dir.join("helper.cl"), "((define helper (lambda () 'helped)))").unwrap();
+        std::fs::write(dir.join("ping.lisp"), "((load \"pong\"))").unwrap();
+        std::fs::write(dir.join("pong.lisp"), "((load \"ping\"))").unwrap();
+
         let mut env = Env::new();
+        env.set(LOAD_PATH, Object::List(vec![Object::Str(lib.display().to_string())]));
+        env.set("loads", Object::Integer(0));
 
-        let program = r#"
-            (
-                (define debt -4000)
-                (define money 6000)
-                (+ money debt)
-            )"#;
+        eval("((require 'util) (require \"util\") (require 'util.lisp))", &mut env).unwrap();
+        assert_eq!(env.get("loads"), Some(Object::Integer(1)));
+        eval("(load \"util\")", &mut env).unwrap();
+        assert_eq!(env.get("loads"), Some(Object::Integer(2)));
 
-        let result = eval(program, &mut env).unwrap();
+        // `.lisp` is preferred, an extension picks the file
+        eval("(load \"ext\")", &mut env).unwrap();
+        assert_eq!(env.get("ext"), Some(Object::Symbol("lisp".to_string())));
+        eval("(load \"ext.cl\")", &mut env).unwrap();
+        assert_eq!(env.get("ext"), Some(Object::Symbol("cl".to_string())));
 
-        assert_eq!(result, Object::List(vec![Object::Integer(2000)]));
+        // Relative to the file being loaded, not to the current directory
+        assert_eq!(
+            eval_file(&dir.join("main.lisp"), &mut env).unwrap(),
+            Object::List(vec![
+                Object::List(vec![Object::Symbol("helper".to_string())]),
+                Object::Symbol("helped".to_string())
+            ])
+        );
+
+        let error = eval_file(&dir.join("ping.lisp"), &mut env).unwrap_err();
+        assert!(error.to_string().contains("Circular load of"), "{}", error);
+        assert_eq!(env.modules().borrow().current_file(), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn equal_keyword() {
+    fn load_reports_the_failing_form() {
+        let dir = std::env::temp_dir().join(format!("lisp-load-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("broken.lisp");
+        std::fs::write(&file, "(\n  (define a 1)\n  (+ a missing)\n  (define b 2)\n)").unwrap();
+        std::fs::write(dir.join("parse.lisp"), "(\n  (define a (+ 1 2)\n)").unwrap();
+        std::fs::write(dir.join("values.lisp"), "((define a 1) (defmacro m (x) `(+ ,x 1)) (m a) (print \"\"))").unwrap();
+        std::fs::write(dir.join("forms.lisp"), "(define x 1)\n(define y 2)\n(+ x y)").unwrap();
+
         let mut env = Env::new();
+        env.set(LOAD_PATH, Object::List(vec![Object::Str(dir.display().to_string())]));
+
+        let error = eval("(load \"broken\")", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnboundSymbol("missing".to_string()));
+        assert_eq!(error.file, Some(Box::new(file.canonicalize().unwrap())));
+        assert_eq!(error.span, Some(Span { line: 3, col: 3 }));
+        assert!(error.to_string().ends_with("broken.lisp:3:3: Unbound symbol: missing"));
+        assert_eq!(env.get("a"), Some(Object::Integer(1)));
+        assert_eq!(env.get("b"), None);
 
+        let error = eval("(load \"parse\")", &mut env).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Parse(_)));
+        assert!(error.file.is_some_and(|f| f.ends_with("parse.lisp")));
+
+        assert_eq!(
+            eval("(load \"values\")", &mut env).unwrap(),
+            Object::List(vec![
+                Object::Symbol("a".to_string()),
+                Object::Symbol("m".to_string()),
+                Object::Integer(2),
+            ])
+        );
+
+        // Forms that are not wrapped in a list are all loaded
+        assert_eq!(
+            eval("(load \"forms\")", &mut env).unwrap(),
+            Object::List(vec![
+                Object::Symbol("x".to_string()),
+                Object::Symbol("y".to_string()),
+                Object::Integer(3),
+            ])
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn record_types() {
+        let mut env = Env::new();
         let program = r#"
             (
-                (define age 20)
-                (equal age 29)
+                (define-record-type <point> (make-point x y) point?
+                    (x point-x set-point-x!)
+                    (y point-y))
+                (define p (make-point 1 2))
+                (define q p)
+                (set-point-x! p 5)
+                (point-x q)
+                (point-y p)
+                (point? p)
+                (point? 1)
+                (equal p (make-point 5 2))
+                (equal p (make-point 5 3))
             )
         "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(5),
+                Object::Integer(2),
+                Object::Bool(true),
+                Object::Bool(false),
+                Object::Bool(true),
+                Object::Bool(false),
+            ])
+        );
+        assert_eq!(env.get("p").unwrap().to_string(), "#<point x: 5 y: 2>");
+        assert_eq!(env.get("<point>").unwrap().to_string(), "#<record-type point>");
 
-        let result = eval(program, &mut env).unwrap();
+        // Records of another type with the same fields are not equal
+        eval("(define-record-type <pair> (make-pair x y) pair? (x pair-x) (y pair-y))", &mut env).unwrap();
+        assert_eq!(eval("(equal (make-pair 5 2) p)", &mut env).unwrap(), Object::Bool(false));
 
-        assert_eq!(result, Object::List(vec![Object::Bool(false)]));
+        let error = eval("(point-x (make-pair 1 2))", &mut env).unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::TypeMismatch {
+                expected: "a point record".to_string(),
+                found: "#<pair x: 1 y: 2>".to_string()
+            }
+        );
+        assert!(eval("(make-point 1)", &mut env).is_err());
+        assert!(eval("(define-record-type bad (make-bad z) bad? (x bad-x))", &mut env).is_err());
     }
 
     #[test]
-    fn bool_variable() {
+    fn generic_functions() {
         let mut env = Env::new();
+        let program = r#"
+            (
+                (defclass shape () ((name :initform "shape" :accessor shape-name)))
+                (defclass circle (shape) ((r :accessor radius)))
+                (defclass square (shape) (side))
+                (defgeneric area (s))
+                (defmethod area ((s circle)) (* 3 (* (radius s) (radius s))))
+                (defmethod area ((s square)) (* (slot-value s 'side) (slot-value s 'side)))
+                (defgeneric describe (s))
+                (defmethod describe ((s shape)) (concat "a " (shape-name s)))
+                (defmethod describe ((s circle)) (concat (call-next-method) " with a radius"))
+                (define c (make-instance 'circle :r 2 :name "circle"))
+                (area c)
+                (area (make-instance square :side 3))
+                (describe c)
+                (describe (make-instance 'square))
+            )
+        "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(12),
+                Object::Integer(9),
+                Object::Str("a circle with a radius".to_string()),
+                Object::Str("a shape".to_string()),
+            ])
+        );
+        assert_eq!(env.get("c").unwrap().to_string(), "#<circle name: \"circle\" r: 2>");
+
+        let error = eval("(area 1)", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NoMethod("No applicable method for area with 1".to_string()));
+        assert!(eval("(make-instance 'circle :z 1)", &mut env).is_err());
+        assert!(eval("(defmethod area ((a circle) b) a)", &mut env).is_err());
+    }
 
+    #[test]
+    fn multiple_dispatch_and_qualifiers() {
+        let mut env = Env::new();
         let program = r#"
             (
-                (define isCool true)
-                (if (isCool) "yeah it's so cool" "It's boring")
+                (define-record-type point (make-point x y) point? (x point-x) (y point-y))
+                (defmethod combine ((a Integer) (b Integer)) (+ a b))
+                (defmethod combine ((a Str) (b Str)) (concat a b))
+                (defmethod combine ((a point) b) `(point ,b))
+                (defmethod combine (a (b Str)) `(any-str ,b))
+                (define-record-type cell (make-cell v) cell? (v cell-value set-cell-value!))
+                (define log (make-cell ""))
+                (defmethod combine :before ((a Integer) b) (set-cell-value! log (concat (cell-value log) "before int ")))
+                (defmethod combine :before (a b) (set-cell-value! log (concat (cell-value log) "before ")))
+                (defmethod combine :after (a b) (set-cell-value! log (concat (cell-value log) "after")))
+                (combine 1 2)
+                (cell-value log)
+                (combine "a" "b")
+                (combine (make-point 1 2) "x")
+                (combine 1.5 "x")
             )
         "#;
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                Object::Integer(3),
+                Object::Str("before int before after".to_string()),
+                Object::Str("ab".to_string()),
+                Object::List(vec![Object::Symbol("point".to_string()), Object::Str("x".to_string())]),
+                Object::List(vec![Object::Symbol("any-str".to_string()), Object::Str("x".to_string())]),
+            ])
+        );
+        assert!(eval("(defmethod combine :around (a b) a)", &mut env).is_err());
+        assert!(eval("(defmethod combine ((a Unknown) b) a)", &mut env).is_err());
+    }
 
-        let result = eval(program, &mut env).unwrap();
+    #[test]
+    fn match_patterns() {
+        let mut env = Env::new();
+        let program = r#"
+            (
+                (define-record-type point (make-point x y) point? (x point-x) (y point-y))
+                (define describe (lambda (v)
+                    (match v
+                        (0 'zero)
+                        ("hi" 'greeting)
+                        ('(1 2) 'one-two)
+                        ((? point? ($ point x 0)) `(on-axis ,x))
+                        (($ point x y) (+ x y))
+                        (`(add ,a ,b) (+ a b))
+                        ((a a) `(twice ,a))
+                        ((first . rest) rest)
+                        ((? (lambda (n) (< 100 n)) n) `(big ,n))
+                        (_ 'other))))
+                (describe 0)
+                (describe "hi")
+                (describe '(1 2))
+                (describe (make-point 3 0))
+                (describe (make-point 3 4))
+                (describe '(add 2 5))
+                (describe 500)
+                (describe '(7 7))
+                (describe '(1 2 3))
+                (describe 5)
+            )
+        "#;
+        let sym = |s: &str| Object::Symbol(s.to_string());
+        assert_eq!(
+            eval(program, &mut env).unwrap(),
+            Object::List(vec![
+                sym("zero"),
+                sym("greeting"),
+                sym("one-two"),
+                Object::List(vec![sym("on-axis"), Object::Integer(3)]),
+                Object::Integer(7),
+                Object::Integer(7),
+                Object::List(vec![sym("big"), Object::Integer(500)]),
+                Object::List(vec![sym("twice"), Object::Integer(7)]),
+                Object::List(vec![Object::Integer(2), Object::Integer(3)]),
+                sym("other"),
+            ])
+        );
 
+        // Quasiquote patterns splice the remaining items
         assert_eq!(
-            result,
-            Object::List(vec![Object::Str("yeah it's so cool".to_string())])
+            eval("(match '(call f 1 2) (`(call ,f ,@args) args))", &mut env).unwrap(),
+            Object::List(vec![Object::Integer(1), Object::Integer(2)])
         );
+
+        let error = eval("(match '(1 2) ((x) x))", &mut env).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NoMatch("(1 2)".to_string()));
+        assert_eq!(error.kind.to_string(), "No matching clause for (1 2)");
+        assert!(eval("(match 1 (($ nothing x) x))", &mut env).is_err());
     }
 }
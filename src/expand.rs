@@ -0,0 +1,605 @@
+use crate::env::Env;
+use crate::eval::*;
+use crate::error::EvalError;
+use crate::object::Object;
+
+/// Forms that the expander rewrites into the core language
+/// (`if`, `define`, `lambda`, `begin`, `quote` and calls), a `let`
+/// whose names are patterns is kept to report the values that do not
+/// match them
+pub const DERIVED_FORMS: [&str; 10] = [
+    "let",
+    "let*",
+    "letrec",
+    "cond",
+    "when",
+    "unless",
+    "and",
+    "or",
+    "receive",
+    "stream-cons",
+];
+
+fn keyword(name: &str) -> Object {
+    Object::Keyword(name.to_string())
+}
+
+/// A list whose head is not an operator is a sequence of forms,
+/// evaluated in order collecting the values
+pub fn is_sequence(forms: &[Object]) -> bool {
+    match forms.first() {
+        None | Some(Object::Keyword(_)) | Some(Object::Symbol(_)) => false,
+        Some(Object::List(head)) => !is_lambda_form(head),
+        Some(_) => true,
+    }
+}
+
+/// `((lambda (x) ...) 1)` applies the lambda instead of being a sequence
+pub fn is_lambda_form(list: &[Object]) -> bool {
+    list.first() == Some(&keyword("lambda"))
+}
+
+/// Expand every top-level form of a program without evaluating it
+pub fn expand_program(program: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    match program {
+        Object::List(forms) if is_sequence(forms) => {
+            let mut expanded = Vec::new();
+            for form in forms {
+                match expand(form, env)? {
+                    Object::Void => {}
+                    core => expanded.push(core),
+                }
+            }
+            Ok(Object::List(expanded))
+        }
+        _ => expand(program, env),
+    }
+}
+
+/// Expand the macros and derived forms of `form` into the core language
+///
+/// Macro definitions are registered in `env` while expanding and
+/// replaced by `Void`, the evaluator never sees them
+pub fn expand(form: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    // Recursive macros count against the limits like evaluation
+    env.budget().step()?;
+    env.budget().descend()?;
+    let result = expand_form(form, env);
+    env.budget().leave();
+    result
+}
+
+fn expand_form(form: &Object, env: &mut Env) -> Result<Object, EvalError> {
+    let items = match form {
+        Object::List(items) if !items.is_empty() => items,
+        _ => return Ok(form.clone()),
+    };
+
+    match &items[0] {
+        Object::Keyword(k) => expand_keyword(k, items, env),
+        Object::Symbol(_) => match macroexpand_1(form, env)? {
+            Some(expanded) => expand(&expanded, env),
+            None => expand_all(items, env),
+        },
+        _ => expand_all(items, env),
+    }
+}
+
+fn expand_all(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let mut expanded = Vec::new();
+
+    for item in items {
+        expanded.push(expand(item, env)?);
+    }
+
+    Ok(Object::List(expanded))
+}
+
+fn expand_keyword(kw: &str, items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    match kw {
+        // Module bodies are expanded in their own scope when evaluated
+        "quote" | "syntax-rules" | "module" | "import" | "define-record-type" | "defclass" | "defgeneric" => Ok(Object::List(items.to_vec())),
+        "quasiquote" => match items {
+            [head, template] => Ok(Object::List(vec![head.clone(), expand_quasi(template, 1, env)?])),
+            _ => Err(EvalError::arity("quasiquote")),
+        },
+        "defmacro" => {
+            let mut definition = items.to_vec();
+            if let Some(body) = definition.get_mut(3) {
+                *body = expand(body, env)?;
+            }
+            eval_defmacro(&definition, env)?;
+            Ok(Object::Void)
+        }
+        "define-syntax" => {
+            eval_define_syntax(items, env)?;
+            Ok(Object::Void)
+        }
+        "let-syntax" => expand_let_syntax(items, env),
+        "lambda" => {
+            if items.len() < 3 {
+                return Err(EvalError::arity("lambda"));
+            }
+
+            let body = lambda_body(&items[2..]);
+            Ok(Object::List(vec![items[0].clone(), items[1].clone(), expand(&body, env)?]))
+        }
+        "defmethod" => expand_defmethod(items, env),
+        "match" => expand_match(items, env),
+        "guard" => expand_guard(items, env),
+        "handler-bind" => expand_handler_bind(items, env),
+        "restart-case" => expand_restart_case(items, env),
+        "let" if is_pattern_let(items) => expand_bindings(kw, items, env),
+        "let" => expand(&derive_let(items)?, env),
+        "let*" => expand(&derive_let_star(items)?, env),
+        "letrec" => expand(&derive_letrec(items)?, env),
+        "destructuring-bind" => expand_destructuring_bind(items, env),
+        "receive" => expand(&derive_receive(items)?, env),
+        "stream-cons" => expand(&derive_stream_cons(items)?, env),
+        "let-values" => expand_bindings(kw, items, env),
+        "define-values" if items.len() == 3 => Ok(Object::List(vec![
+            items[0].clone(),
+            items[1].clone(),
+            expand(&items[2], env)?,
+        ])),
+        "cond" => expand(&derive_cond(&items[1..])?, env),
+        "when" | "unless" => expand(&derive_when(kw, items)?, env),
+        "and" => expand(&derive_and_or(&items[1..], true), env),
+        "or" => expand(&derive_and_or(&items[1..], false), env),
+        _ => expand_all(items, env),
+    }
+}
+
+/// `(defmethod name [qualifier] params body...)`, only the body is code
+fn expand_defmethod(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    let params = match items.get(2) {
+        Some(Object::Symbol(_)) => 3,
+        _ => 2,
+    };
+
+    if items.len() <= params + 1 {
+        return Ok(Object::List(items.to_vec()));
+    }
+
+    let mut expanded = items[..=params].to_vec();
+    expanded.push(expand(&lambda_body(&items[params + 1..]), env)?);
+    Ok(Object::List(expanded))
+}
+
+/// `(match value (pattern body...)...)`, patterns are not code
+fn expand_match(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 2 {
+        return Err(EvalError::arity("match"));
+    }
+
+    let mut expanded = vec![items[0].clone(), expand(&items[1], env)?];
+    for clause in &items[2..] {
+        match clause {
+            Object::List(parts) if parts.len() >= 2 => {
+                expanded.push(Object::List(vec![parts[0].clone(), expand(&lambda_body(&parts[1..]), env)?]));
+            }
+            _ => return Err(EvalError::syntax(format!("Invalid match clause: {}", clause))),
+        }
+    }
+
+    Ok(Object::List(expanded))
+}
+
+/// Only the unquoted parts of a template are code
+fn expand_quasi(template: &Object, depth: usize, env: &mut Env) -> Result<Object, EvalError> {
+    let items = match template {
+        Object::List(items) => items,
+        _ => return Ok(template.clone()),
+    };
+
+    if let [Object::Keyword(k), inner] = items.as_slice() {
+        let depth = match k.as_str() {
+            "unquote" | "unquote-splicing" if depth == 1 => {
+                return Ok(Object::List(vec![items[0].clone(), expand(inner, env)?]));
+            }
+            "unquote" | "unquote-splicing" => depth - 1,
+            "quasiquote" => depth + 1,
+            _ => depth,
+        };
+
+        return Ok(Object::List(vec![items[0].clone(), expand_quasi(inner, depth, env)?]));
+    }
+
+    let mut expanded = Vec::new();
+    for item in items {
+        expanded.push(expand_quasi(item, depth, env)?);
+    }
+
+    Ok(Object::List(expanded))
+}
+
+fn expand_let_syntax(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity("let-syntax"));
+    }
+
+    let bindings = match &items[1] {
+        Object::List(bindings) => bindings,
+        _ => return Err(EvalError::syntax("let-syntax bindings must be a list")),
+    };
+
+    let mut scope = env.clone();
+    for binding in bindings {
+        match binding {
+            Object::List(pair) if pair.len() == 2 => {
+                let name = macro_name(&pair[0])?;
+                let transformer = eval_transformer(&pair[1], env)?;
+                scope.set_macro(&name, transformer);
+            }
+            _ => return Err(EvalError::syntax(format!("Invalid let-syntax binding: {}", binding))),
+        }
+    }
+
+    expand(&sequence(&items[2..])?, &mut scope)
+}
+
+/// The clauses of a `guard` are expanded like the items of `cond`
+fn expand_guard(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity("guard"));
+    }
+
+    let spec = match &items[1] {
+        Object::List(spec) if matches!(spec.first(), Some(Object::Symbol(_))) => spec,
+        _ => return Err(EvalError::syntax("guard needs a variable for the condition")),
+    };
+
+    let mut expanded = vec![spec[0].clone()];
+    for clause in &spec[1..] {
+        match clause {
+            Object::List(clause) => expanded.push(expand_all(clause, env)?),
+            _ => return Err(EvalError::syntax(format!("Invalid guard clause: {}", clause))),
+        }
+    }
+
+    let body = expand(&sequence(&items[2..])?, env)?;
+
+    Ok(Object::List(vec![items[0].clone(), Object::List(expanded), body]))
+}
+
+fn expand_handler_bind(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity("handler-bind"));
+    }
+
+    let bindings = match &items[1] {
+        Object::List(bindings) => bindings,
+        _ => return Err(EvalError::syntax("handler-bind bindings must be a list")),
+    };
+
+    let mut expanded = Vec::new();
+    for binding in bindings {
+        match binding {
+            Object::List(pair) if pair.len() == 2 => {
+                expanded.push(Object::List(vec![pair[0].clone(), expand(&pair[1], env)?]));
+            }
+            _ => return Err(EvalError::syntax(format!("Invalid handler-bind binding: {}", binding))),
+        }
+    }
+
+    let body = expand(&sequence(&items[2..])?, env)?;
+
+    Ok(Object::List(vec![items[0].clone(), Object::List(expanded), body]))
+}
+
+/// Every restart clause `(name (params...) body...)` keeps a single body form
+fn expand_restart_case(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 2 {
+        return Err(EvalError::arity("restart-case"));
+    }
+
+    let mut expanded = vec![items[0].clone(), expand(&items[1], env)?];
+    for clause in &items[2..] {
+        match clause {
+            Object::List(clause) if clause.len() >= 3 => {
+                let body = expand(&sequence(&clause[2..])?, env)?;
+                expanded.push(Object::List(vec![clause[0].clone(), clause[1].clone(), body]));
+            }
+            _ => return Err(EvalError::syntax(format!("Invalid restart-case clause: {}", clause))),
+        }
+    }
+
+    Ok(Object::List(expanded))
+}
+
+/// A body with several forms becomes a `begin`
+fn sequence(forms: &[Object]) -> Result<Object, EvalError> {
+    match forms {
+        [] => Err(EvalError::syntax("Expected at least one form in the body")),
+        [form] => Ok(form.clone()),
+        _ => {
+            let mut list = vec![keyword("begin")];
+            list.extend_from_slice(forms);
+            Ok(Object::List(list))
+        }
+    }
+}
+
+/// Body of a lambda, several forms are wrapped in a `begin`
+pub fn lambda_body(forms: &[Object]) -> Object {
+    match forms {
+        [Object::List(list)] if !list.is_empty() => forms[0].clone(),
+        _ => {
+            let mut list = vec![keyword("begin")];
+            list.extend_from_slice(forms);
+            Object::List(list)
+        }
+    }
+}
+
+fn lambda(params: Vec<Object>, body: &[Object]) -> Object {
+    Object::List(vec![keyword("lambda"), Object::List(params), lambda_body(body)])
+}
+
+/// Names and values of `((name value)...)`
+fn let_bindings(obj: &Object) -> Result<(Vec<Object>, Vec<Object>), EvalError> {
+    let bindings = match obj {
+        Object::List(bindings) => bindings,
+        _ => return Err(EvalError::syntax(format!("Invalid let bindings: {}", obj))),
+    };
+
+    let mut names = Vec::new();
+    let mut values = Vec::new();
+    for binding in bindings {
+        match binding {
+            Object::List(pair) if pair.len() == 2 && matches!(pair[0], Object::Symbol(_)) => {
+                names.push(pair[0].clone());
+                values.push(pair[1].clone());
+            }
+            _ => return Err(EvalError::syntax(format!("Invalid let binding: {}", binding))),
+        }
+    }
+
+    Ok((names, values))
+}
+
+/// `(let ((x 1)) body)` is `((lambda (x) body) 1)`, a named let
+/// `(let loop ((i 0)) body)` defines `loop` inside a new scope
+fn derive_let(items: &[Object]) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity("let"));
+    }
+
+    if let Object::Symbol(_) = &items[1] {
+        if items.len() < 4 {
+            return Err(EvalError::arity("named let"));
+        }
+
+        let (names, values) = let_bindings(&items[2])?;
+        let define = Object::List(vec![keyword("define"), items[1].clone(), lambda(names, &items[3..])]);
+        let mut call = vec![items[1].clone()];
+        call.extend(values);
+
+        let scope = lambda(vec![], &[define, Object::List(call)]);
+        return Ok(Object::List(vec![scope]));
+    }
+
+    let (names, values) = let_bindings(&items[1])?;
+    let mut call = vec![lambda(names, &items[2..])];
+    call.extend(values);
+
+    Ok(Object::List(call))
+}
+
+fn derive_let_star(items: &[Object]) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity("let*"));
+    }
+
+    let bindings = match &items[1] {
+        Object::List(bindings) => bindings.clone(),
+        other => return Err(EvalError::syntax(format!("Invalid let* bindings: {}", other))),
+    };
+
+    if bindings.len() <= 1 {
+        let mut list = vec![keyword("let")];
+        list.extend_from_slice(&items[1..]);
+        return Ok(Object::List(list));
+    }
+
+    let mut inner = vec![keyword("let*"), Object::List(bindings[1..].to_vec())];
+    inner.extend_from_slice(&items[2..]);
+
+    Ok(Object::List(vec![
+        keyword("let"),
+        Object::List(vec![bindings[0].clone()]),
+        Object::List(inner),
+    ]))
+}
+
+/// Every binding is defined inside a new scope, so the values
+/// can refer to each other
+fn derive_letrec(items: &[Object]) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity("letrec"));
+    }
+
+    let (names, values) = let_bindings(&items[1])?;
+    let mut body = Vec::new();
+    for (name, value) in names.into_iter().zip(values) {
+        body.push(Object::List(vec![keyword("define"), name, value]));
+    }
+    body.extend_from_slice(&items[2..]);
+
+    Ok(Object::List(vec![lambda(vec![], &body)]))
+}
+
+/// A plain `let` with a list among its names, as in `(let (((a b) value)) body)`
+pub fn is_pattern_let(items: &[Object]) -> bool {
+    match items.get(1) {
+        Some(Object::List(bindings)) => bindings
+            .iter()
+            .any(|binding| matches!(binding, Object::List(pair) if matches!(pair.first(), Some(Object::List(_))))),
+        _ => false,
+    }
+}
+
+/// `(destructuring-bind (a (b c)) value body...)`, the pattern is not code
+fn expand_destructuring_bind(items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 4 {
+        return Err(EvalError::arity("destructuring-bind"));
+    }
+
+    Ok(Object::List(vec![
+        items[0].clone(),
+        items[1].clone(),
+        expand(&items[2], env)?,
+        expand(&lambda_body(&items[3..]), env)?,
+    ]))
+}
+
+/// `(receive formals expr body)` is `(let-values ((formals expr)) body)`
+fn derive_receive(items: &[Object]) -> Result<Object, EvalError> {
+    if items.len() < 4 {
+        return Err(EvalError::arity("receive"));
+    }
+
+    let bindings = Object::List(vec![Object::List(vec![items[1].clone(), items[2].clone()])]);
+    let mut list = vec![keyword("let-values"), bindings];
+    list.extend_from_slice(&items[3..]);
+    Ok(Object::List(list))
+}
+
+/// `(stream-cons a b)` is `` `(,a ,(delay b)) ``, a stream is empty or
+/// a list of its first item and a promise of the rest
+fn derive_stream_cons(items: &[Object]) -> Result<Object, EvalError> {
+    match items {
+        [_, first, rest] => {
+            let unquote = |form: Object| Object::List(vec![keyword("unquote"), form]);
+            let delayed = Object::List(vec![keyword("delay"), rest.clone()]);
+            let template = Object::List(vec![unquote(first.clone()), unquote(delayed)]);
+            Ok(Object::List(vec![keyword("quasiquote"), template]))
+        }
+        _ => Err(EvalError::arity("stream-cons")),
+    }
+}
+
+/// `(let-values ((formals expr)...) body...)`, or a `let` with patterns,
+/// the formals and the patterns are not code
+fn expand_bindings(kw: &str, items: &[Object], env: &mut Env) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity(kw));
+    }
+
+    let bindings = match &items[1] {
+        Object::List(bindings) => bindings,
+        other => return Err(EvalError::syntax(format!("Invalid {} bindings: {}", kw, other))),
+    };
+
+    let mut expanded = Vec::new();
+    for binding in bindings {
+        match binding {
+            Object::List(pair) if pair.len() == 2 => {
+                expanded.push(Object::List(vec![pair[0].clone(), expand(&pair[1], env)?]));
+            }
+            _ => return Err(EvalError::syntax(format!("Invalid {} binding: {}", kw, binding))),
+        }
+    }
+
+    Ok(Object::List(vec![
+        items[0].clone(),
+        Object::List(expanded),
+        expand(&lambda_body(&items[2..]), env)?,
+    ]))
+}
+
+fn derive_cond(clauses: &[Object]) -> Result<Object, EvalError> {
+    let clause = match clauses.first() {
+        Some(Object::List(clause)) if !clause.is_empty() => clause,
+        Some(other) => return Err(EvalError::syntax(format!("Invalid cond clause: {}", other))),
+        None => return Ok(Object::List(vec![])),
+    };
+
+    if clause[0] == Object::Symbol("else".to_string()) {
+        return sequence(&clause[1..]);
+    }
+
+    let then = match clause.len() {
+        1 => clause[0].clone(),
+        _ => sequence(&clause[1..])?,
+    };
+
+    let mut rest = vec![keyword("cond")];
+    rest.extend_from_slice(&clauses[1..]);
+
+    Ok(Object::List(vec![keyword("if"), clause[0].clone(), then, Object::List(rest)]))
+}
+
+fn derive_when(kw: &str, items: &[Object]) -> Result<Object, EvalError> {
+    if items.len() < 3 {
+        return Err(EvalError::arity(kw));
+    }
+
+    let body = sequence(&items[2..])?;
+    let nothing = Object::List(vec![]);
+    let (then, otherwise) = match kw {
+        "when" => (body, nothing),
+        _ => (nothing, body),
+    };
+
+    Ok(Object::List(vec![keyword("if"), items[1].clone(), then, otherwise]))
+}
+
+/// `and` and `or` short circuit through nested `if`s
+fn derive_and_or(forms: &[Object], is_and: bool) -> Object {
+    let (name, empty) = match is_and {
+        true => ("and", "true"),
+        false => ("or", "false"),
+    };
+
+    match forms {
+        [] => keyword(empty),
+        [form] => form.clone(),
+        _ => {
+            let mut rest = vec![keyword(name)];
+            rest.extend_from_slice(&forms[1..]);
+
+            let (then, otherwise) = match is_and {
+                true => (Object::List(rest), keyword("false")),
+                false => (keyword("true"), Object::List(rest)),
+            };
+
+            Object::List(vec![keyword("if"), forms[0].clone(), then, otherwise])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn expand_str(program: &str) -> Object {
+        let mut env = Env::new();
+        expand(&parse(program).unwrap(), &mut env).unwrap()
+    }
+
+    #[test]
+    fn let_into_lambda() {
+        assert_eq!(
+            expand_str("(let ((a 1) (b 2)) (+ a b))"),
+            parse("((lambda (a b) (+ a b)) 1 2)").unwrap()
+        );
+    }
+
+    #[test]
+    fn cond_into_if() {
+        assert_eq!(
+            expand_str("(cond ((< n 0) 1) ((< n 5) 2 3) (else 4))"),
+            parse("(if (< n 0) 1 (if (< n 5) (begin 2 3) 4))").unwrap()
+        );
+    }
+
+    #[test]
+    fn when_inside_lambda_body() {
+        assert_eq!(
+            expand_str("(lambda (x) (when (and x y) (print x)))"),
+            parse("(lambda (x) (if (if x y false) (print x) ()))").unwrap()
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use crate::error::{ErrorKind, EvalError};
+use crate::limits::Budget;
+use crate::object::Object;
+use crate::strings;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Digits after the point `~w,df` can write, the most Rust formats
+const MAX_PRECISION: i64 = u16::MAX as i64;
+
+/// Width of a directive and count of `~%` and `~~`, larger ones would
+/// only exhaust the memory
+const MAX_WIDTH: i64 = 1 << 20;
+
+/// Parameter of a directive, as the 10 and '0 of `~10,'0d`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Param {
+    Number(i64),
+    Char(char),
+}
+
+/// Part of a control string
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Text(String),
+    Directive {
+        kind: char,
+        params: Vec<Option<Param>>,
+        at: bool,
+    },
+    /// `~{...~}`, the pieces run for each item of a list
+    Iteration(Vec<Piece>),
+    /// `~^`, stops an iteration when there are no arguments left
+    Escape,
+}
+
+/// Arguments not consumed yet by the directives
+struct Args<'a> {
+    items: &'a [Object],
+    next: usize,
+}
+
+impl<'a> Args<'a> {
+    fn take(&mut self) -> Result<&'a Object, EvalError> {
+        let item = self.items.get(self.next).ok_or_else(|| EvalError::arity("format"))?;
+        self.next += 1;
+        Ok(item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.next >= self.items.len()
+    }
+}
+
+/// Text of a control string with its directives replaced by the
+/// arguments:
+///
+/// - `~a` displays a value and `~s` writes it, strings with quotes
+/// - `~d`, `~x`, `~b` and `~o` write an integer in radix 10, 16, 2 and 8
+/// - `~w,df` writes a number with `d` digits after the point
+/// - `~%` is a newline and `~~` a tilde
+/// - `~{...~}` formats the items of a list, `~^` stops before the last
+///
+/// The first parameter is the width and the second the padding char,
+/// as in `~5,'0d`. `~a` and `~s` are padded on the right unless they
+/// have `@`, with `@` the numbers always have a sign. The output is
+/// charged to the budget before it is written
+pub fn format(control: &str, args: &[Object], budget: &Budget) -> Result<String, EvalError> {
+    let pieces = parse(&mut control.chars().peekable(), false)?;
+    let mut out = String::new();
+    render(&pieces, &mut Args { items: args, next: 0 }, &mut out, budget)?;
+    Ok(out)
+}
+
+fn parse(chars: &mut Peekable<Chars>, nested: bool) -> Result<Vec<Piece>, EvalError> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            text.push(c);
+            continue;
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut text)));
+        }
+
+        let params = parse_params(chars)?;
+        let mut at = false;
+        while let Some(&modifier @ ('@' | ':')) = chars.peek() {
+            at |= modifier == '@';
+            chars.next();
+        }
+
+        match chars.next().map(|c| c.to_ascii_lowercase()) {
+            Some('{') => pieces.push(Piece::Iteration(parse(chars, true)?)),
+            Some('}') if nested => return Ok(pieces),
+            Some('^') => pieces.push(Piece::Escape),
+            Some(kind @ ('a' | 's' | 'd' | 'x' | 'b' | 'o' | 'f' | '%' | '~')) => {
+                pieces.push(Piece::Directive { kind, params, at })
+            }
+            Some(other) => return Err(EvalError::syntax(format!("Unknown format directive ~{}", other))),
+            None => return Err(EvalError::syntax("Format directive without a name at the end")),
+        }
+    }
+
+    if nested {
+        return Err(EvalError::syntax("Format iteration ~{ without ~}"));
+    }
+
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+/// Parameters separated by commas, a number or a char after a quote
+fn parse_params(chars: &mut Peekable<Chars>) -> Result<Vec<Option<Param>>, EvalError> {
+    let mut params = Vec::new();
+
+    loop {
+        let param = match chars.peek() {
+            Some('\'') => {
+                chars.next();
+                let c = chars
+                    .next()
+                    .ok_or_else(|| EvalError::syntax("Format parameter without a char"))?;
+                Some(Param::Char(c))
+            }
+            Some(c) if c.is_ascii_digit() || *c == '-' => {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '-') {
+                    digits.push(c);
+                    chars.next();
+                }
+                let n = digits
+                    .parse()
+                    .map_err(|_| EvalError::syntax(format!("Invalid format parameter: {}", digits)))?;
+                Some(Param::Number(n))
+            }
+            _ => None,
+        };
+
+        if chars.peek() == Some(&',') {
+            chars.next();
+            params.push(param);
+        } else {
+            if param.is_some() {
+                params.push(param);
+            }
+            return Ok(params);
+        }
+    }
+}
+
+/// Write the pieces, false when a `~^` stopped them
+fn render(pieces: &[Piece], args: &mut Args, out: &mut String, budget: &Budget) -> Result<bool, EvalError> {
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => {
+                budget.allocate(text.len())?;
+                out.push_str(text);
+            }
+            Piece::Escape if args.is_empty() => return Ok(false),
+            Piece::Escape => {}
+            Piece::Iteration(body) => {
+                let items = match args.take()? {
+                    Object::List(items) => items,
+                    other => return Err(EvalError::type_mismatch("a list", other)),
+                };
+
+                let mut inner = Args { items, next: 0 };
+                while !inner.is_empty() {
+                    let before = inner.next;
+                    if !render(body, &mut inner, out, budget)? || inner.next == before {
+                        break;
+                    }
+                }
+            }
+            Piece::Directive { kind, params, at } => directive(*kind, params, *at, args, out, budget)?,
+        }
+    }
+
+    Ok(true)
+}
+
+fn directive(
+    kind: char,
+    params: &[Option<Param>],
+    at: bool,
+    args: &mut Args,
+    out: &mut String,
+    budget: &Budget,
+) -> Result<(), EvalError> {
+    let number = |i: usize| match params.get(i) {
+        Some(Some(Param::Number(n))) => Ok(Some(*n)),
+        Some(Some(Param::Char(c))) => Err(EvalError::syntax(format!("Expected a number parameter, found '{}", c))),
+        _ => Ok(None),
+    };
+    let width = match number(0)? {
+        Some(n) if n > MAX_WIDTH => {
+            let what = if let '%' | '~' = kind { "Count" } else { "Width" };
+            let message = format!("{} {} of ~{} is greater than {}", what, n, kind, MAX_WIDTH);
+            return Err(EvalError::new(ErrorKind::Domain(message)));
+        }
+        width => width,
+    };
+    // The parameter of ~% and ~~ is a count instead of a width
+    if let '%' | '~' = kind {
+        let c = if kind == '%' { '\n' } else { '~' };
+        out.push_str(&repeat(c, width.unwrap_or(1), budget)?);
+        return Ok(());
+    }
+
+    let width = width.unwrap_or(0).max(0) as usize;
+    let fill = match params.get(1) {
+        Some(Some(Param::Char(c))) => *c,
+        _ => ' ',
+    };
+
+    let (text, left) = match kind {
+        'a' => (display(args.take()?), at),
+        's' => (args.take()?.repr(), at),
+        'f' => {
+            let n = match args.take()? {
+                Object::Integer(n) => *n as f64,
+                Object::Float(f) => *f,
+                other => return Err(EvalError::type_mismatch("a number", other)),
+            };
+            let text = match number(1)? {
+                Some(digits) if digits > MAX_PRECISION => {
+                    let message = format!("Precision {} of ~f is greater than {}", digits, MAX_PRECISION);
+                    return Err(EvalError::new(ErrorKind::Domain(message)));
+                }
+                Some(digits) => format!("{:.*}", digits.max(0) as usize, n),
+                None if n.is_finite() && n.fract() == 0.0 => format!("{:.1}", n),
+                None => n.to_string(),
+            };
+            (signed(text, n >= 0.0 && at), true)
+        }
+        _ => {
+            let n = match args.take()? {
+                Object::Integer(n) => *n,
+                other => return Err(EvalError::type_mismatch("an integer", other)),
+            };
+            let radix = match kind {
+                'x' => 16,
+                'b' => 2,
+                'o' => 8,
+                _ => 10,
+            };
+            (signed(strings::format_radix(n, radix), n >= 0 && at), true)
+        }
+    };
+
+    let padding = repeat(fill, width.saturating_sub(strings::char_count(&text)) as i64, budget)?;
+    budget.allocate(text.len())?;
+    match left {
+        true => out.extend([padding, text]),
+        false => out.extend([text, padding]),
+    }
+    Ok(())
+}
+
+fn repeat(c: char, times: i64, budget: &Budget) -> Result<String, EvalError> {
+    let times = times.max(0) as usize;
+    budget.allocate(times.saturating_mul(c.len_utf8()))?;
+    Ok(std::iter::repeat_n(c, times).collect())
+}
+
+fn signed(text: String, plus: bool) -> String {
+    match plus {
+        true => format!("+{}", text),
+        false => text,
+    }
+}
+
+/// Value as `~a` shows it, strings without quotes, also in lists
+fn display(obj: &Object) -> String {
+    match obj {
+        Object::Str(s) => s.clone(),
+        Object::List(items) => {
+            let items = items.iter().map(display).collect::<Vec<_>>();
+            format!("({})", items.join(" "))
+        }
+        other => other.repr(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+
+    fn format_str(control: &str, args: &[Object]) -> String {
+        format(control, args, &Budget::default()).unwrap()
+    }
+
+    #[test]
+    fn directives() {
+        let s = |s: &str| Object::Str(s.to_string());
+        let list = Object::List(vec![s("a"), Object::Integer(1)]);
+
+        assert_eq!(format_str("~a and ~s", &[s("x"), s("x")]), "x and \"x\"");
+        assert_eq!(format_str("~a ~s", &[list.clone(), list]), "(a 1) (\"a\" 1)");
+        assert_eq!(
+            format_str("~d ~x ~b ~o ~@d", &[255, 255, 5, 8, 3].map(Object::Integer)),
+            "255 ff 101 10 +3"
+        );
+        assert_eq!(
+            format_str(
+                "~,2f|~f|~8,3f",
+                &[Object::Float(1.23456), Object::Integer(2), Object::Float(-1.5)]
+            ),
+            "1.23|2.0|  -1.500"
+        );
+        assert_eq!(format_str("line~%~~~2%", &[]), "line\n~\n\n");
+    }
+
+    #[test]
+    fn padding() {
+        let s = |s: &str| Object::Str(s.to_string());
+
+        assert_eq!(format_str("[~5a]", &[s("ab")]), "[ab   ]");
+        assert_eq!(format_str("[~5@a]", &[s("ab")]), "[   ab]");
+        assert_eq!(format_str("[~5,'0d]", &[Object::Integer(42)]), "[00042]");
+        assert_eq!(format_str("[~3,'*a]", &[s("héé")]), "[héé]");
+        assert_eq!(format_str("[~2d]", &[Object::Integer(12345)]), "[12345]");
+
+        // The padding is charged before it is made
+        let budget = Budget::new(Limits {
+            max_allocation: Some(1_000),
+            ..Limits::default()
+        });
+        for control in ["~60000a", "~60000%"] {
+            let error = format(control, &[Object::Integer(1)], &budget).unwrap_err();
+            assert!(matches!(error.kind, ErrorKind::ResourceLimit(_)));
+        }
+    }
+
+    #[test]
+    fn iteration() {
+        let items = Object::List((1..=3).map(Object::Integer).collect());
+
+        assert_eq!(format_str("~{~a~^, ~}.", std::slice::from_ref(&items)), "1, 2, 3.");
+        assert_eq!(format_str("~{<~a>~}", &[items]), "<1><2><3>");
+        let pairs = Object::List(vec![Object::Str("a".to_string()), Object::Integer(1)]);
+        assert_eq!(format_str("~{~a=~d~}", &[pairs]), "a=1");
+        assert_eq!(format_str("~{~}", &[Object::List(vec![])]), "");
+
+        // Every item writes the text of the loop, it is charged as it is written
+        let budget = Budget::new(Limits {
+            max_allocation: Some(100_000),
+            ..Limits::default()
+        });
+        let items = Object::List(vec![Object::Integer(1); 1000]);
+        let control = format!("~{{~a{}~}}", "x".repeat(1000));
+        let error = format(&control, &[items], &budget).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::ResourceLimit(_)));
+        assert!(budget.allocated() <= 100_000);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            format("~a ~a", &[Object::Integer(1)], &Budget::default()).unwrap_err(),
+            EvalError::arity("format")
+        );
+        assert!(format("~q", &[], &Budget::default())
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown format directive ~q"));
+        assert!(format("~{~a", &[], &Budget::default()).is_err());
+        assert!(format("~d", &[Object::Str("x".to_string())], &Budget::default()).is_err());
+        for control in ["~1,99999999999f", "~100000000000a", "~100000000000%", "~100000000000~"] {
+            let error = format(control, &[Object::Float(1.5)], &Budget::default()).unwrap_err();
+            assert_eq!(error.kind.name(), "domain-error");
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::eval::get_type;
+use crate::object::{Object, RecordType};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// What a method parameter accepts, a type of `get_type` like
+/// `Integer` or a class or record type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Specializer {
+    Any,
+    Builtin(String),
+    Type(Rc<RecordType>),
+}
+
+/// Methods run around the primary one, `:before` the most specific
+/// first and `:after` the most specific last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Qualifier {
+    Primary,
+    Before,
+    After,
+}
+
+impl Qualifier {
+    pub fn from_name(name: &str) -> Option<Qualifier> {
+        match name {
+            ":before" => Some(Qualifier::Before),
+            ":after" => Some(Qualifier::After),
+            _ => None,
+        }
+    }
+}
+
+/// Method added to a generic function by `defmethod`
+#[derive(Debug, PartialEq)]
+pub struct Method {
+    pub qualifier: Qualifier,
+    pub specializers: Vec<Specializer>,
+    pub params: Vec<String>,
+    pub body: Object,
+}
+
+/// Generic function made by `defgeneric` or by the first `defmethod`,
+/// its copies share the methods
+#[derive(Debug, PartialEq)]
+pub struct Generic {
+    pub name: String,
+    pub arity: usize,
+    pub methods: RefCell<Vec<Rc<Method>>>,
+}
+
+impl Generic {
+    pub fn new(name: &str, arity: usize) -> Self {
+        Generic {
+            name: name.to_string(),
+            arity,
+            methods: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Add a method, replacing the one with the same qualifier and specializers
+    pub fn add_method(&self, method: Method) {
+        let mut methods = self.methods.borrow_mut();
+        methods.retain(|m| m.qualifier != method.qualifier || m.specializers != method.specializers);
+        methods.push(Rc::new(method));
+    }
+
+    /// Methods that accept the arguments, the most specific first
+    pub fn applicable(&self, args: &[Object]) -> Vec<Rc<Method>> {
+        let mut ranked = self
+            .methods
+            .borrow()
+            .iter()
+            .filter_map(|method| {
+                let ranks = method
+                    .specializers
+                    .iter()
+                    .zip(args)
+                    .map(|(specializer, arg)| rank(specializer, arg))
+                    .collect::<Option<Vec<_>>>()?;
+                Some((ranks, method.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        // The leftmost arguments decide first, as in CLOS
+        ranked.sort_by(|(a, _), (b, _)| a.cmp(b));
+        ranked.into_iter().map(|(_, method)| method).collect()
+    }
+}
+
+/// `call-next-method` in a primary method, with the less specific
+/// methods and the arguments of the call
+#[derive(Debug, PartialEq)]
+pub struct NextMethod {
+    pub name: String,
+    pub methods: Vec<Rc<Method>>,
+    pub args: Vec<Object>,
+}
+
+/// Classes a class inherits from, itself first and each class before
+/// the ones it inherits from
+pub fn precedence(class: &Rc<RecordType>) -> Vec<Rc<RecordType>> {
+    let mut all = vec![class.clone()];
+    for sup in &class.supers {
+        all.extend(precedence(sup));
+    }
+
+    // A class shared by several supers goes after all of them
+    let mut list: Vec<Rc<RecordType>> = Vec::new();
+    for (i, class) in all.iter().enumerate() {
+        if !all[i + 1..].iter().any(|later| Rc::ptr_eq(later, class)) {
+            list.push(class.clone());
+        }
+    }
+    list
+}
+
+/// How far the specializer is from the type of the argument,
+/// `None` when it does not accept it
+fn rank(specializer: &Specializer, arg: &Object) -> Option<usize> {
+    let classes = match arg {
+        Object::Record(record) => precedence(&record.rtype),
+        _ => Vec::new(),
+    };
+
+    match specializer {
+        Specializer::Type(class) => classes.iter().position(|c| Rc::ptr_eq(c, class)),
+        Specializer::Builtin(name) if *name == get_type(arg) => Some(classes.len()),
+        Specializer::Builtin(_) => None,
+        Specializer::Any => Some(classes.len() + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(name: &str, supers: Vec<Rc<RecordType>>) -> Rc<RecordType> {
+        Rc::new(RecordType {
+            name: name.to_string(),
+            fields: Vec::new(),
+            supers,
+            initforms: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn shared_superclasses_come_last() {
+        let base = class("base", vec![]);
+        let left = class("left", vec![base.clone()]);
+        let right = class("right", vec![base.clone()]);
+        let both = class("both", vec![left, right]);
+
+        let names = precedence(&both).iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["both", "left", "right", "base"]);
+    }
+}
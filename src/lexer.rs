@@ -1,3 +1,4 @@
+use crate::error::*;
 use std::error::Error;
 use std::fmt;
 
@@ -6,9 +7,15 @@ pub enum Token {
     Integer(i64),
     Float(f64),
     Str(String),
+    /// Pattern of a `#rx"..."` literal
+    Regex(String),
     Symbol(String),
     LParen,
     RParen,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
 }
 
 impl fmt::Display for Token {
@@ -17,9 +24,14 @@ impl fmt::Display for Token {
             Token::Integer(n) => write!(f, "{}", n),
             Token::Float(n) => write!(f, "{}", n),
             Token::Str(s) => write!(f, "{}", s),
+            Token::Regex(s) => write!(f, "#rx\"{}\"", s),
             Token::Symbol(s) => write!(f, "{}", s),
             Token::RParen => write!(f, "("),
             Token::LParen => write!(f, ")"),
+            Token::Quote => write!(f, "'"),
+            Token::Quasiquote => write!(f, "`"),
+            Token::Unquote => write!(f, ","),
+            Token::UnquoteSplicing => write!(f, ",@"),
         }
     }
 }
@@ -27,6 +39,7 @@ impl fmt::Display for Token {
 #[derive(Debug)]
 pub struct TokenError {
     ch: char,
+    span: Span,
 }
 
 impl Error for TokenError {}
@@ -37,58 +50,125 @@ impl fmt::Display for TokenError {
     }
 }
 
+impl TokenError {
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl From<TokenError> for EvalError {
+    fn from(error: TokenError) -> Self {
+        EvalError::new(ErrorKind::Parse(error.to_string())).with_span(error.span)
+    }
+}
+
 /// Validate the correct position of the parens `()`
 ///
 /// ```lisp
 /// (concat "hola" ("mundo")
 /// // should throw error
 /// ```
-fn paren_validation(input: &str) -> bool {
-    let chars: Vec<char> = input.chars().filter(|c| *c == '(' || *c == ')').collect();
-    let left = chars.iter().filter(|c| **c == '(').count();
-    let right = chars.iter().filter(|c| **c == ')').count();
+fn paren_validation(input: &str) -> Result<(), TokenError> {
+    let mut open = Vec::new();
+    let mut in_str = false;
+
+    for (span, c) in spanned_chars(input) {
+        match c {
+            '"' => in_str = !in_str,
+            _ if in_str => {}
+            '(' => open.push(span),
+            ')' if open.pop().is_none() => return Err(TokenError { ch: '(', span }),
+            _ => {}
+        }
+    }
 
-    left == right
+    match open.pop() {
+        Some(span) => Err(TokenError { ch: ')', span }),
+        None => Ok(()),
+    }
+}
+
+fn spanned_chars(input: &str) -> impl Iterator<Item = (Span, char)> + '_ {
+    let mut span = Span { line: 1, col: 1 };
+
+    input.chars().map(move |c| {
+        let current = span;
+        if c == '\n' {
+            span = Span { line: span.line + 1, col: 1 };
+        } else {
+            span.col += 1;
+        }
+        (current, c)
+    })
 }
 
+/// Tokenize without positions
 pub fn tokenize(program: &str) -> Result<Vec<Token>, TokenError> {
-    if !paren_validation(program) {
-        return Err(TokenError { ch: ')' });
-    }
+    let tokens = tokenize_spanned(program)?;
+
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
 
-    let program_fmt = program.replace('(', " ( ").replace(')', " ) ");
+/// Tokenize keeping the position where every token starts
+pub fn tokenize_spanned(program: &str) -> Result<Vec<(Token, Span)>, TokenError> {
+    paren_validation(program)?;
+
+    // Pad the parens out of strings with spaces, every char keeps its
+    // original position
+    let mut chars: Vec<char> = Vec::new();
+    let mut spans: Vec<Span> = Vec::new();
+    let mut in_str = false;
+    for (span, c) in spanned_chars(program) {
+        in_str ^= c == '"';
+        if (c == '(' || c == ')') && !in_str {
+            chars.extend([' ', c, ' ']);
+            spans.extend([span, span, span]);
+        } else {
+            chars.push(c);
+            spans.push(span);
+        }
+    }
 
-    let mut tokens: Vec<Token> = Vec::new();
-    let chars: Vec<char> = program_fmt.chars().collect();
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
     let mut build_str = false;
+    let mut build_regex = false;
     let mut build_num = false;
     let mut pos_num = String::new();
     let mut pos_str = String::new();
     let mut pos_sym = String::new();
+    let mut start = Span { line: 1, col: 1 };
+    let mut skip_next = false;
+
+    for (i, c) in chars.iter().copied().enumerate() {
+        let span = spans[i];
+
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
 
-    for (i, c) in program_fmt.chars().enumerate() {
         match c {
-            '(' => tokens.push(Token::LParen),
-            ')' => tokens.push(Token::RParen),
+            '(' if !build_str => tokens.push((Token::LParen, span)),
+            ')' if !build_str => tokens.push((Token::RParen, span)),
             _ => {
                 if c.is_whitespace() && !build_str {
                     let i = pos_num.parse::<i64>();
                     let f = pos_num.parse::<f64>();
 
                     if let Ok(integer) = i {
-                        tokens.push(Token::Integer(integer));
+                        tokens.push((Token::Integer(integer), start));
                         pos_num = "".to_string();
                         build_num = false;
                         continue;
                     } else if let Ok(float) = f {
-                        tokens.push(Token::Float(float));
+                        tokens.push((Token::Float(float), start));
                         pos_num = "".to_string();
                         build_num = false;
                         continue;
                     }
 
                     if !pos_sym.is_empty() {
-                        tokens.push(Token::Symbol(pos_sym.to_string()));
+                        tokens.push((Token::Symbol(pos_sym.to_string()), start));
                         pos_sym = "".to_string();
                     }
 
@@ -101,6 +181,9 @@ pub fn tokenize(program: &str) -> Result<Vec<Token>, TokenError> {
                 }
 
                 if c.is_numeric() && !build_str && pos_sym.is_empty() {
+                    if pos_num.is_empty() {
+                        start = span;
+                    }
                     pos_num.push(c);
                     build_num = true;
                     build_str = false;
@@ -109,9 +192,13 @@ pub fn tokenize(program: &str) -> Result<Vec<Token>, TokenError> {
 
                 if build_str {
                     if c == '"' {
-                        tokens.push(Token::Str(pos_str.to_string()));
+                        match build_regex {
+                            true => tokens.push((Token::Regex(pos_str.to_string()), start)),
+                            false => tokens.push((Token::Str(pos_str.to_string()), start)),
+                        }
                         pos_str = "".to_string();
                         build_str = false;
+                        build_regex = false;
                         continue;
                     }
 
@@ -119,11 +206,40 @@ pub fn tokenize(program: &str) -> Result<Vec<Token>, TokenError> {
                     continue;
                 }
 
+                // The `#rx` before the quote makes the string a regex
+                if c == '"' && pos_sym == "#rx" {
+                    pos_sym = "".to_string();
+                    build_str = true;
+                    build_regex = true;
+                    continue;
+                }
+
                 if c == '"' {
+                    start = span;
                     build_str = true;
                     continue;
                 }
 
+                if matches!(c, '\'' | '`' | ',') && pos_sym.is_empty() && !build_num {
+                    match c {
+                        '\'' => tokens.push((Token::Quote, span)),
+                        '`' => tokens.push((Token::Quasiquote, span)),
+                        _ => {
+                            if chars.get(i + 1) == Some(&'@') {
+                                tokens.push((Token::UnquoteSplicing, span));
+                                skip_next = true;
+                            } else {
+                                tokens.push((Token::Unquote, span));
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                if pos_sym.is_empty() && pos_num.is_empty() {
+                    start = span;
+                }
+
                 if c.is_ascii() && !build_num || c == '+' && c == '-' {
                     if c != '+' && c != '-' {
                         pos_sym.push(c);
@@ -134,7 +250,7 @@ pub fn tokenize(program: &str) -> Result<Vec<Token>, TokenError> {
                         if next == &' ' {
                             pos_sym.push(c);
                             continue;
-                        } else if next.is_numeric() {
+                        } else if next.is_numeric() && pos_sym.is_empty() {
                             pos_num.push(c);
                             continue;
                         }
@@ -277,4 +393,71 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn parens_in_strings_and_regexes() {
+        let program = r#"(f "a (b" #rx"(\d+)) x")"#;
+
+        let tokens = tokenize(program).unwrap_or(vec![]);
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LParen,
+                Token::Symbol("f".to_string()),
+                Token::Str("a (b".to_string()),
+                Token::Regex(r"(\d+)) x".to_string()),
+                Token::RParen
+            ]
+        );
+    }
+
+    #[test]
+    fn quote_tokens() {
+        let program = "(`(if ,c ,@body) '(1 2))";
+
+        let tokens = tokenize(program).unwrap_or(vec![]);
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LParen,
+                Token::Quasiquote,
+                Token::LParen,
+                Token::Symbol("if".to_string()),
+                Token::Unquote,
+                Token::Symbol("c".to_string()),
+                Token::UnquoteSplicing,
+                Token::Symbol("body".to_string()),
+                Token::RParen,
+                Token::Quote,
+                Token::LParen,
+                Token::Integer(1),
+                Token::Integer(2),
+                Token::RParen,
+                Token::RParen
+            ]
+        );
+    }
+
+    #[test]
+    fn token_positions() {
+        let program = "(define x\n  \"a b\" -3)";
+
+        let spans = tokenize_spanned(program)
+            .unwrap()
+            .into_iter()
+            .map(|(_, span)| (span.line, span.col))
+            .collect::<Vec<_>>();
+
+        assert_eq!(spans, vec![(1, 1), (1, 2), (1, 9), (2, 3), (2, 9), (2, 11)]);
+    }
+
+    #[test]
+    fn unmatched_paren_position() {
+        let error = tokenize("(print 1))").unwrap_err();
+
+        assert_eq!(error.to_string(), "expected character: (");
+        assert_eq!(error.span(), Span { line: 1, col: 10 });
+    }
 }
@@ -0,0 +1,50 @@
+//! Interpreter of Lisp language
+//!
+//! Embedding it takes an environment and a program, the environment
+//! keeps the definitions between evaluations and bounds them, in
+//! steps, depth, time and memory
+//!
+//! ```
+//! use lisp_inrs::env::Env;
+//! use lisp_inrs::error::ErrorKind;
+//! use lisp_inrs::eval::eval;
+//! use lisp_inrs::limits::Limits;
+//! use lisp_inrs::object::Object;
+//!
+//! let mut env = Env::new();
+//! env.set_limits(Limits {
+//!     max_steps: Some(10_000),
+//!     max_depth: Some(500),
+//!     max_nesting: Some(100),
+//!     max_time: None,
+//!     max_allocation: Some(1 << 20),
+//! });
+//!
+//! let result = eval("(+ 1 2)", &mut env).unwrap();
+//! assert_eq!(result, Object::Integer(3));
+//!
+//! let error = eval("((define f (lambda (n) (f n))) (f 1))", &mut env).unwrap_err();
+//! assert!(matches!(error.kind, ErrorKind::ResourceLimit(_)));
+//! ```
+
+pub mod condition;
+pub mod continuation;
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod expand;
+pub mod format;
+pub mod generic;
+pub mod lexer;
+pub mod limits;
+pub mod load;
+pub mod module;
+pub mod object;
+pub mod parser;
+pub mod pattern;
+pub mod port;
+pub mod promise;
+pub mod regexp;
+pub mod sandbox;
+pub mod strings;
+pub mod syntax;
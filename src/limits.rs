@@ -0,0 +1,165 @@
+use crate::error::{ErrorKind, EvalError};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Depth allowed by default, the frames of the forms waiting for a
+/// value live on the heap, a runaway recursion only costs memory
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// Evaluations nested by default by the calls the interpreter makes
+/// itself, like the methods of a generic function or `force`. Each one
+/// takes native stack, this fits the 8 MB of a main thread in a debug
+/// build, embedders running on smaller stacks should lower `max_nesting`
+pub const DEFAULT_MAX_NESTING: usize = 100;
+
+/// The clock is read once every this many steps
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Bounds for one evaluation, `None` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Forms evaluated
+    pub max_steps: Option<u64>,
+    /// Nesting of forms being evaluated, calls add to it unless they
+    /// are the last form of a procedure
+    pub max_depth: Option<usize>,
+    /// Evaluations started inside another one, they add to the depth too
+    pub max_nesting: Option<usize>,
+    /// Wall-clock time
+    pub max_time: Option<Duration>,
+    /// Bytes allocated in total for strings, lists, records and scopes,
+    /// a quota that memory freed during the evaluation does not refill
+    pub max_allocation: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_nesting: Some(DEFAULT_MAX_NESTING),
+            max_time: None,
+            max_allocation: None,
+        }
+    }
+}
+
+/// What an evaluation has used of its limits
+///
+/// The copies of an environment share it, the counters start
+/// again when an evaluation begins at depth 0
+#[derive(Debug, Default, PartialEq)]
+pub struct Budget {
+    pub limits: Limits,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    nesting: Cell<usize>,
+    started: Cell<Option<Instant>>,
+    allocated: Cell<usize>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Budget {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Reset the counters unless an evaluation is already running
+    pub fn start(&self) {
+        if self.depth.get() == 0 {
+            self.steps.set(0);
+            self.allocated.set(0);
+            self.started.set(Some(Instant::now()));
+        }
+    }
+
+    /// Account for an evaluation about to start, inside the running one
+    /// when there is one
+    pub fn enter(&self) -> Result<(), EvalError> {
+        let nesting = self.nesting.get() + 1;
+
+        if let Some(max) = self.limits.max_nesting {
+            if nesting > max {
+                return Err(exceeded(format!("more than {} nested evaluations", max)));
+            }
+        }
+
+        self.step()?;
+        self.descend()?;
+        self.nesting.set(nesting);
+        Ok(())
+    }
+
+    /// The evaluation started by `enter` is over
+    pub fn exit(&self) {
+        self.nesting.set(self.nesting.get().saturating_sub(1));
+        self.leave();
+    }
+
+    /// Count a step, the clock is checked every few of them
+    pub fn step(&self) -> Result<(), EvalError> {
+        let steps = self.steps.get() + 1;
+
+        if let Some(max) = self.limits.max_steps {
+            if steps > max {
+                return Err(exceeded(format!("more than {} steps", max)));
+            }
+        }
+
+        if let (Some(max), Some(started)) = (self.limits.max_time, self.started.get()) {
+            if steps.is_multiple_of(CLOCK_INTERVAL) && started.elapsed() > max {
+                return Err(exceeded(format!("more than {:?}", max)));
+            }
+        }
+
+        self.steps.set(steps);
+        Ok(())
+    }
+
+    /// Go one level deeper, as a call or a form waiting for a subform
+    pub fn descend(&self) -> Result<(), EvalError> {
+        let depth = self.depth.get() + 1;
+
+        if let Some(max) = self.limits.max_depth {
+            if depth > max {
+                return Err(exceeded(format!("depth greater than {}", max)));
+            }
+        }
+
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    pub fn leave(&self) {
+        self.depth.set(self.depth.get().saturating_sub(1));
+    }
+
+    /// Charge bytes the evaluation allocates to its quota, nothing is
+    /// given back when they are freed
+    pub fn allocate(&self, bytes: usize) -> Result<(), EvalError> {
+        let allocated = self.allocated.get().saturating_add(bytes);
+
+        if let Some(max) = self.limits.max_allocation {
+            if allocated > max {
+                return Err(exceeded(format!("more than {} bytes allocated", max)));
+            }
+        }
+
+        self.allocated.set(allocated);
+        Ok(())
+    }
+
+    /// Bytes allocated since the evaluation began
+    pub fn allocated(&self) -> usize {
+        self.allocated.get()
+    }
+}
+
+fn exceeded(msg: String) -> EvalError {
+    let mut error = EvalError::new(ErrorKind::ResourceLimit(msg));
+    // The program can not handle it, it would go on using the resource
+    error.signaled = true;
+    error
+}
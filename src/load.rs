@@ -0,0 +1,90 @@
+use crate::env::{Env, LOAD_PATH};
+use crate::error::EvalError;
+use crate::object::Object;
+use std::path::{Path, PathBuf};
+
+/// Environment variable with the directories of `*load-path*`,
+/// separated as in `PATH`
+pub const LOAD_PATH_VAR: &str = "LISP_LOAD_PATH";
+
+/// Files to try for a name, `name.lisp` is preferred to `name.cl`
+/// when the name has no extension
+fn candidates(name: &str) -> Result<Vec<String>, EvalError> {
+    match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some("lisp" | "cl") => Ok(vec![name.to_string()]),
+        Some(ext) => Err(EvalError::io(format!("Invalid file extension: {}", ext))),
+        _ => Ok(vec![format!("{}.lisp", name), format!("{}.cl", name)]),
+    }
+}
+
+/// Directories of `*load-path*`, the strings of the list
+pub fn load_path(env: &Env) -> Vec<PathBuf> {
+    match env.get(LOAD_PATH) {
+        Some(Object::List(dirs)) => dirs
+            .iter()
+            .filter_map(|dir| match dir {
+                Object::Str(dir) => Some(PathBuf::from(dir)),
+                _ => None,
+            })
+            .collect(),
+        Some(Object::Str(dir)) => vec![PathBuf::from(dir)],
+        _ => Vec::new(),
+    }
+}
+
+/// `*load-path*` for the directories of a `LISP_LOAD_PATH` value
+pub fn load_path_value(dirs: &[PathBuf]) -> Object {
+    Object::List(
+        dirs.iter()
+            .map(|dir| Object::Str(dir.display().to_string()))
+            .collect(),
+    )
+}
+
+/// Find the file `load`, `require` and `import` read for `name`
+///
+/// A relative name is looked up first next to the file being loaded,
+/// the current directory at the top level, then in each directory
+/// of `*load-path*`
+pub fn resolve(name: &str, env: &Env) -> Result<PathBuf, EvalError> {
+    let candidates = candidates(name)?;
+
+    let mut dirs = Vec::new();
+    if Path::new(name).is_absolute() {
+        dirs.push(PathBuf::new());
+    } else {
+        let current = env.modules().borrow().current_file();
+        dirs.push(match current.as_ref().and_then(|file| file.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::new(),
+        });
+        dirs.extend(load_path(env));
+    }
+
+    // The sandbox checks a candidate before looking for it, so whether a
+    // denied file exists stays hidden
+    let mut denied = None;
+    for file in dirs.iter().flat_map(|dir| candidates.iter().map(move |file| dir.join(file))) {
+        match env.sandbox().check_load(&file) {
+            Ok(()) if file.is_file() => return Ok(file),
+            Ok(()) => {}
+            Err(error) => {
+                denied.get_or_insert(error);
+            }
+        }
+    }
+
+    Err(denied.unwrap_or_else(|| EvalError::io(format!("Module {} not found", name))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions() {
+        assert_eq!(candidates("lib/util").unwrap(), vec!["lib/util.lisp", "lib/util.cl"]);
+        assert_eq!(candidates("util.cl").unwrap(), vec!["util.cl"]);
+        assert!(candidates("util.txt").is_err());
+    }
+}
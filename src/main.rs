@@ -1,47 +1,262 @@
-mod env;
-mod eval;
-mod lexer;
-mod object;
-mod parser;
-
-use linefeed::{Interface, ReadResult};
-use object::Object;
+use linefeed::{DefaultTerminal, Interface, ReadResult};
+use lisp_inrs::condition::{self, Restart};
+use lisp_inrs::error::{ErrorKind, EvalError};
+use lisp_inrs::limits::Limits;
+use lisp_inrs::object::Object;
+use lisp_inrs::sandbox::{Capability, Sandbox};
+use lisp_inrs::{env, eval, load};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 const PROMPT: &str = "lisp-rs> ";
 
-fn repl() -> Result<(), Box<dyn std::error::Error>> {
-    let reader = Interface::new(PROMPT).unwrap();
-    let mut env = env::Env::new();
+/// Stack of the interpreter thread. Calls made by the interpreter
+/// itself, like the methods of a generic function, nest evaluations on
+/// it, this fits `MAX_NESTING` of them
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Nested evaluations allowed by default, more than the library allows
+/// as the interpreter thread has a larger stack
+const MAX_NESTING: usize = 2_000;
+
+fn read_answer(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    std::io::stdout().flush().ok()?;
+
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    }
+}
+
+/// Offer the active restarts, numbered, when an error has no handler
+fn debugger(condition: &Object, restarts: &[Restart], env: &mut env::Env) -> Option<(Restart, Vec<Object>)> {
+    match condition {
+        Object::Condition(c) => println!("Error: {}", c.message),
+        other => println!("Error: {}", other.repr()),
+    }
+
+    println!("Restarts:");
+    for (i, restart) in restarts.iter().enumerate() {
+        println!("  {}: [{}]", i, restart.name);
+    }
+
+    let restart = loop {
+        let answer = read_answer("Choose a restart: ")?;
+        match answer.parse::<usize>().ok().and_then(|i| restarts.get(i)) {
+            Some(restart) => break restart.clone(),
+            None => println!("Invalid restart: {}", answer),
+        }
+    };
+
+    let mut args = Vec::new();
+    for param in &restart.params {
+        let answer = read_answer(&format!("{}: ", param))?;
+
+        // A failing value lets the error unwind instead of nesting debuggers
+        condition::set_debugger(None);
+        // Programs are lists, the answer is read as a sequence of one form
+        let value = eval::eval(&format!("({})", answer), env);
+        condition::set_debugger(Some(debugger));
+
+        match value {
+            Ok(Object::List(mut values)) if values.len() == 1 => args.push(values.remove(0)),
+            Ok(value) => args.push(value),
+            Err(err) => {
+                println!("Error: {}", err);
+                return None;
+            }
+        }
+    }
 
-    reader.set_prompt(PROMPT)?;
+    Some((restart, args))
+}
 
+/// Evaluate an input of the REPL with the `abort` and `retry` restarts
+fn eval_input(input: &str, env: &mut env::Env) -> Result<Object, EvalError> {
     loop {
-        let raw_input = reader.read_line()?;
+        let abort = condition::push_restart("abort", vec![]);
+        let retry = condition::push_restart("retry", vec![]);
+        let result = eval::eval(input, env);
+        condition::pop_restarts(2);
 
+        match result {
+            Err(EvalError {
+                kind: ErrorKind::Restart { id, .. },
+                ..
+            }) if id == retry => continue,
+            Err(EvalError {
+                kind: ErrorKind::Restart { id, .. },
+                ..
+            }) if id == abort => {
+                println!("Aborted");
+                return Ok(Object::Void);
+            }
+            result => return result,
+        }
+    }
+}
 
-        let input = match raw_input {
-            ReadResult::Input(input) => input,
-            ReadResult::Signal(_) => break,
-            _ => break,
+/// Settings given on the command line
+#[derive(Debug, Default)]
+struct Options {
+    strict_float: bool,
+    load_verbose: bool,
+    limits: Limits,
+    sandbox: Option<Sandbox>,
+    load_path: Vec<PathBuf>,
+}
+
+impl Options {
+    /// `--strict-float`, `--load-verbose`, `--max-steps=N`, `--max-depth=N`,
+    /// `--max-nesting=N`, `--max-time=SECONDS`, `--max-allocation=BYTES` and the sandbox ones,
+    /// `--sandbox`, `--allow=CAPABILITY,...` and `--load-dir=DIR`, and
+    /// `--load-path=DIR` that can be repeated. Any other option is an error
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            limits: Limits {
+                max_nesting: Some(MAX_NESTING),
+                ..Limits::default()
+            },
+            ..Options::default()
         };
 
+        for arg in args {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            match (flag, value) {
+                ("--strict-float", None) => options.strict_float = true,
+                ("--load-verbose", None) => options.load_verbose = true,
+                ("--max-steps", Some(n)) => options.limits.max_steps = Some(parse_number(flag, n)?),
+                ("--max-depth", Some(n)) => options.limits.max_depth = Some(parse_number(flag, n)?),
+                ("--max-nesting", Some(n)) => options.limits.max_nesting = Some(parse_number(flag, n)?),
+                ("--max-allocation", Some(n)) => options.limits.max_allocation = Some(parse_number(flag, n)?),
+                ("--max-time", Some(secs)) => {
+                    let secs = secs.parse::<f64>().ok().and_then(|s| Duration::try_from_secs_f64(s).ok());
+                    options.limits.max_time = Some(secs.ok_or(format!("Invalid value for {}: {}", flag, arg))?);
+                }
+                ("--sandbox", None) => {
+                    options.sandbox.get_or_insert_with(Sandbox::deny_all);
+                }
+                ("--allow", Some(names)) => {
+                    for name in names.split(',') {
+                        let capability = Capability::from_name(name).ok_or(format!("Unknown capability: {}", name))?;
+                        let sandbox = options.sandbox.take().unwrap_or_else(Sandbox::deny_all);
+                        options.sandbox = Some(sandbox.allow(capability));
+                    }
+                }
+                ("--load-dir", Some(dir)) => {
+                    let sandbox = options.sandbox.take().unwrap_or_else(Sandbox::deny_all);
+                    options.sandbox = Some(sandbox.allow_load_dir(dir));
+                }
+                ("--load-path", Some(dir)) => options.load_path.push(PathBuf::from(dir)),
+                (
+                    "--max-steps" | "--max-depth" | "--max-nesting" | "--max-time" | "--max-allocation" | "--allow" | "--load-dir"
+                    | "--load-path",
+                    None,
+                ) => {
+                    return Err(format!("{} needs a value, as in {}=1000", flag, flag));
+                }
+                ("--expand", None) => {}
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                _ => {}
+            }
+        }
+
+        // The directories of the environment variable come after the flags
+        if let Some(dirs) = std::env::var_os(load::LOAD_PATH_VAR) {
+            options.load_path.extend(std::env::split_paths(&dirs));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+/// Environment of a session, `--strict-float` binds `*strict-float*`,
+/// `--load-verbose` binds `*load-verbose*` and the load path `*load-path*`
+fn new_env(options: &Options) -> env::Env {
+    let mut env = env::Env::new();
+    env.set(env::LOAD_PATH, load::load_path_value(&options.load_path));
+    env.set_limits(options.limits);
+    if let Some(sandbox) = &options.sandbox {
+        env.set_sandbox(sandbox.clone());
+    }
+    if options.strict_float {
+        env.set(env::STRICT_FLOAT, Object::Bool(true));
+    }
+    if options.load_verbose {
+        env.set(env::LOAD_VERBOSE, Object::Bool(true));
+    }
+    env
+}
+
+/// Source of the REPL lines, plain stdin when linefeed does not
+/// support the terminal, as when the input is piped
+enum Reader {
+    Terminal(Box<Interface<DefaultTerminal>>),
+    Plain,
+}
+
+impl Reader {
+    fn new() -> Self {
+        match Interface::new(PROMPT) {
+            Ok(interface) => Reader::Terminal(Box::new(interface)),
+            Err(_) => Reader::Plain,
+        }
+    }
+
+    /// Next line, `None` at the end of the input
+    fn read_line(&self) -> std::io::Result<Option<String>> {
+        match self {
+            Reader::Terminal(interface) => match interface.read_line()? {
+                ReadResult::Input(input) => Ok(Some(input)),
+                _ => Ok(None),
+            },
+            Reader::Plain => Ok(read_answer(PROMPT)),
+        }
+    }
+
+    fn add_history(&self, input: &str) {
+        if let Reader::Terminal(interface) = self {
+            interface.add_history_unique(input.to_string());
+        }
+    }
+}
+
+fn repl(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = Reader::new();
+    let mut env = new_env(options);
+
+    condition::set_debugger(Some(debugger));
+
+    while let Some(input) = reader.read_line()? {
+
         if input.eq("exit") || input.eq("q") {
             break;
         }
 
-        if input.eq("") {
+        if input.is_empty() {
             continue;
         }
 
-        reader.add_history_unique(input.to_string());
+        reader.add_history(&input);
 
         if input.eq("clean") {
-            env = env::Env::new();
+            env = new_env(options);
             println!("Env cleaned 🗑️");
             continue;
         }
 
-        let val = match eval::eval(input.as_ref(), &mut env) {
+        let val = match eval_input(input.as_ref(), &mut env) {
             Ok(data) => data,
             Err(err) => {
                 println!("Error: {}", err);
@@ -55,6 +270,12 @@ fn repl() -> Result<(), Box<dyn std::error::Error>> {
             Object::Bool(b) => println!("{}", b),
             Object::Symbol(s) => println!("{}", s),
             Object::Str(s) => println!("{}", s),
+            Object::Record(_) | Object::RecordType(_) | Object::RecordProc(_) => println!("{}", val),
+            Object::Values(values) => {
+                for value in values {
+                    println!("{}", value);
+                }
+            }
             Object::Lambda(params, body) => {
                 println!("Lambda(");
                 for param in params {
@@ -90,19 +311,16 @@ fn repl() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn execute(file: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let parts = file.split('.').collect::<Vec<&str>>();
-    let ext = parts.get(1).expect("Filename not correctly").to_string();
+fn execute(file: &str, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let ext = Path::new(file).extension().and_then(|ext| ext.to_str());
 
-    if ext != "lisp" && ext != "cl" {
-        panic!("extension not correct, file not valid");
+    if ext != Some("lisp") && ext != Some("cl") {
+        return Err(format!("Invalid file {}, expected a .lisp or .cl extension", file).into());
     }
 
-    let program = std::fs::read_to_string(file).expect("Should have been able to read the file");
+    let mut env = new_env(options);
 
-    let mut env = env::Env::new();
-
-    let result = eval::eval(program.as_ref(), &mut env)?;
+    let result = eval::eval_file(Path::new(file), &mut env)?;
     if let Object::List(l) = result {
         if l.len() == 1 {
             println!("{}", l[0]);
@@ -119,15 +337,66 @@ fn execute(file: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
+/// Print the program after macros and derived forms are expanded, the
+/// macros run in `env` with its limits and sandbox
+fn dump_expansion(file: &str, env: &mut env::Env) -> Result<(), Box<dyn std::error::Error>> {
+    let program = std::fs::read_to_string(file)?;
 
-    if args.len() == 1 {
-        repl()?;
-    } else if args.len() >= 2 {
-        let file = &args[1];
-        execute(file)?;
+    let expanded = eval::expand_source(program.as_ref(), env)?;
+    println!("{}", expanded.repr());
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let expand_only = args.iter().any(|a| a == "--expand");
+    let options = Options::parse(args)?;
+    let files = args.iter().filter(|a| !a.starts_with("--")).collect::<Vec<_>>();
+
+    match files.first() {
+        None => repl(&options)?,
+        Some(file) if expand_only => dump_expansion(file, &mut new_env(&options))?,
+        Some(file) => execute(file, &options)?,
     }
 
     Ok(())
 }
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let interpreter = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(&args).map_err(|err| err.to_string()));
+
+    let result = match interpreter {
+        Ok(handle) => handle.join().unwrap_or_else(|_| Err("The interpreter stopped unexpectedly".to_string())),
+        Err(err) => Err(format!("Could not start the interpreter: {}", err)),
+    };
+
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_runs_macros_in_the_sandbox() {
+        let dir = std::env::temp_dir().join(format!("lisp-expand-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("secret.lisp");
+        std::fs::write(&secret, "((define secret 1))").unwrap();
+        let file = dir.join("macro.lisp");
+        std::fs::write(&file, format!("((defmacro m () (load \"{}\")) (m))", secret.display())).unwrap();
+
+        let args = ["--expand", "--sandbox", file.to_str().unwrap()].map(String::from);
+        let options = Options::parse(&args).unwrap();
+        let error = dump_expansion(&args[2], &mut new_env(&options)).unwrap_err();
+        assert!(error.to_string().contains("outside of the allowed directories"), "{}", error);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
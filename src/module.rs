@@ -0,0 +1,349 @@
+use crate::object::Object;
+use crate::pattern;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Module evaluated by `(module name (export ...) body...)`
+///
+/// Its definitions are renamed, `name:sym` for the exported ones and
+/// `name sym` for the rest, so they can live in the environment of the
+/// importer without clashing. The lexer splits symbols at spaces, so
+/// the importer can not name the private ones. Classes and record
+/// types keep their names. Lambdas are not closures, the importer gets
+/// every binding the exported values may need
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub exports: Vec<String>,
+    pub bindings: Vec<(String, Object)>,
+}
+
+impl Module {
+    /// Global name of an exported symbol
+    pub fn qualified(&self, export: &str) -> String {
+        qualified(&self.name, export)
+    }
+}
+
+pub fn qualified(module: &str, name: &str) -> String {
+    format!("{}:{}", module, name)
+}
+
+pub fn private(module: &str, name: &str) -> String {
+    format!("{} {}", module, name)
+}
+
+/// Modules already evaluated, by name and by the file that defined them,
+/// and the files being loaded
+#[derive(Debug, Default, PartialEq)]
+pub struct Registry {
+    modules: HashMap<String, Rc<Module>>,
+    files: HashMap<PathBuf, String>,
+    /// Files being loaded, the innermost last
+    loading: Vec<PathBuf>,
+    /// Files loaded by `require`
+    required: HashSet<PathBuf>,
+}
+
+impl Registry {
+    pub fn get(&self, name: &str) -> Option<Rc<Module>> {
+        self.modules.get(name).cloned()
+    }
+
+    pub fn insert(&mut self, module: Module) {
+        self.modules.insert(module.name.clone(), Rc::new(module));
+    }
+
+    /// Module a file has defined, files are evaluated only once
+    pub fn from_file(&self, path: &PathBuf) -> Option<Rc<Module>> {
+        self.files.get(path).and_then(|name| self.get(name))
+    }
+
+    pub fn set_file(&mut self, path: PathBuf, name: &str) {
+        self.files.insert(path, name.to_string());
+    }
+
+    /// Mark a file as being loaded, false when it already was,
+    /// which means that two files load each other
+    pub fn start_loading(&mut self, path: PathBuf) -> bool {
+        if self.loading.contains(&path) {
+            return false;
+        }
+
+        self.loading.push(path);
+        true
+    }
+
+    pub fn finish_loading(&mut self) {
+        self.loading.pop();
+    }
+
+    /// File being loaded, relative names are resolved from its directory
+    pub fn current_file(&self) -> Option<PathBuf> {
+        self.loading.last().cloned()
+    }
+
+    /// Record a file loaded by `require`, false when it already was
+    pub fn require(&mut self, path: PathBuf) -> bool {
+        self.required.insert(path)
+    }
+
+    pub fn forget_required(&mut self, path: &PathBuf) {
+        self.required.remove(path);
+    }
+}
+
+fn is_form(items: &[Object], name: &str) -> bool {
+    matches!(items.first(), Some(Object::Keyword(k)) if k == name)
+}
+
+/// Names defined at the top level of a module body, inside `begin` too
+pub fn definitions(forms: &[Object], names: &mut Vec<String>) {
+    for form in forms {
+        if let Object::List(items) = form {
+            if ["define", "defgeneric", "defmethod"].iter().any(|k| is_form(items, k)) {
+                if let Some(Object::Symbol(name)) = items.get(1) {
+                    names.push(name.clone());
+                }
+            } else if is_form(items, "define-values") {
+                names.extend(items.get(1).map(symbols).unwrap_or_default().into_iter().filter(|s| s != "."));
+            } else if is_form(items, "defclass") {
+                names.extend(slot_options(items, ":accessor").into_iter().flat_map(symbols));
+            } else if is_form(items, "define-record-type") {
+                record_definitions(items, names);
+            } else if is_form(items, "begin") {
+                definitions(&items[1..], names);
+            }
+        }
+    }
+}
+
+/// Names of the classes and record types defined at the top level of a
+/// module body, they are not renamed
+pub fn type_names(forms: &[Object]) -> Vec<String> {
+    let mut names = Vec::new();
+    for form in forms {
+        if let Object::List(items) = form {
+            if is_form(items, "defclass") || is_form(items, "define-record-type") {
+                names.extend(items.get(1).map(symbols).unwrap_or_default());
+            } else if is_form(items, "begin") {
+                names.extend(type_names(&items[1..]));
+            }
+        }
+    }
+    names
+}
+
+/// The constructor, predicate, accessors and modifiers of
+/// `(define-record-type name (constructor field...) predicate (field accessor modifier)...)`,
+/// the type keeps its name so records show it
+fn record_definitions(items: &[Object], names: &mut Vec<String>) {
+    if let Some(Object::List(constructor)) = items.get(2) {
+        names.extend(constructor.first().map(symbols).unwrap_or_default());
+    }
+    if let Some(Object::Symbol(predicate)) = items.get(3) {
+        names.push(predicate.clone());
+    }
+    for spec in items.iter().skip(4) {
+        if let Object::List(spec) = spec {
+            names.extend(spec.iter().skip(1).flat_map(symbols));
+        }
+    }
+}
+
+/// Values of an option in the slots of `(defclass name (super...) (slot...))`
+fn slot_options<'a>(items: &'a [Object], option: &str) -> Vec<&'a Object> {
+    let mut values = Vec::new();
+    if let Some(Object::List(slots)) = items.get(3) {
+        for slot in slots {
+            if let Object::List(slot) = slot {
+                for pair in slot[1..].chunks(2) {
+                    if let [Object::Symbol(o), value] = pair {
+                        if o == option {
+                            values.push(value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    values
+}
+
+/// Rename the references to module level names, the parameters
+/// and handler variables that shadow them are left alone
+pub fn rename(form: &Object, renames: &HashMap<String, String>, shadowed: &HashSet<String>) -> Object {
+    let items = match form {
+        Object::Symbol(s) if !shadowed.contains(s) => {
+            return match renames.get(s) {
+                Some(name) => Object::Symbol(name.clone()),
+                None => form.clone(),
+            };
+        }
+        Object::List(items) => items,
+        _ => return form.clone(),
+    };
+
+    let rename_all = |items: &[Object], shadowed: &HashSet<String>| {
+        items.iter().map(|item| rename(item, renames, shadowed)).collect::<Vec<_>>()
+    };
+
+    let head = match items.first() {
+        Some(Object::Keyword(k)) => k.as_str(),
+        _ => return Object::List(rename_all(items, shadowed)),
+    };
+
+    match head {
+        "quote" | "module" => form.clone(),
+        "quasiquote" => Object::List(items.iter().map(|item| rename_unquoted(item, renames, shadowed)).collect()),
+        "lambda" | "shift" if items.len() >= 2 => {
+            let mut inner = shadowed.clone();
+            inner.extend(symbols(&items[1]));
+
+            let mut renamed = vec![items[0].clone(), items[1].clone()];
+            renamed.extend(rename_all(&items[2..], &inner));
+            Object::List(renamed)
+        }
+        "guard" if items.len() >= 2 => {
+            let mut renamed = vec![items[0].clone()];
+            match &items[1] {
+                Object::List(spec) if !spec.is_empty() => {
+                    let mut inner = shadowed.clone();
+                    inner.extend(symbols(&spec[0]));
+
+                    let mut clauses = vec![spec[0].clone()];
+                    clauses.extend(rename_all(&spec[1..], &inner));
+                    renamed.push(Object::List(clauses));
+                }
+                other => renamed.push(other.clone()),
+            }
+            renamed.extend(rename_all(&items[2..], shadowed));
+            Object::List(renamed)
+        }
+        "restart-case" if items.len() >= 2 => {
+            let mut renamed = vec![items[0].clone(), rename(&items[1], renames, shadowed)];
+            for clause in &items[2..] {
+                match clause {
+                    Object::List(clause) if clause.len() >= 2 => {
+                        let mut inner = shadowed.clone();
+                        inner.extend(symbols(&clause[1]));
+
+                        let mut parts = vec![clause[0].clone(), clause[1].clone()];
+                        parts.extend(rename_all(&clause[2..], &inner));
+                        renamed.push(Object::List(parts));
+                    }
+                    other => renamed.push(other.clone()),
+                }
+            }
+            Object::List(renamed)
+        }
+        "define-record-type" if items.len() >= 4 => {
+            // Only the procedures are renamed, the type and the fields keep their names
+            let rename_at = |spec: &Object, procs: &dyn Fn(usize) -> bool| match spec {
+                Object::List(parts) => Object::List(
+                    parts
+                        .iter()
+                        .enumerate()
+                        .map(|(i, part)| match procs(i) {
+                            true => rename(part, renames, shadowed),
+                            false => part.clone(),
+                        })
+                        .collect(),
+                ),
+                other => other.clone(),
+            };
+
+            let mut renamed = vec![
+                items[0].clone(),
+                items[1].clone(),
+                rename_at(&items[2], &|i| i == 0),
+                rename(&items[3], renames, shadowed),
+            ];
+            renamed.extend(items[4..].iter().map(|spec| rename_at(spec, &|i| i > 0)));
+            Object::List(renamed)
+        }
+        "match" if items.len() >= 2 => {
+            // Pattern variables shadow in the body of their clause
+            let mut renamed = vec![items[0].clone(), rename(&items[1], renames, shadowed)];
+            for clause in &items[2..] {
+                match clause {
+                    Object::List(parts) if !parts.is_empty() => {
+                        let mut inner = shadowed.clone();
+                        inner.extend(pattern::variables(&parts[0]));
+
+                        let mut clause = vec![parts[0].clone()];
+                        clause.extend(rename_all(&parts[1..], &inner));
+                        renamed.push(Object::List(clause));
+                    }
+                    other => renamed.push(other.clone()),
+                }
+            }
+            Object::List(renamed)
+        }
+        "defmethod" if items.len() >= 3 => {
+            // The parameters shadow, the types they specialize on are not renamed
+            let params = match items[2] {
+                Object::Symbol(_) => 3,
+                _ => 2,
+            };
+            let mut inner = shadowed.clone();
+            if let Some(Object::List(specs)) = items.get(params) {
+                for spec in specs {
+                    match spec {
+                        Object::List(spec) => inner.extend(spec.first().map(symbols).unwrap_or_default()),
+                        other => inner.extend(symbols(other)),
+                    }
+                }
+            }
+
+            let mut renamed = vec![items[0].clone(), rename(&items[1], renames, shadowed)];
+            renamed.extend(items[2..].iter().take(params - 1).cloned());
+            renamed.extend(rename_all(items.get(params + 1..).unwrap_or_default(), &inner));
+            Object::List(renamed)
+        }
+        "defclass" if items.len() == 4 => {
+            // The class and its slots keep their names, accessors and initforms are renamed
+            let slots = match &items[3] {
+                Object::List(slots) => Object::List(
+                    slots
+                        .iter()
+                        .map(|slot| match slot {
+                            Object::List(parts) if !parts.is_empty() => {
+                                let mut renamed = vec![parts[0].clone()];
+                                for pair in parts[1..].chunks(2) {
+                                    renamed.push(pair[0].clone());
+                                    renamed.extend(rename_all(&pair[1..], shadowed));
+                                }
+                                Object::List(renamed)
+                            }
+                            other => other.clone(),
+                        })
+                        .collect(),
+                ),
+                other => other.clone(),
+            };
+            Object::List(vec![items[0].clone(), items[1].clone(), items[2].clone(), slots])
+        }
+        _ => Object::List(rename_all(items, shadowed)),
+    }
+}
+
+/// Inside a quasiquote only the unquoted forms are code
+fn rename_unquoted(form: &Object, renames: &HashMap<String, String>, shadowed: &HashSet<String>) -> Object {
+    match form {
+        Object::List(items) if is_form(items, "unquote") || is_form(items, "unquote-splicing") => {
+            rename(form, renames, shadowed)
+        }
+        Object::List(items) => Object::List(items.iter().map(|item| rename_unquoted(item, renames, shadowed)).collect()),
+        _ => form.clone(),
+    }
+}
+
+fn symbols(obj: &Object) -> Vec<String> {
+    match obj {
+        Object::Symbol(s) => vec![s.clone()],
+        Object::List(items) => items.iter().flat_map(symbols).collect(),
+        _ => Vec::new(),
+    }
+}
@@ -1,4 +1,70 @@
+use crate::continuation::{Continuation, Generator};
+use crate::generic::{Generic, NextMethod};
+use crate::port::Port;
+use crate::promise::Promise;
+use crate::regexp::Regexp;
+use std::cell::RefCell;
 use std::fmt;
+use std::rc::Rc;
+
+/// Condition raised by `error` or by a failing builtin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub kind: String,
+    pub message: String,
+    pub irritants: Vec<Object>,
+}
+
+/// Type defined by `define-record-type` or by `defclass`, with the
+/// names of its fields. Classes also have superclasses and the forms
+/// that initialize the fields `make-instance` is not given
+#[derive(Debug, PartialEq)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub supers: Vec<Rc<RecordType>>,
+    pub initforms: Vec<Option<Object>>,
+}
+
+impl RecordType {
+    /// Name without the angle brackets, `<point>` is shown as `point`
+    pub fn short_name(&self) -> &str {
+        self.name.trim_start_matches('<').trim_end_matches('>')
+    }
+}
+
+/// Instance of a record type, its copies share the fields so a
+/// modifier changes every reference to the record
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub rtype: Rc<RecordType>,
+    pub fields: Rc<RefCell<Vec<Object>>>,
+}
+
+/// Records are equal when they have the same type and equal fields
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.rtype, &other.rtype) && self.fields == other.fields
+    }
+}
+
+/// What a procedure made by `define-record-type` does, the
+/// constructor has the index of each of its fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordOp {
+    Constructor(Vec<usize>),
+    Predicate,
+    Accessor(usize),
+    Modifier(usize),
+}
+
+/// Procedure made by `define-record-type`
+#[derive(Debug, PartialEq)]
+pub struct RecordProc {
+    pub name: String,
+    pub rtype: Rc<RecordType>,
+    pub op: RecordOp,
+}
 
 /// Object in Lisp
 #[derive(Debug, Clone, PartialEq)]
@@ -27,10 +93,122 @@ pub enum Object {
     /// ```
     Str(String),
     Symbol(String),
-    Lambda(Vec<String>, Vec<Object>),
+    /// Parameters and body of a lambda, a parameter is a symbol or a
+    /// list of parameters that destructures its argument
+    Lambda(Vec<Object>, Vec<Object>),
+    /// Macro transformer defined with `defmacro`, it receives
+    /// the unevaluated forms and returns a new form
+    /// ```lisp
+    /// (defmacro my-unless (c x y) `(if ,c ,y ,x))
+    /// ```
+    Macro(Vec<String>, Vec<Object>),
+    /// Hygienic transformer created by `syntax-rules`, it holds
+    /// the literals and the `(pattern template)` rules
+    /// ```lisp
+    /// (syntax-rules (else) ((_ else e) e) ((_ c e) (if c e false)))
+    /// ```
+    SyntaxRules(Vec<String>, Vec<Object>),
+    /// Condition object, raised by `error` and caught by `guard`
+    /// ```lisp
+    /// (guard (e (true (condition-message e))) (error "Failed" 1))
+    /// ```
+    Condition(Box<Condition>),
+    /// Record type descriptor, bound to the name given to `define-record-type`
+    RecordType(Rc<RecordType>),
+    /// Record made by the constructor of a record type
+    /// ```lisp
+    /// (define-record-type point (make-point x y) point? (x point-x) (y point-y))
+    /// (make-point 1 2) // #<point x: 1 y: 2>
+    /// ```
+    Record(Record),
+    /// Constructor, predicate, accessor or modifier of a record type
+    RecordProc(Rc<RecordProc>),
+    /// Generic function, it runs the methods that apply to its arguments
+    /// ```lisp
+    /// (defgeneric area (shape))
+    /// (defmethod area ((s circle)) (* 3 (slot-value s 'r)))
+    /// ```
+    Generic(Rc<Generic>),
+    /// `call-next-method` in the body of a method
+    NextMethod(Rc<NextMethod>),
+    /// Continuation captured by `call/cc`, calling it returns its
+    /// arguments from the `call/cc` again
+    /// ```lisp
+    /// (+ 1 (call/cc (lambda (k) (k 2)))) // 3
+    /// ```
+    Continuation(Rc<Continuation>),
+    /// Generator made by `make-generator`, calling it returns the next
+    /// value its producer yields
+    /// ```lisp
+    /// (define g (make-generator (lambda () (yield 1) (yield 2))))
+    /// (g) // 1
+    /// ```
+    Generator(Rc<Generator>),
+    /// Promise made by `delay`, `force` evaluates its form once
+    /// ```lisp
+    /// (force (delay (+ 1 2))) // 3
+    /// ```
+    Promise(Rc<Promise>),
+    /// Regular expression made by `regex` or read from `#rx"..."`
+    /// ```lisp
+    /// (regex-search #rx"(\d+)-(\d+)" "from 10-20") // ("10-20" "10" "20")
+    /// ```
+    Regex(Rc<Regexp>),
+    /// String output port made by `open-output-string`
+    /// ```lisp
+    /// (define out (open-output-string))
+    /// (format out "~a-~a" 1 2)
+    /// (get-output-string out) // "1-2"
+    /// ```
+    Port(Rc<Port>),
+    /// Several values returned by `values`, one value is returned as itself
+    /// ```lisp
+    /// (floor/ 7 2) // 3 1
+    /// ```
+    Values(Vec<Object>),
     List(Vec<Object>),
 }
 
+impl Object {
+    /// Representation that the parser can read back, unlike
+    /// `Display` strings keep their quotes and keywords their name
+    /// ```rs
+    /// let s = Object::Str("hi".to_string());
+    /// assert_eq!(s.repr(), "\"hi\"");
+    /// ```
+    pub fn repr(&self) -> String {
+        match self {
+            Object::Str(s) => format!("\"{}\"", s),
+            Object::Keyword(k) => k.clone(),
+            Object::List(items) => {
+                let items = items.iter().map(|i| i.repr()).collect::<Vec<_>>();
+                format!("({})", items.join(" "))
+            }
+            Object::Values(values) => values.iter().map(|v| v.repr()).collect::<Vec<_>>().join(" "),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Approximate bytes held by the object, counted for memory limits
+    pub fn size(&self) -> usize {
+        let own = std::mem::size_of::<Object>();
+        let names = |names: &[String]| names.iter().map(|n| n.len() + std::mem::size_of::<String>()).sum::<usize>();
+        let items = |items: &[Object]| items.iter().map(Object::size).sum::<usize>();
+
+        match self {
+            Object::Str(s) | Object::Symbol(s) | Object::Keyword(s) => own + s.len(),
+            Object::List(list) | Object::Values(list) => own + items(list),
+            Object::Lambda(params, body) => own + items(params) + items(body),
+            Object::Macro(params, body) | Object::SyntaxRules(params, body) => own + names(params) + items(body),
+            Object::Condition(c) => own + c.kind.len() + c.message.len() + items(&c.irritants),
+            Object::Record(r) => own + items(&r.fields.borrow()),
+            Object::Regex(r) => own + r.regex.as_str().len(),
+            Object::Port(p) => own + p.buffer.borrow().len(),
+            _ => own,
+        }
+    }
+}
+
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -53,6 +231,57 @@ impl fmt::Display for Object {
 
                 Ok(())
             }
+            Object::Macro(params, body) => {
+                write!(f, "Macro(")?;
+                for param in params {
+                    write!(f, "{} ", param)?;
+                }
+                write!(f, ")")?;
+                for expr in body {
+                    write!(f, "{}", expr)?;
+                }
+
+                Ok(())
+            }
+            Object::SyntaxRules(literals, rules) => {
+                write!(f, "SyntaxRules(")?;
+                for literal in literals {
+                    write!(f, "{} ", literal)?;
+                }
+                write!(f, ")")?;
+                for rule in rules {
+                    write!(f, "{}", rule)?;
+                }
+
+                Ok(())
+            }
+            Object::Condition(c) => {
+                write!(f, "#<condition {}: {}", c.kind, c.message)?;
+                for irritant in &c.irritants {
+                    write!(f, " {}", irritant.repr())?;
+                }
+                write!(f, ">")
+            }
+            Object::RecordType(t) => write!(f, "#<record-type {}>", t.short_name()),
+            Object::Record(r) => {
+                write!(f, "#<{}", r.rtype.short_name())?;
+                for (field, value) in r.rtype.fields.iter().zip(r.fields.borrow().iter()) {
+                    write!(f, " {}: {}", field, value.repr())?;
+                }
+                write!(f, ">")
+            }
+            Object::RecordProc(p) => write!(f, "#<procedure {}>", p.name),
+            Object::Generic(g) => write!(f, "#<generic {}>", g.name),
+            Object::NextMethod(next) => write!(f, "#<next-method {}>", next.name),
+            Object::Continuation(k) => write!(f, "{}", k),
+            Object::Generator(g) => write!(f, "{}", g),
+            Object::Promise(p) => write!(f, "{}", p),
+            Object::Regex(r) => write!(f, "{}", r),
+            Object::Port(p) => write!(f, "{}", p),
+            Object::Values(values) => {
+                let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", values.join(" "))
+            }
             Object::List(items) => {
                 write!(f, "(")?;
 
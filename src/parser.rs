@@ -1,83 +1,169 @@
 use crate::env::KEYWORDS;
+use crate::error::*;
 use crate::lexer::*;
 use crate::object::*;
+use crate::regexp::Regexp;
 use std::error::Error;
 use std::fmt;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct ParseError {
     err: String,
+    span: Option<Span>,
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Parse error: {}", self.err)
+        write!(f, "Parse error: {}", self.err)?;
+
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+
+        Ok(())
     }
 }
 
 impl Error for ParseError {}
 
+impl From<ParseError> for EvalError {
+    fn from(error: ParseError) -> Self {
+        let parse_error = EvalError::new(ErrorKind::Parse(error.err));
+
+        match error.span {
+            Some(span) => parse_error.with_span(span),
+            None => parse_error,
+        }
+    }
+}
+
+/// Parsed program with the position of its top-level forms
+#[derive(Debug)]
+pub struct Program {
+    pub body: Object,
+    pub span: Span,
+    pub forms: Vec<Span>,
+}
+
 pub fn parse(program: &str) -> Result<Object, ParseError> {
-    let token_result = tokenize(program);
+    parse_program(program).map(|program| program.body)
+}
+
+pub fn parse_program(program: &str) -> Result<Program, ParseError> {
+    let token_result = tokenize_spanned(program).map_err(|error| ParseError {
+        err: format!("{}", error),
+        span: Some(error.span()),
+    })?;
+
+    let mut tokens = token_result.into_iter().rev().collect::<Vec<_>>();
+    let span = match tokens.last() {
+        Some((_, span)) => *span,
+        None => Span { line: 1, col: 1 },
+    };
+    let (mut body, mut forms) = parse_list_spanned(&mut tokens)?;
 
-    if let Err(error) = token_result {
-        return Err(ParseError {
-            err: format!("{}", error),
-        });
+    // Forms after the first one make the program a sequence of all of them
+    if !tokens.is_empty() {
+        let mut items = vec![body];
+        forms = vec![span];
+        while let Some((token, span)) = tokens.pop() {
+            items.push(parse_datum(token, span, &mut tokens)?);
+            forms.push(span);
+        }
+        body = Object::List(items);
     }
 
-    let mut tokens = token_result.unwrap().into_iter().rev().collect::<Vec<_>>();
-    let parsed_list = parse_list(&mut tokens)?;
+    Ok(Program { body, span, forms })
+}
 
-    Ok(parsed_list)
+fn parse_list(tokens: &mut Vec<(Token, Span)>) -> Result<Object, ParseError> {
+    parse_list_spanned(tokens).map(|(list, _)| list)
 }
 
-fn parse_list(tokens: &mut Vec<Token>) -> Result<Object, ParseError> {
+/// Parse a list, also returning where each of its items starts
+fn parse_list_spanned(tokens: &mut Vec<(Token, Span)>) -> Result<(Object, Vec<Span>), ParseError> {
     let token = tokens.pop();
 
-    if token != Some(Token::LParen) {
-        return Err(ParseError {
-            err: format!("Expected LParen, found {:?}", token),
-        });
+    match token {
+        Some((Token::LParen, _)) => {}
+        Some((token, span)) => {
+            return Err(ParseError {
+                err: format!("Expected LParen, found {:?}", token),
+                span: Some(span),
+            })
+        }
+        None => {
+            return Err(ParseError {
+                err: "Expected LParen, found None".to_string(),
+                span: None,
+            })
+        }
     }
 
     let mut list: Vec<Object> = Vec::new();
+    let mut spans: Vec<Span> = Vec::new();
 
-    while !tokens.is_empty() {
-        let token = tokens.pop();
-
-        if token.is_none() {
-            return Err(ParseError {
-                err: "Did not find enough tokens".to_string(),
-            });
+    while let Some((t, span)) = tokens.pop() {
+        if t == Token::RParen {
+            return Ok((Object::List(list), spans));
         }
 
-        let t = token.unwrap();
+        list.push(parse_datum(t, span, tokens)?);
+        spans.push(span);
+    }
 
-        match t {
-            Token::Integer(n) => list.push(Object::Integer(n)),
-            Token::Float(n) => list.push(Object::Float(n)),
-            Token::Str(s) => list.push(Object::Str(s.to_string())),
-            Token::Symbol(s) => {
-                if KEYWORDS.contains(&s.as_str()) {
-                    list.push(Object::Keyword(s.to_string()));
-                    continue;
-                }
+    Ok((Object::List(list), spans))
+}
 
-                list.push(Object::Symbol(s));
-            }
-            Token::LParen => {
-                tokens.push(Token::LParen);
-                let sub_list = parse_list(tokens)?;
-                list.push(sub_list);
-            }
-            Token::RParen => {
-                return Ok(Object::List(list));
+fn parse_datum(token: Token, span: Span, tokens: &mut Vec<(Token, Span)>) -> Result<Object, ParseError> {
+    match token {
+        Token::Integer(n) => Ok(Object::Integer(n)),
+        Token::Float(n) => Ok(Object::Float(n)),
+        Token::Str(s) => Ok(Object::Str(s.to_string())),
+        Token::Regex(pattern) => match Regexp::new(&pattern) {
+            Ok(regex) => Ok(Object::Regex(Rc::new(regex))),
+            Err(error) => Err(ParseError {
+                err: error.to_string(),
+                span: Some(span),
+            }),
+        },
+        Token::Symbol(s) => {
+            if KEYWORDS.contains(&s.as_str()) {
+                return Ok(Object::Keyword(s.to_string()));
             }
+
+            Ok(Object::Symbol(s))
         }
+        Token::LParen => {
+            tokens.push((Token::LParen, span));
+            parse_list(tokens)
+        }
+        Token::Quote => parse_quoted("quote", span, tokens),
+        Token::Quasiquote => parse_quoted("quasiquote", span, tokens),
+        Token::Unquote => parse_quoted("unquote", span, tokens),
+        Token::UnquoteSplicing => parse_quoted("unquote-splicing", span, tokens),
+        Token::RParen => Err(ParseError {
+            err: "Unexpected RParen".to_string(),
+            span: Some(span),
+        }),
     }
+}
 
-    Ok(Object::List(list))
+/// Expand the reader shorthands `'x`, `` `x ``, `,x` and `,@x`
+/// into `(quote x)`, `(quasiquote x)`, `(unquote x)` and `(unquote-splicing x)`
+fn parse_quoted(name: &str, span: Span, tokens: &mut Vec<(Token, Span)>) -> Result<Object, ParseError> {
+    let datum = match tokens.pop() {
+        Some((Token::RParen, _)) | None => {
+            return Err(ParseError {
+                err: format!("Expected an expression after {}", name),
+                span: Some(span),
+            })
+        }
+        Some((t, span)) => parse_datum(t, span, tokens)?,
+    };
+
+    Ok(Object::List(vec![Object::Keyword(name.to_string()), datum]))
 }
 
 #[cfg(test)]
@@ -111,4 +197,63 @@ mod tests {
             ])
         )
     }
+
+    #[test]
+    fn quote_shorthand() {
+        let list = parse("(define xs '(1 `(a ,b)))").unwrap();
+
+        assert_eq!(
+            list,
+            Object::List(vec![
+                Object::Keyword("define".to_string()),
+                Object::Symbol("xs".to_string()),
+                Object::List(vec![
+                    Object::Keyword("quote".to_string()),
+                    Object::List(vec![
+                        Object::Integer(1),
+                        Object::List(vec![
+                            Object::Keyword("quasiquote".to_string()),
+                            Object::List(vec![
+                                Object::Symbol("a".to_string()),
+                                Object::List(vec![
+                                    Object::Keyword("unquote".to_string()),
+                                    Object::Symbol("b".to_string()),
+                                ]),
+                            ]),
+                        ]),
+                    ]),
+                ]),
+            ])
+        )
+    }
+
+    #[test]
+    fn top_level_form_spans() {
+        let program = parse_program("(\n  (define x 1)\n  (print x))").unwrap();
+
+        assert_eq!(program.span, Span { line: 1, col: 1 });
+        assert_eq!(
+            program.forms,
+            vec![Span { line: 2, col: 3 }, Span { line: 3, col: 3 }]
+        );
+    }
+
+    #[test]
+    fn unwrapped_top_level_forms() {
+        let program = parse_program("(define x 1)\n(define y 2)\n(+ x y)").unwrap();
+
+        assert_eq!(
+            program.body,
+            Object::List(vec![
+                parse("(define x 1)").unwrap(),
+                parse("(define y 2)").unwrap(),
+                parse("(+ x y)").unwrap(),
+            ])
+        );
+        assert_eq!(
+            program.forms,
+            vec![Span { line: 1, col: 1 }, Span { line: 2, col: 1 }, Span { line: 3, col: 1 }]
+        );
+        assert!(parse_program("(define x 1) )").is_err());
+    }
 }
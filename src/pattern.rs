@@ -0,0 +1,198 @@
+use crate::env::Env;
+use crate::error::EvalError;
+use crate::eval::eval_list;
+use crate::generic::precedence;
+use crate::object::Object;
+
+/// Variables bound by a pattern, in the order they appear
+pub type Bindings = Vec<(String, Object)>;
+
+fn is_symbol(obj: &Object, name: &str) -> bool {
+    matches!(obj, Object::Symbol(s) if s == name)
+}
+
+fn is_keyword(obj: &Object, name: &str) -> bool {
+    matches!(obj, Object::Keyword(k) if k == name)
+}
+
+/// Match `value` against a pattern of `match`, adding the variables it binds
+///
+/// Symbols bind, `_` matches anything, `(p ... . rest)` matches lists,
+/// `(? pred p...)` values that satisfy `pred` and the patterns,
+/// `($ type p...)` records by their fields and quoted and
+/// quasiquoted patterns the structure they show
+pub fn matches(pattern: &Object, value: &Object, bindings: &mut Bindings, env: &mut Env) -> Result<bool, EvalError> {
+    let items = match pattern {
+        Object::Symbol(s) if s == "_" => return Ok(true),
+        // `:name` evaluates to itself, so it is a literal
+        Object::Symbol(s) if !s.starts_with(':') => return Ok(bind(s, value, bindings)),
+        Object::Keyword(k) if k == "true" => return Ok(*value == Object::Bool(true)),
+        Object::Keyword(k) if k == "false" => return Ok(*value == Object::Bool(false)),
+        Object::List(items) => items,
+        literal => return Ok(literal == value),
+    };
+
+    match items.as_slice() {
+        [quote, datum] if is_keyword(quote, "quote") => Ok(datum == value),
+        [quasi, template] if is_keyword(quasi, "quasiquote") => matches_quasi(template, value, bindings, env),
+        [question, predicate, patterns @ ..] if is_symbol(question, "?") => {
+            // The predicate is called like a procedure, builtins too
+            let call = [
+                predicate.clone(),
+                Object::List(vec![Object::Keyword("quote".to_string()), value.clone()]),
+            ];
+            if eval_list(&call, env)? == Object::Bool(false) {
+                return Ok(false);
+            }
+
+            for pattern in patterns {
+                if !matches(pattern, value, bindings, env)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        [dollar, Object::Symbol(type_name), fields @ ..] if is_symbol(dollar, "$") => {
+            let record = match (value, env.get(type_name)) {
+                (Object::Record(record), Some(Object::RecordType(rtype)))
+                    if precedence(&record.rtype).iter().any(|t| std::rc::Rc::ptr_eq(t, &rtype)) =>
+                {
+                    record
+                }
+                (_, Some(Object::RecordType(_))) => return Ok(false),
+                _ => return Err(EvalError::syntax(format!("Unknown record type: {}", type_name))),
+            };
+
+            let values = record.fields.borrow().clone();
+            if fields.len() > values.len() {
+                return Ok(false);
+            }
+            matches_all(fields, &values, bindings, env)
+        }
+        _ => match value {
+            Object::List(values) => matches_list(items, values, bindings, env),
+            _ => Ok(false),
+        },
+    }
+}
+
+/// A variable used twice must match equal values
+fn bind(name: &str, value: &Object, bindings: &mut Bindings) -> bool {
+    match bindings.iter().find(|(bound, _)| bound == name) {
+        Some((_, previous)) => previous == value,
+        None => {
+            bindings.push((name.to_string(), value.clone()));
+            true
+        }
+    }
+}
+
+fn matches_all(
+    patterns: &[Object],
+    values: &[Object],
+    bindings: &mut Bindings,
+    env: &mut Env,
+) -> Result<bool, EvalError> {
+    for (pattern, value) in patterns.iter().zip(values) {
+        if !matches(pattern, value, bindings, env)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// `(p1 p2)` matches lists of two items, `(p1 . rest)` lists of at
+/// least one with `rest` matching the list of the other items
+fn matches_list(
+    patterns: &[Object],
+    values: &[Object],
+    bindings: &mut Bindings,
+    env: &mut Env,
+) -> Result<bool, EvalError> {
+    match patterns.iter().position(|p| is_symbol(p, ".")) {
+        Some(dot) if dot + 2 == patterns.len() => {
+            if values.len() < dot || !matches_all(&patterns[..dot], values, bindings, env)? {
+                return Ok(false);
+            }
+            matches(&patterns[dot + 1], &Object::List(values[dot..].to_vec()), bindings, env)
+        }
+        Some(_) => Err(EvalError::syntax(format!(
+            "Invalid dotted pattern: {}",
+            Object::List(patterns.to_vec())
+        ))),
+        None if patterns.len() == values.len() => matches_all(patterns, values, bindings, env),
+        None => Ok(false),
+    }
+}
+
+/// Inside a quasiquote only the unquoted parts are patterns, a final
+/// `,@rest` matches the remaining items
+fn matches_quasi(template: &Object, value: &Object, bindings: &mut Bindings, env: &mut Env) -> Result<bool, EvalError> {
+    let items = match template {
+        Object::List(items) => items,
+        literal => return Ok(literal == value),
+    };
+
+    if let [unquote, pattern] = items.as_slice() {
+        if is_keyword(unquote, "unquote") {
+            return matches(pattern, value, bindings, env);
+        }
+    }
+
+    let values = match value {
+        Object::List(values) => values,
+        _ => return Ok(false),
+    };
+
+    let splice = match items.last() {
+        Some(Object::List(last)) if last.len() == 2 && is_keyword(&last[0], "unquote-splicing") => Some(&last[1]),
+        _ => None,
+    };
+
+    let fixed = match splice {
+        Some(_) => &items[..items.len() - 1],
+        None => &items[..],
+    };
+
+    if values.len() < fixed.len() || (splice.is_none() && values.len() != fixed.len()) {
+        return Ok(false);
+    }
+
+    for (template, value) in fixed.iter().zip(values) {
+        if !matches_quasi(template, value, bindings, env)? {
+            return Ok(false);
+        }
+    }
+
+    match splice {
+        Some(rest) => matches(rest, &Object::List(values[fixed.len()..].to_vec()), bindings, env),
+        None => Ok(true),
+    }
+}
+
+/// Variables a pattern binds, the bodies of its clause see them
+pub fn variables(pattern: &Object) -> Vec<String> {
+    match pattern {
+        Object::Symbol(s) if s != "_" && s != "." && !s.starts_with(':') => vec![s.clone()],
+        Object::List(items) => match items.as_slice() {
+            [quote, _] if is_keyword(quote, "quote") => Vec::new(),
+            [quasi, template] if is_keyword(quasi, "quasiquote") => unquoted_variables(template),
+            [question, _, patterns @ ..] if is_symbol(question, "?") => patterns.iter().flat_map(variables).collect(),
+            [dollar, _, fields @ ..] if is_symbol(dollar, "$") => fields.iter().flat_map(variables).collect(),
+            _ => items.iter().flat_map(variables).collect(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn unquoted_variables(template: &Object) -> Vec<String> {
+    match template {
+        Object::List(items) => match items.as_slice() {
+            [unquote, pattern] if is_keyword(unquote, "unquote") || is_keyword(unquote, "unquote-splicing") => {
+                variables(pattern)
+            }
+            _ => items.iter().flat_map(unquoted_variables).collect(),
+        },
+        _ => Vec::new(),
+    }
+}
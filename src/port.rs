@@ -0,0 +1,28 @@
+use std::cell::RefCell;
+use std::fmt;
+
+/// String output port made by `open-output-string`, `format` writes
+/// into it and `get-output-string` returns what it holds
+#[derive(Debug, Default)]
+pub struct Port {
+    pub buffer: RefCell<String>,
+}
+
+impl Port {
+    pub fn write(&self, text: &str) {
+        self.buffer.borrow_mut().push_str(text);
+    }
+}
+
+/// Ports are only equal to themselves
+impl PartialEq for Port {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<output-port>")
+    }
+}
@@ -0,0 +1,62 @@
+use crate::object::Object;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Promise made by `delay`, `delay-force` or `make-promise`, forcing
+/// it evaluates its form once and keeps the value
+#[derive(Debug)]
+pub struct Promise {
+    pub state: RefCell<PromiseState>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PromiseState {
+    /// Form still to evaluate with the values of the symbols it uses,
+    /// the form of a `delay-force` returns another promise
+    Delayed {
+        form: Object,
+        bindings: Vec<(String, Object)>,
+        lazy: bool,
+    },
+    Forced(Object),
+    /// Promise that took over the state of this one, when it was the
+    /// result of a `delay-force`
+    Shared(Rc<Promise>),
+}
+
+impl Promise {
+    pub fn delayed(form: Object, bindings: Vec<(String, Object)>, lazy: bool) -> Self {
+        Promise {
+            state: RefCell::new(PromiseState::Delayed { form, bindings, lazy }),
+        }
+    }
+
+    pub fn forced(value: Object) -> Self {
+        Promise {
+            state: RefCell::new(PromiseState::Forced(value)),
+        }
+    }
+}
+
+/// Promises are only equal to themselves
+impl PartialEq for Promise {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Display for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<promise>")
+    }
+}
+
+/// Symbols in a form, the variables a promise keeps the values of
+pub fn symbols(form: &Object, found: &mut Vec<String>) {
+    match form {
+        Object::Symbol(s) if !found.contains(s) => found.push(s.clone()),
+        Object::List(items) => items.iter().for_each(|item| symbols(item, found)),
+        _ => {}
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::EvalError;
+use crate::limits::Budget;
+use crate::object::Object;
+use regex::{Captures, Regex};
+use std::fmt;
+
+/// Regular expression made by `regex` or read from a `#rx"..."`
+/// literal, its pattern is compiled once
+#[derive(Debug)]
+pub struct Regexp {
+    pub regex: Regex,
+    /// Pattern matching the whole string, used by `regex-match`, the
+    /// groups keep their numbers and names
+    pub anchored: Regex,
+}
+
+impl Regexp {
+    pub fn new(pattern: &str) -> Result<Self, EvalError> {
+        let invalid = |error: regex::Error| {
+            let message = format!("Invalid regex #rx\"{}\": {}", pattern, error);
+            EvalError::syntax(message)
+        };
+
+        let regex = Regex::new(pattern).map_err(invalid)?;
+        let anchored = anchor(pattern).map_err(invalid)?;
+        Ok(Regexp { regex, anchored })
+    }
+}
+
+/// Wrap a valid pattern as `^(?:...)$`, a pattern ending in a `(?x)`
+/// comment would swallow the `)$`, a newline closes the comment first
+/// and is whitespace in the `(?x)` mode the comment needs
+fn anchor(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+        .or_else(|_| Regex::new(&format!("^(?:{}\n)$", pattern)))
+}
+
+/// Regexes are equal when their patterns are
+impl PartialEq for Regexp {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+impl fmt::Display for Regexp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#rx\"{}\"", self.regex.as_str())
+    }
+}
+
+/// Whole match and groups of a match, false for the groups that did
+/// not take part in it
+pub fn groups(captures: &Captures) -> Object {
+    let groups = captures.iter().map(|group| match group {
+        Some(group) => Object::Str(group.as_str().to_string()),
+        None => Object::Bool(false),
+    });
+    Object::List(groups.collect())
+}
+
+/// `(name value)` for each named group of a match
+pub fn named_groups(regex: &Regex, captures: &Captures) -> Object {
+    let pairs = regex.capture_names().flatten().map(|name| {
+        let value = match captures.name(name) {
+            Some(group) => Object::Str(group.as_str().to_string()),
+            None => Object::Bool(false),
+        };
+        Object::List(vec![Object::Str(name.to_string()), value])
+    });
+    Object::List(pairs.collect())
+}
+
+/// Text with the first `limit` matches replaced, charged to the budget
+/// piece by piece so a large result fails before it is made
+pub fn replace(regex: &Regex, s: &str, limit: usize, replacement: &str, budget: &Budget) -> Result<String, EvalError> {
+    let mut out = String::new();
+    let mut last = 0;
+
+    for captures in regex.captures_iter(s).take(limit) {
+        let whole = match captures.get(0) {
+            Some(whole) => whole,
+            None => continue,
+        };
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        budget.allocate(whole.start() - last + expanded.len())?;
+        out.push_str(&s[last..whole.start()]);
+        out.push_str(&expanded);
+        last = whole.end();
+    }
+
+    budget.allocate(s.len() - last)?;
+    out.push_str(&s[last..]);
+    Ok(out)
+}
+
+/// Replacement with the backreferences `\1` written as `${1}`, the
+/// syntax of the regex crate that also takes `$1` and `${name}`
+pub fn replacement(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(d)) if d.is_ascii_digit() => {
+                out.push_str("${");
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    out.push(d);
+                    chars.next();
+                }
+                out.push('}');
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backreferences() {
+        assert_eq!(replacement(r"\2-\1"), "${2}-${1}");
+        assert_eq!(replacement(r"\12x"), "${12}x");
+        assert_eq!(replacement(r"a\\1 $1 ${name}"), r"a\1 $1 ${name}");
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let error = Regexp::new("(a").unwrap_err();
+        assert!(error.to_string().contains("Invalid regex #rx\"(a\""));
+    }
+
+    #[test]
+    fn anchored_comment() {
+        let regexp = Regexp::new("(?x)a # c").unwrap();
+        assert!(regexp.anchored.is_match("a"));
+        assert!(!regexp.anchored.is_match("ab"));
+    }
+}
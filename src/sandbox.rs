@@ -0,0 +1,163 @@
+use crate::error::{ErrorKind, EvalError};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Group of operations that reach outside of the interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Capability {
+    FsRead,
+    FsWrite,
+    Process,
+    EnvVars,
+    Time,
+    Random,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::FsRead,
+        Capability::FsWrite,
+        Capability::Process,
+        Capability::EnvVars,
+        Capability::Time,
+        Capability::Random,
+    ];
+
+    /// Name used on the command line, as in `--allow=fs-read,time`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::FsRead => "fs-read",
+            Capability::FsWrite => "fs-write",
+            Capability::Process => "process",
+            Capability::EnvVars => "env",
+            Capability::Time => "time",
+            Capability::Random => "random",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        Capability::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What the evaluated code is allowed to do
+///
+/// By default everything is allowed, `Sandbox::deny_all` is the
+/// starting point for untrusted code
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    allowed: BTreeSet<Capability>,
+    /// Directories `load` can read from without `FsRead`
+    load_dirs: Vec<PathBuf>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox::allow_all()
+    }
+}
+
+impl Sandbox {
+    pub fn allow_all() -> Self {
+        Sandbox {
+            allowed: Capability::ALL.into_iter().collect(),
+            load_dirs: Vec::new(),
+        }
+    }
+
+    /// No capabilities and no load directories
+    pub fn deny_all() -> Self {
+        Sandbox {
+            allowed: BTreeSet::new(),
+            load_dirs: Vec::new(),
+        }
+    }
+
+    pub fn allow(mut self, capability: Capability) -> Self {
+        self.allowed.insert(capability);
+        self
+    }
+
+    pub fn deny(mut self, capability: Capability) -> Self {
+        self.allowed.remove(&capability);
+        self
+    }
+
+    /// Let `load` read the files under `dir`, even without `FsRead`
+    pub fn allow_load_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.load_dirs.push(dir.into());
+        self
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.allowed.contains(&capability)
+    }
+
+    /// Fail with a permission error unless `capability` is allowed
+    pub fn check(&self, capability: Capability, operation: &str) -> Result<(), EvalError> {
+        if self.allows(capability) {
+            return Ok(());
+        }
+
+        Err(EvalError::new(ErrorKind::Permission(format!(
+            "{} needs the {} capability",
+            operation, capability
+        ))))
+    }
+
+    /// Check that `load` can read `file`, it needs `FsRead`
+    /// or the file to be in one of the load directories
+    pub fn check_load(&self, file: &Path) -> Result<(), EvalError> {
+        if self.allows(Capability::FsRead) {
+            return Ok(());
+        }
+
+        // Compare real paths so `..` and links can not leave the directories,
+        // a missing file by the real path of its directory
+        let real = file.canonicalize().or_else(|_| {
+            let dir = file.parent().unwrap_or(file).canonicalize()?;
+            Ok::<_, std::io::Error>(dir.join(file.file_name().unwrap_or_default()))
+        });
+        let allowed = real.is_ok_and(|file| {
+            self.load_dirs
+                .iter()
+                .filter_map(|dir| dir.canonicalize().ok())
+                .any(|dir| file.starts_with(dir))
+        });
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(EvalError::new(ErrorKind::Permission(format!(
+                "load of {} is outside of the allowed directories",
+                file.display()
+            ))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities() {
+        let sandbox = Sandbox::deny_all().allow(Capability::Time);
+
+        assert!(sandbox.check(Capability::Time, "current-time").is_ok());
+        assert_eq!(
+            sandbox.check(Capability::Process, "run").unwrap_err().kind,
+            ErrorKind::Permission("run needs the process capability".to_string())
+        );
+        assert!(Sandbox::default().allows(Capability::FsWrite));
+        assert!(!Sandbox::allow_all().deny(Capability::FsWrite).allows(Capability::FsWrite));
+        assert_eq!(Capability::from_name("env"), Some(Capability::EnvVars));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::object::Object;
+use std::cmp::Ordering;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Digits of the numbers `number->string` writes in a radix up to 36
+const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Lengths and indices of strings count chars, the Unicode scalar
+/// values, so "é" written as e and a combining accent has length 2.
+/// `string-graphemes` gives the characters a reader sees
+pub fn char_count(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Chars from `start` up to `end`, none when they are out of the string
+pub fn substring(s: &str, start: usize, end: usize) -> Option<&str> {
+    if start > end || end > char_count(s) {
+        return None;
+    }
+
+    let byte = |index: usize| s.char_indices().nth(index).map_or(s.len(), |(byte, _)| byte);
+    Some(&s[byte(start)..byte(end)])
+}
+
+/// Bytes of `s` with every `from` replaced by `to`, known before the
+/// replaced string is made
+pub fn replaced_len(s: &str, from: &str, to: &str) -> usize {
+    let count = s.matches(from).count();
+    (s.len() - count * from.len()).saturating_add(count.saturating_mul(to.len()))
+}
+
+/// Bytes of `s` in upper or lower case, some chars change their length
+pub fn case_len(s: &str, upper: bool) -> usize {
+    match upper {
+        true => s.chars().flat_map(char::to_uppercase).map(char::len_utf8).sum(),
+        false => s.chars().flat_map(char::to_lowercase).map(char::len_utf8).sum(),
+    }
+}
+
+/// Index in chars of the first occurrence of `needle`
+pub fn find(s: &str, needle: &str) -> Option<usize> {
+    s.find(needle).map(|byte| char_count(&s[..byte]))
+}
+
+/// Extended grapheme clusters of a string, as strings
+pub fn graphemes(s: &str) -> Vec<Object> {
+    s.graphemes(true).map(|g| Object::Str(g.to_string())).collect()
+}
+
+/// Items of a string between the occurrences of `separator`, its chars
+/// when the separator is empty
+pub fn split(s: &str, separator: &str) -> Vec<Object> {
+    match separator {
+        "" => s.chars().map(|c| Object::Str(c.to_string())).collect(),
+        _ => s.split(separator).map(|item| Object::Str(item.to_string())).collect(),
+    }
+}
+
+/// Number written in a string, an integer in any radix or a decimal
+/// float, none when the string is not a number
+pub fn parse_number(s: &str, radix: u32) -> Option<Object> {
+    if let Ok(n) = i64::from_str_radix(s, radix) {
+        return Some(Object::Integer(n));
+    }
+
+    // Rust also reads "inf" and "NaN", a number needs a digit
+    let decimal = |c: char| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-');
+    match radix == 10 && s.chars().all(decimal) && s.chars().any(|c| c.is_ascii_digit()) {
+        true => s.parse::<f64>().ok().map(Object::Float),
+        false => None,
+    }
+}
+
+/// Integer written in a radix from 2 to 36, with lowercase digits
+pub fn format_radix(n: i64, radix: u32) -> String {
+    let mut magnitude = n.unsigned_abs();
+    let mut digits = Vec::new();
+
+    loop {
+        digits.push(DIGITS[(magnitude % radix as u64) as usize]);
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+
+    if n < 0 {
+        digits.push(b'-');
+    }
+    digits.iter().rev().map(|&d| d as char).collect()
+}
+
+/// Order of two strings for the comparison predicates, by scalar
+/// values, or by their lowercase forms for the `-ci` ones
+pub fn compare(a: &str, b: &str, fold_case: bool) -> Ordering {
+    match fold_case {
+        true => a.to_lowercase().cmp(&b.to_lowercase()),
+        false => a.cmp(b),
+    }
+}
@@ -0,0 +1,410 @@
+use crate::error::EvalError;
+use crate::object::Object;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+
+const ELLIPSIS: &str = "...";
+
+thread_local! {
+    static RENAME_COUNTER: Cell<usize> = const { Cell::new(0) };
+}
+
+/// What a pattern variable matched, `Many` holds one entry
+/// for every repetition of an ellipsis pattern
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Object),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+fn is_symbol(obj: &Object, name: &str) -> bool {
+    matches!(obj, Object::Symbol(s) if s == name)
+}
+
+/// Check the shape of a `syntax-rules` rule, every rule is a
+/// `(pattern template)` pair and the pattern must be a list
+pub fn validate_rule(rule: &Object) -> Result<(), EvalError> {
+    let items = match rule {
+        Object::List(items) if items.len() == 2 => items,
+        _ => return Err(EvalError::syntax(format!("Invalid syntax-rules rule: {}", rule))),
+    };
+
+    match &items[0] {
+        Object::List(pattern) if !pattern.is_empty() => validate_pattern(pattern),
+        _ => Err(EvalError::syntax(format!("Invalid syntax-rules pattern: {}", items[0]))),
+    }
+}
+
+fn validate_pattern(items: &[Object]) -> Result<(), EvalError> {
+    let ellipses = items.iter().filter(|i| is_symbol(i, ELLIPSIS)).count();
+    let dot = items.iter().position(|i| is_symbol(i, "."));
+
+    if ellipses > 1 {
+        return Err(EvalError::syntax("Only one ellipsis is allowed for each list pattern"));
+    }
+
+    if is_symbol(&items[0], ELLIPSIS) {
+        return Err(EvalError::syntax("Ellipsis must follow a pattern"));
+    }
+
+    if let Some(pos) = dot {
+        if pos + 2 != items.len() || ellipses > 0 {
+            return Err(EvalError::syntax("Invalid dotted pattern"));
+        }
+    }
+
+    for item in items {
+        if let Object::List(inner) = item {
+            if !inner.is_empty() {
+                validate_pattern(inner)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Expand a use of a `syntax-rules` macro with the first rule whose pattern matches
+///
+/// The symbols that the template introduces in binding positions are renamed,
+/// so they cannot capture variables coming from the macro call
+pub fn expand(form: &[Object], literals: &[String], rules: &[Object]) -> Result<Object, EvalError> {
+    for rule in rules {
+        let (pattern, template) = match rule {
+            Object::List(items) => (&items[0], &items[1]),
+            _ => continue,
+        };
+
+        let pattern = match pattern {
+            Object::List(items) => &items[1..],
+            _ => continue,
+        };
+
+        let mut bindings = Bindings::new();
+        if !match_list(pattern, &form[1..], literals, &mut bindings) {
+            continue;
+        }
+
+        let mut binders = HashSet::new();
+        collect_binders(template, &bindings, &mut binders);
+        let renames = binders
+            .into_iter()
+            .map(|name| {
+                let fresh = fresh_name(&name);
+                (name, fresh)
+            })
+            .collect::<HashMap<_, _>>();
+
+        return instantiate(template, &bindings, &renames);
+    }
+
+    Err(EvalError::syntax(format!("No syntax-rules pattern matches: {}", Object::List(form.to_vec()))))
+}
+
+fn fresh_name(name: &str) -> String {
+    RENAME_COUNTER.with(|counter| {
+        let n = counter.get() + 1;
+        counter.set(n);
+        format!("{}%{}", name, n)
+    })
+}
+
+fn match_pattern(pattern: &Object, input: &Object, literals: &[String], bindings: &mut Bindings) -> bool {
+    match pattern {
+        Object::Symbol(s) if s == "_" => true,
+        Object::Symbol(s) if literals.contains(s) => is_symbol(input, s),
+        Object::Symbol(s) => {
+            bindings.insert(s.clone(), Binding::One(input.clone()));
+            true
+        }
+        Object::List(items) => match input {
+            Object::List(inputs) => match_list(items, inputs, literals, bindings),
+            Object::Void => items.is_empty(),
+            _ => false,
+        },
+        _ => pattern == input,
+    }
+}
+
+fn match_list(patterns: &[Object], inputs: &[Object], literals: &[String], bindings: &mut Bindings) -> bool {
+    if let Some(dot) = patterns.iter().position(|p| is_symbol(p, ".")) {
+        if inputs.len() < dot {
+            return false;
+        }
+
+        let rest = Object::List(inputs[dot..].to_vec());
+        return match_list(&patterns[..dot], &inputs[..dot], literals, bindings)
+            && match_pattern(&patterns[dot + 1], &rest, literals, bindings);
+    }
+
+    let ellipsis = match patterns.iter().position(|p| is_symbol(p, ELLIPSIS)) {
+        Some(pos) => pos,
+        None => {
+            return patterns.len() == inputs.len()
+                && patterns
+                    .iter()
+                    .zip(inputs)
+                    .all(|(p, i)| match_pattern(p, i, literals, bindings));
+        }
+    };
+
+    let before = &patterns[..ellipsis - 1];
+    let repeated = &patterns[ellipsis - 1];
+    let after = &patterns[ellipsis + 1..];
+
+    if inputs.len() < before.len() + after.len() {
+        return false;
+    }
+
+    let middle_end = inputs.len() - after.len();
+
+    if !match_list(before, &inputs[..before.len()], literals, bindings) {
+        return false;
+    }
+
+    if !match_list(after, &inputs[middle_end..], literals, bindings) {
+        return false;
+    }
+
+    let mut matches = Vec::new();
+    for input in &inputs[before.len()..middle_end] {
+        let mut inner = Bindings::new();
+        if !match_pattern(repeated, input, literals, &mut inner) {
+            return false;
+        }
+        matches.push(inner);
+    }
+
+    let mut vars = Vec::new();
+    pattern_vars(repeated, literals, &mut vars);
+    for var in vars {
+        let seq = matches.iter().map(|m| m[&var].clone()).collect();
+        bindings.insert(var, Binding::Many(seq));
+    }
+
+    true
+}
+
+fn pattern_vars(pattern: &Object, literals: &[String], vars: &mut Vec<String>) {
+    match pattern {
+        Object::Symbol(s) if s == "_" || s == ELLIPSIS || s == "." => {}
+        Object::Symbol(s) if literals.contains(s) => {}
+        Object::Symbol(s) => vars.push(s.clone()),
+        Object::List(items) => {
+            for item in items {
+                pattern_vars(item, literals, vars);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Symbols of a parameter that destructures its argument
+fn param_symbols(param: &Object) -> Vec<&Object> {
+    match param {
+        Object::List(items) => items.iter().flat_map(param_symbols).collect(),
+        Object::Symbol(s) if s == "." => Vec::new(),
+        _ => vec![param],
+    }
+}
+
+/// Find the symbols the template itself binds, as `lambda`
+/// parameters or as names of the `let` family of forms
+fn collect_binders(template: &Object, bindings: &Bindings, binders: &mut HashSet<String>) {
+    let items = match template {
+        Object::List(items) => items,
+        _ => return,
+    };
+
+    let mut introduce = |obj: &Object| match obj {
+        Object::Symbol(s) if !bindings.contains_key(s) && s != ELLIPSIS => {
+            binders.insert(s.clone());
+        }
+        _ => {}
+    };
+
+    if let Some(Object::Keyword(k)) = items.first() {
+        match (k.as_str(), items.get(1)) {
+            ("lambda", Some(Object::List(params))) => params.iter().flat_map(param_symbols).for_each(&mut introduce),
+            ("let" | "let*" | "letrec" | "let-values", Some(Object::List(pairs))) => {
+                for pair in pairs {
+                    if let Object::List(pair) = pair {
+                        pair.first().into_iter().flat_map(param_symbols).for_each(&mut introduce);
+                    }
+                }
+            }
+            ("destructuring-bind" | "receive" | "define-values" | "shift", Some(pattern)) => {
+                param_symbols(pattern).into_iter().for_each(&mut introduce)
+            }
+            ("let", Some(name @ Object::Symbol(_))) => {
+                introduce(name);
+                if let Some(Object::List(pairs)) = items.get(2) {
+                    for pair in pairs {
+                        if let Object::List(pair) = pair {
+                            pair.first().into_iter().for_each(&mut introduce);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for item in items {
+        collect_binders(item, bindings, binders);
+    }
+}
+
+fn instantiate(template: &Object, bindings: &Bindings, renames: &HashMap<String, String>) -> Result<Object, EvalError> {
+    match template {
+        Object::Symbol(s) => match bindings.get(s) {
+            Some(Binding::One(obj)) => Ok(obj.clone()),
+            Some(Binding::Many(_)) => Err(EvalError::syntax(format!("Pattern variable {} used without ellipsis", s))),
+            None => match renames.get(s) {
+                Some(fresh) => Ok(Object::Symbol(fresh.clone())),
+                None => Ok(template.clone()),
+            },
+        },
+        Object::List(items) => {
+            if items.len() == 2 && is_symbol(&items[0], ELLIPSIS) {
+                return Ok(items[1].clone());
+            }
+
+            let mut expanded = Vec::new();
+            let mut i = 0;
+            while i < items.len() {
+                let item = &items[i];
+                let depth = items[i + 1..].iter().take_while(|t| is_symbol(t, ELLIPSIS)).count();
+
+                if depth > 0 {
+                    expanded.extend(instantiate_ellipsis(item, depth, bindings, renames)?);
+                    i += depth + 1;
+                    continue;
+                }
+
+                if is_symbol(item, ".") && i + 2 == items.len() {
+                    match instantiate(&items[i + 1], bindings, renames)? {
+                        Object::List(tail) => expanded.extend(tail),
+                        other => return Err(EvalError::syntax(format!("Template tail must be a list, found {}", other))),
+                    }
+                    break;
+                }
+
+                expanded.push(instantiate(item, bindings, renames)?);
+                i += 1;
+            }
+
+            Ok(Object::List(expanded))
+        }
+        _ => Ok(template.clone()),
+    }
+}
+
+fn instantiate_ellipsis(
+    template: &Object,
+    depth: usize,
+    bindings: &Bindings,
+    renames: &HashMap<String, String>,
+) -> Result<Vec<Object>, EvalError> {
+    if depth == 0 {
+        return Ok(vec![instantiate(template, bindings, renames)?]);
+    }
+
+    let mut names = Vec::new();
+    pattern_vars(template, &[], &mut names);
+
+    let seqs = names
+        .iter()
+        .filter_map(|name| match bindings.get(name) {
+            Some(Binding::Many(seq)) => Some((name, seq)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let len = match seqs.first() {
+        Some((_, seq)) => seq.len(),
+        None => return Err(EvalError::syntax(format!("No pattern variable before ellipsis in {}", template))),
+    };
+
+    if seqs.iter().any(|(_, seq)| seq.len() != len) {
+        return Err(EvalError::syntax(format!("Pattern variables of different lengths in {}", template)));
+    }
+
+    let mut expanded = Vec::new();
+    for i in 0..len {
+        let mut inner = bindings.clone();
+        for (name, seq) in &seqs {
+            inner.insert(name.to_string(), seq[i].clone());
+        }
+        expanded.extend(instantiate_ellipsis(template, depth - 1, &inner, renames)?);
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn rules(program: &str) -> Vec<Object> {
+        match parse(program).unwrap() {
+            Object::List(rules) => rules,
+            _ => unreachable!(),
+        }
+    }
+
+    fn form(program: &str) -> Vec<Object> {
+        match parse(program).unwrap() {
+            Object::List(items) => items,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn ellipsis_pattern() {
+        let rules = rules("(((_ (name val) ...) ((lambda (name ...) body) val ...)))");
+        let expanded = expand(&form("(my-let (a 1) (b 2))"), &[], &rules).unwrap();
+
+        assert_eq!(expanded, parse("((lambda (a b) body) 1 2)").unwrap());
+    }
+
+    #[test]
+    fn literals_must_match() {
+        let rules = rules("(((_ else e) e) ((_ c e) (if c e false)))");
+        let literals = vec!["else".to_string()];
+
+        let with_else = expand(&form("(my-cond else 1)"), &literals, &rules).unwrap();
+        let without_else = expand(&form("(my-cond other 1)"), &literals, &rules).unwrap();
+
+        assert_eq!(with_else, Object::Integer(1));
+        assert_eq!(without_else, parse("(if other 1 false)").unwrap());
+    }
+
+    #[test]
+    fn introduced_binders_are_renamed() {
+        let rules = rules("(((_ e) (lambda (tmp) (+ tmp e))))");
+        let expanded = expand(&form("(adder tmp)"), &[], &rules).unwrap();
+
+        let items = match expanded {
+            Object::List(items) => items,
+            _ => unreachable!(),
+        };
+        let param = match &items[1] {
+            Object::List(params) => params[0].clone(),
+            _ => unreachable!(),
+        };
+
+        assert_ne!(param, Object::Symbol("tmp".to_string()));
+        assert_eq!(
+            items[2],
+            Object::List(vec![
+                Object::Symbol("+".to_string()),
+                param,
+                Object::Symbol("tmp".to_string()),
+            ])
+        );
+    }
+}